@@ -0,0 +1,433 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use bgzip::{BGZFWriter, Compression};
+use flate2::read::MultiGzDecoder;
+use flate2::{Crc, Decompress, FlushDecompress};
+use crate::error::{with_filename, FastSeekError, Result};
+use crate::file_reader::{FileReader, GzFileReader};
+use crate::file_writer::FileWriter;
+use crate::gzip_checkpoints::checkpoints_path;
+use crate::zstd_seekable::{has_seek_table, is_zstd};
+
+//BGZF block: a gzip member whose extra field has a "BC" subfield holding the size of the block minus 1.
+//Its last 4 bytes are the size of its uncompressed data.
+const GZIP_HEADER_SIZE: usize = 12;
+const BLOCK_FOOTER_SIZE: u64 = 8;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum FileCompression{
+    None,
+    //gzip, but not BGZF: can't be read from random positions
+    Gzip,
+    Bgzf,
+    //zstd, but without a seek table: can't be read from random positions
+    Zstd,
+    SeekableZstd,
+}
+
+//Compression of filename, told by its first bytes (and last, for zstd)
+pub fn file_compression(filename: &str) -> Result<FileCompression>{
+    let mut file = io::BufReader::new(File::open(filename).map_err(|e| with_filename(e, "open", filename))?);
+    if is_zstd(file.fill_buf()?) {
+        return Ok(match has_seek_table(file.get_mut())? {
+            true => FileCompression::SeekableZstd,
+            false => FileCompression::Zstd,
+        });
+    }
+    if !file.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        return Ok(FileCompression::None);
+    }
+    return Ok(match read_block_header(&mut file)? {
+        Some(_) => FileCompression::Bgzf,
+        None => FileCompression::Gzip,
+    });
+}
+
+/// Recompresses `input`, a gzip-compressed, zstd-compressed or uncompressed file, to the BGZF file `output`,
+/// which FastSeek can index. `output` is written to `<output>.tmp` first, so it can be `input` itself, replaced once converted.
+/// With `gzi`, also writes the block index of `output` to `<output>.gzi`, as `bgzip -i` does.
+pub fn bgzip(input: &str, output: &str, gzi: bool) -> Result<()>{
+    BgzfConverter::new(input, output)?.finish()?;
+    if gzi {
+        BlockIndex::scan(output)?.write(&gzi_path(output))?;
+    }
+    return Ok(());
+}
+
+//Decompressed content of filename, whatever its compression
+pub fn decompressed_reader(filename: &str) -> Result<Box<dyn BufRead>>{
+    let compression = file_compression(filename)?;
+    let file = io::BufReader::new(File::open(filename).map_err(|e| with_filename(e, "open", filename))?);
+    return Ok(match compression {
+        FileCompression::None => Box::new(file),
+        //Every member of the file, BGZF blocks included
+        FileCompression::Gzip | FileCompression::Bgzf => Box::new(io::BufReader::new(MultiGzDecoder::new(file))),
+        //Every frame of the file, skipping the seek table
+        FileCompression::Zstd | FileCompression::SeekableZstd => Box::new(io::BufReader::new(zstd::Decoder::with_buffer(file)?)),
+    });
+}
+
+//Path of the block index of a BGZF file, as written by `bgzip -i`
+pub fn gzi_path(filename: &str) -> String{
+    return format!("{}.gzi", filename);
+}
+
+//Compressed and uncompressed offsets of the start of each block of a BGZF file, to translate
+//positions in the uncompressed data to virtual offsets and back.
+//Stored in .gzi files (samtools): entry count (u64) | compressed offset (u64) | uncompressed offset (u64) ...
+//all little-endian, without the first block, which starts at 0 in both.
+#[derive(Clone, PartialEq, Eq, Debug, Default)]
+pub struct BlockIndex{
+    entries: Vec<(u64, u64)>,
+}
+
+impl BlockIndex{
+    //The .gzi of filename if there is one, otherwise the blocks found by reading their headers
+    pub fn of_file(filename: &str) -> Result<BlockIndex>{
+        let gzi_path = gzi_path(filename);
+        if Path::new(&gzi_path).exists() {
+            return BlockIndex::read(&gzi_path);
+        }
+        return BlockIndex::scan(filename);
+    }
+
+    pub fn read(path: &str) -> Result<BlockIndex>{
+        let mut bytes = vec![];
+        File::open(path).map_err(|e| with_filename(e, "open", path))?.read_to_end(&mut bytes)?;
+        let invalid = || FastSeekError::CorruptIndex(format!("invalid block index {}", path));
+        if bytes.len() < 8 {
+            return Err(invalid());
+        }
+        let count = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        if count.checked_mul(16).and_then(|size| size.checked_add(8)) != Some(bytes.len() as u64) {
+            return Err(invalid());
+        }
+        let entries: Vec<(u64, u64)> = bytes[8..].chunks(16).map(|entry| (
+            u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+            u64::from_le_bytes(entry[8..16].try_into().unwrap()),
+        )).collect();
+        if entries.windows(2).any(|pair| pair[0].0 >= pair[1].0 || pair[0].1 > pair[1].1) {
+            return Err(invalid());
+        }
+        return Ok(BlockIndex{entries});
+    }
+
+    pub fn write(&self, path: &str) -> Result<()>{
+        let mut writer = FileWriter::get_writer(path.to_string())?;
+        writer.write_all(&(self.entries.len() as u64).to_le_bytes())?;
+        for (compressed, uncompressed) in self.entries.iter() {
+            writer.write_all(&compressed.to_le_bytes())?;
+            writer.write_all(&uncompressed.to_le_bytes())?;
+        }
+        writer.flush()?;
+        return Ok(());
+    }
+
+    //Reads the header and footer of every block of filename, without decompressing them.
+    //Fails with InvalidArgument if the file isn't BGZF.
+    pub fn scan(filename: &str) -> Result<BlockIndex>{
+        let mut file = io::BufReader::new(File::open(filename).map_err(|e| with_filename(e, "open", filename))?);
+        let len = file.get_ref().metadata()?.len();
+        let mut entries = vec![];
+        let (mut compressed, mut uncompressed) = (0, 0);
+        while compressed < len {
+            file.seek(SeekFrom::Start(compressed))?;
+            let block_size = match read_block_header(&mut file)? {
+                Some((block_size, _)) => block_size,
+                None => return Err(FastSeekError::InvalidArgument(format!("{} is not BGZF-compressed", filename))),
+            };
+            if compressed + block_size > len {
+                return Err(FastSeekError::InvalidArgument(format!("{} has a truncated BGZF block at {}", filename, compressed)));
+            }
+            file.seek(SeekFrom::Start(compressed + block_size - 4))?;
+            let mut data_size = [0; 4];
+            file.read_exact(&mut data_size)?;
+            let data_size = u32::from_le_bytes(data_size) as u64;
+            //Empty blocks, as the end-of-file marker, start no data
+            if compressed > 0 && data_size > 0 {
+                entries.push((compressed, uncompressed));
+            }
+            compressed += block_size;
+            uncompressed += data_size;
+        }
+        return Ok(BlockIndex{entries});
+    }
+
+    //Virtual offset of position in the uncompressed data
+    pub fn virtual_offset(&self, position: u64) -> u64{
+        let (compressed, uncompressed) = match self.entries.partition_point(|&(_, uncompressed)| uncompressed <= position) {
+            0 => (0, 0),
+            i => self.entries[i - 1],
+        };
+        return compressed << 16 | (position - uncompressed);
+    }
+
+    //Position in the uncompressed data of a virtual offset
+    pub fn uncompressed_position(&self, virtual_offset: u64) -> u64{
+        let compressed = virtual_offset >> 16;
+        let uncompressed = match self.entries.binary_search_by(|&(block, _)| block.cmp(&compressed)) {
+            Ok(i) => self.entries[i].1,
+            Err(0) => 0,
+            //Block starting no data, as the end-of-file marker: only its first byte, the end of the data, is reachable,
+            //and it isn't known without reading the previous block. Counted from the previous block instead.
+            Err(i) => self.entries[i - 1].1,
+        };
+        return uncompressed + (virtual_offset & 0xffff);
+    }
+}
+
+//Reader of the uncompressed data of a BGZF file, one block at a time, that seeks to virtual offsets:
+//offset of the block in the file << 16 | offset in the uncompressed data of the block
+pub struct BgzfReader{
+    file: io::BufReader<File>,
+    file_size: u64,
+    //Offset of the loaded block, and of the next one: u64::MAX if unknown because loading failed
+    block_offset: u64,
+    next_block_offset: u64,
+    //Uncompressed data of the loaded block, and position of the next byte to read in it
+    data: Vec<u8>,
+    position: usize,
+    compressed: Vec<u8>,
+    decompress: Decompress,
+}
+
+impl BgzfReader{
+    pub fn new(filename: &str) -> Result<BgzfReader>{
+        let file = File::open(filename).map_err(|e| with_filename(e, "open", filename))?;
+        let file_size = file.metadata()?.len();
+        let mut reader = BgzfReader{
+            file: io::BufReader::new(file), file_size, block_offset: 0, next_block_offset: 0,
+            data: vec![], position: 0, compressed: vec![], decompress: Decompress::new(false)
+        };
+        if !reader.load_block(0)? {
+            return Err(FastSeekError::InvalidArgument(format!("{} is not BGZF-compressed", filename)));
+        }
+        return Ok(reader);
+    }
+
+    pub fn seek_virtual(&mut self, virtual_offset: u64) -> Result<()>{
+        let block_offset = virtual_offset >> 16;
+        //Records found one after the other are often in the same block
+        let loaded = block_offset == self.block_offset && self.next_block_offset != u64::MAX;
+        if !loaded && !self.load_block(block_offset)? {
+            return Err(invalid_block(block_offset).into());
+        }
+        let position = (virtual_offset & 0xffff) as usize;
+        if position > self.data.len() {
+            return Err(FastSeekError::InvalidArgument(format!("virtual offset {} is past the end of its block", virtual_offset)));
+        }
+        self.position = position;
+        return Ok(());
+    }
+
+    //Virtual offset of the next byte read
+    pub fn virtual_position(&mut self) -> Result<u64>{
+        //At the end of a block, the next byte is the first one of the next block
+        self.fill_buf()?;
+        return Ok(self.block_offset << 16 | self.position as u64);
+    }
+
+    //Loads the block at offset, or nothing at the end of the file. False if there is no BGZF block at offset
+    fn load_block(&mut self, offset: u64) -> io::Result<bool>{
+        if offset != self.next_block_offset {
+            self.file.seek(SeekFrom::Start(offset))?;
+        }
+        self.next_block_offset = u64::MAX;
+        self.block_offset = offset;
+        self.data.clear();
+        self.position = 0;
+        if offset >= self.file_size {
+            self.next_block_offset = offset;
+            return Ok(true);
+        }
+        let (block_size, header_size) = match read_block_header(&mut self.file)? {
+            Some(sizes) => sizes,
+            None => return Ok(false),
+        };
+        if block_size < header_size + BLOCK_FOOTER_SIZE {
+            return Err(invalid_block(offset));
+        }
+        self.compressed.resize((block_size - header_size) as usize, 0);
+        self.file.read_exact(&mut self.compressed)?;
+        let footer = self.compressed.len() - BLOCK_FOOTER_SIZE as usize;
+        let crc = u32::from_le_bytes(self.compressed[footer..footer + 4].try_into().unwrap());
+        let data_size = u32::from_le_bytes(self.compressed[footer + 4..].try_into().unwrap()) as usize;
+        //Empty blocks, as the end-of-file marker, have nothing to decompress
+        if data_size > 0 {
+            self.data.reserve_exact(data_size);
+            self.decompress.reset(false);
+            self.decompress.decompress_vec(&self.compressed[..footer], &mut self.data, FlushDecompress::Finish)
+                .map_err(|_| invalid_block(offset))?;
+            let mut data_crc = Crc::new();
+            data_crc.update(&self.data);
+            if self.data.len() != data_size || data_crc.sum() != crc {
+                return Err(invalid_block(offset));
+            }
+        }
+        self.next_block_offset = offset + block_size;
+        return Ok(true);
+    }
+}
+
+impl BufRead for BgzfReader{
+    fn fill_buf(&mut self) -> io::Result<&[u8]>{
+        while self.position >= self.data.len() && self.next_block_offset < self.file_size {
+            if !self.load_block(self.next_block_offset)? {
+                return Err(invalid_block(self.block_offset));
+            }
+        }
+        return Ok(&self.data[self.position..]);
+    }
+
+    fn consume(&mut self, amount: usize){
+        self.position = (self.position + amount).min(self.data.len());
+    }
+}
+
+impl Read for BgzfReader{
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize>{
+        let data = self.fill_buf()?;
+        let n = data.len().min(buffer.len());
+        buffer[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        return Ok(n);
+    }
+}
+
+//Reads a compressed or uncompressed file while writing it BGZF-compressed to output, so that the file is
+//indexed while converted: offsets are those of the output. The first seek finishes the output, read from then on.
+pub struct BgzfConverter{
+    input: Box<dyn BufRead>,
+    output: String,
+    writer: Option<BGZFWriter<io::BufWriter<File>>>,
+    //Reader of the output once finished
+    reader: Option<GzFileReader>,
+}
+
+impl BgzfConverter{
+    pub fn new(input: &str, output: &str) -> Result<BgzfConverter>{
+        let input_reader = decompressed_reader(input)?;
+        let temp_path = format!("{}.tmp", output);
+        let temp_file = File::create(&temp_path).map_err(|e| with_filename(e, "create", &temp_path))?;
+        return Ok(BgzfConverter{
+            input: input_reader, output: output.to_string(),
+            writer: Some(BGZFWriter::new(io::BufWriter::new(temp_file), Compression::default())), reader: None
+        });
+    }
+
+    //Converts the rest of the input and replaces output with the converted file
+    pub fn finish(&mut self) -> Result<()>{
+        let mut writer = match self.writer.take() {
+            Some(writer) => writer,
+            None => return Ok(()),
+        };
+        io::copy(&mut self.input, &mut writer)?;
+        writer.close()?;
+        let temp_path = format!("{}.tmp", self.output);
+        std::fs::rename(&temp_path, &self.output).map_err(|e| with_filename(e, "replace", &self.output))?;
+        //The block index or checkpoints of a previous file don't match this one
+        for path in [gzi_path(&self.output), checkpoints_path(&self.output)] {
+            if Path::new(&path).exists() {
+                std::fs::remove_file(path)?;
+            }
+        }
+        self.reader = Some(GzFileReader::new(&self.output)?);
+        return Ok(());
+    }
+
+    //Writer of the output, until finished
+    fn writer(&mut self) -> Result<&mut BGZFWriter<io::BufWriter<File>>>{
+        if self.writer.is_none() {
+            return Err(self.failed());
+        }
+        return Ok(self.writer.as_mut().unwrap());
+    }
+
+    //Error of a conversion that failed, leaving neither a writer nor a reader
+    fn failed(&self) -> FastSeekError{
+        return FastSeekError::InvalidArgument(format!("conversion to {} failed", self.output));
+    }
+}
+
+impl FileReader for BgzfConverter{
+    fn seek(&mut self, pos: u64) -> Result<()> {
+        self.finish()?;
+        return match &mut self.reader {
+            Some(reader) => reader.seek(pos),
+            None => Err(self.failed()),
+        };
+    }
+    fn position(&mut self) -> Result<u64> {
+        return match (&mut self.writer, &mut self.reader) {
+            (Some(writer), _) => Ok(writer.bgzf_pos()),
+            (None, Some(reader)) => reader.position(),
+            (None, None) => Err(self.failed()),
+        };
+    }
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        if let Some(reader) = &mut self.reader {
+            return reader.read_exact(buffer);
+        }
+        self.input.read_exact(buffer)?;
+        self.writer()?.write_all(buffer)?;
+        return Ok(());
+    }
+    fn read_line(&mut self, buffer: &mut String) -> Result<usize> {
+        if let Some(reader) = &mut self.reader {
+            return reader.read_line(buffer);
+        }
+        let start = buffer.len();
+        let bytes_read = self.input.read_line(buffer)?;
+        self.writer()?.write_all(&buffer.as_bytes()[start..])?;
+        return Ok(bytes_read);
+    }
+    fn num_lines(&mut self) -> Result<u64> {
+        if let Some(reader) = &mut self.reader {
+            return reader.num_lines();
+        }
+        let mut number_lines = 0;
+        let mut buffer = [0; 8192];
+        loop {
+            let n = self.input.read(&mut buffer)?;
+            if n == 0 { break; }
+            number_lines += buffer[..n].iter()
+                .filter(|&&byte| byte == b'\n')
+                .count();
+            self.writer()?.write_all(&buffer[..n])?;
+        }
+        self.seek(0)?;
+        return Ok(number_lines as u64);
+    }
+}
+
+fn invalid_block(offset: u64) -> io::Error{
+    return io::Error::new(io::ErrorKind::InvalidData, format!("invalid BGZF block at offset {}", offset));
+}
+
+//Size of the BGZF block starting at the position of reader and of its header, which is read.
+//None if it isn't a BGZF block.
+fn read_block_header<R: Read>(reader: &mut R) -> io::Result<Option<(u64, u64)>>{
+    let mut header = [0; GZIP_HEADER_SIZE];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    //gzip magic, deflate, FEXTRA flag
+    if header[0..3] != [0x1f, 0x8b, 8] || header[3] & 0x04 == 0 {
+        return Ok(None);
+    }
+    let mut extra = vec![0; u16::from_le_bytes([header[10], header[11]]) as usize];
+    reader.read_exact(&mut extra)?;
+    let mut position = 0;
+    while position + 4 <= extra.len() {
+        let subfield_size = u16::from_le_bytes([extra[position + 2], extra[position + 3]]) as usize;
+        if extra[position..position + 2] == *b"BC" && subfield_size == 2 && position + 6 <= extra.len() {
+            let block_size = u16::from_le_bytes([extra[position + 4], extra[position + 5]]) as u64 + 1;
+            return Ok(Some((block_size, (GZIP_HEADER_SIZE + extra.len()) as u64)));
+        }
+        position += 4 + subfield_size;
+    }
+    return Ok(None);
+}
@@ -0,0 +1,630 @@
+use crate::bgzf::{file_compression, gzi_path, BgzfConverter, BlockIndex, FileCompression};
+use crate::error::{FastSeekError, Result};
+use crate::file_reader::{open_file_reader, FastqInputReader, FileReader, InputReader, MultiFastaInputReader, TabularInputReader, TabularOptions};
+use crate::fingerprint::Fingerprint;
+use crate::gzip_checkpoints::{GzipCheckpointReader, DEFAULT_CHECKPOINT_INTERVAL};
+use crate::header::{Header, FLAG_BGZF_VIRTUAL_OFFSETS, FLAG_COMPOSITE_KEY, FLAG_COMPRESSED_BLOCKS, FLAG_COMPRESSED_HASHMAP, FLAG_CONTIGUOUS_BLOCKS, FLAG_FINGERPRINT, FLAG_HAS_HEADER, FLAG_COMMENT_CHAR, FLAG_ID_TOKEN, FLAG_NORMALIZED_KEYS, FLAG_ORDERED_KEYS, FLAG_QUOTED_FIELDS, FLAG_UPDATABLE, FLAG_WHITESPACE_SEPARATOR};
+use crate::index_compression::compress_index;
+use crate::index_structure::{rewrite_header, IndexStructure};
+use crate::key_extractor::{split_quoted, CaseFolding, KeyExtractor, KeyNormalization, Separator};
+use crate::ordered_index::{ordered_index_path, OrderedIndexWriter};
+use crate::pipeline::{index_pass, IndexTarget};
+use crate::{index_path, recorded_source_path, CURRENT_VERSION};
+
+/// Kind of file being indexed
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Format {
+    /// Tabular file (csv, tsv), indexed by one or more columns
+    Tabular,
+    /// Multi-fasta file, indexed by sequence-id or sequence
+    Fasta,
+    /// FastQ file, indexed by sequence-id or sequence
+    Fastq,
+}
+
+/// Compression of the index file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum IndexCompression {
+    /// Uncompressed index
+    None,
+    /// Block area compressed, hashmap kept plain for random access
+    Blocks,
+    /// Hashmap and block area compressed. The hashmap is loaded entirely in memory when searching.
+    Full,
+}
+
+/// Order of the keys in an ordered index
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum KeyOrder {
+    /// Keys compared byte by byte, as strings
+    Lexicographic,
+    /// Keys compared as numbers: every key must be one
+    Numeric,
+}
+
+/// Builds the index of a file. The index is written to `<filename>.index`, or `<filename>.<name>.index` if named,
+/// unless another directory or path is given.
+#[derive(Clone)]
+pub struct IndexBuilder {
+    filename: String,
+    format: Format,
+    columns: Vec<usize>,
+    separator: String,
+    by_sequence: bool,
+    id_token: Option<usize>,
+    hashmap_size: u64,
+    in_memory_map_size: u64,
+    contiguous_blocks: bool,
+    compression: IndexCompression,
+    threads: usize,
+    name: Option<String>,
+    index_file: Option<String>,
+    index_dir: Option<String>,
+    quote: Option<char>,
+    has_header: bool,
+    comment_char: Option<char>,
+    column_names: Vec<String>,
+    whitespace: bool,
+    normalization: KeyNormalization,
+    ordered: Option<KeyOrder>,
+    gzi: bool,
+    bgzip_source: Option<String>,
+    checkpoint_interval: u64,
+    command_line: String,
+}
+
+impl IndexBuilder {
+    pub fn new(filename: &str, format: Format) -> IndexBuilder {
+        return IndexBuilder {
+            filename: filename.to_string(),
+            format,
+            columns: vec![0],
+            separator: "\t".to_string(),
+            by_sequence: false,
+            id_token: None,
+            hashmap_size: 0,
+            in_memory_map_size: 2000000000,
+            contiguous_blocks: false,
+            compression: IndexCompression::None,
+            threads: 1,
+            name: None,
+            index_file: None,
+            index_dir: None,
+            quote: None,
+            has_header: false,
+            comment_char: None,
+            column_names: vec![],
+            whitespace: false,
+            normalization: KeyNormalization{line_end: true, ..KeyNormalization::default()},
+            ordered: None,
+            gzi: false,
+            bgzip_source: None,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+            command_line: String::new(),
+        };
+    }
+
+    /// Column to index (0-based). Tabular files only.
+    pub fn column(mut self, column: usize) -> IndexBuilder {
+        self.columns = vec![column];
+        return self;
+    }
+
+    /// Columns to index (0-based), the key is their fields joined by the separator. Tabular files only.
+    pub fn columns(mut self, columns: &[usize]) -> IndexBuilder {
+        self.columns = columns.to_vec();
+        return self;
+    }
+
+    /// Field separator, of any length. Tabular files only, defaults to tab.
+    pub fn separator(mut self, separator: &str) -> IndexBuilder {
+        self.separator = separator.to_string();
+        return self;
+    }
+
+    /// If set, index by raw sequence instead of sequence-id. Fasta and fastQ files only.
+    pub fn by_sequence(mut self, by_sequence: bool) -> IndexBuilder {
+        self.by_sequence = by_sequence;
+        return self;
+    }
+
+    /// Index by the `token`-th (0-based) whitespace-separated token of the header line, without the leading `>` or `@`,
+    /// instead of the whole header line. Searched keys are normalised the same way. Fasta and fastQ files only.
+    pub fn id_token(mut self, token: usize) -> IndexBuilder {
+        self.id_token = Some(token);
+        return self;
+    }
+
+    /// Number of entries of the hashmap. If 0 (default), the number of entries of the file is used.
+    pub fn hashmap_size(mut self, hashmap_size: u64) -> IndexBuilder {
+        self.hashmap_size = hashmap_size;
+        return self;
+    }
+
+    /// Max number of hashmap entries kept in memory, each entry takes 8 bytes.
+    /// If the hashmap is larger, the input file is iterated multiple times.
+    pub fn in_memory_map_size(mut self, in_memory_map_size: u64) -> IndexBuilder {
+        self.in_memory_map_size = in_memory_map_size;
+        return self;
+    }
+
+    /// If set, entries sharing a hashmap slot are stored as contiguous blocks instead of linked lists.
+    /// Searching keys with many duplicates is faster and the index is smaller, but colliding entries
+    /// are sorted at the end of each iteration over the file: they take the memory the hashmap leaves of
+    /// [`in_memory_map_size`](IndexBuilder::in_memory_map_size), up to 256 MiB, and temporary files beyond it.
+    pub fn contiguous_blocks(mut self, contiguous_blocks: bool) -> IndexBuilder {
+        self.contiguous_blocks = contiguous_blocks;
+        return self;
+    }
+
+    /// Compression of the index file, defaults to none.
+    /// Compressed indexes are written uncompressed to `<index>.tmp` first.
+    pub fn compression(mut self, compression: IndexCompression) -> IndexBuilder {
+        self.compression = compression;
+        return self;
+    }
+
+    /// Number of threads extracting and hashing keys, defaults to 1.
+    /// With more than one, reading and insertion into the index run on two additional threads.
+    /// The index is the same whatever the number of threads.
+    pub fn threads(mut self, threads: usize) -> IndexBuilder {
+        self.threads = threads;
+        return self;
+    }
+
+    /// Split fields on runs of blanks instead of the separator, ignoring leading and trailing ones (as awk does).
+    /// Composite keys are joined by a space. Tabular files only.
+    pub fn whitespace(mut self, whitespace: bool) -> IndexBuilder {
+        self.whitespace = whitespace;
+        return self;
+    }
+
+    /// Parse the file as csv (RFC 4180): fields enclosed in `quote` may contain the separator, line breaks,
+    /// and the quote itself, doubled. Records span as many lines as their quoted fields. Tabular files only.
+    pub fn quote(mut self, quote: char) -> IndexBuilder {
+        self.quote = Some(quote);
+        return self;
+    }
+
+    /// The first line of the file, after comments, is a header line: it is not indexed, and stored in the index.
+    /// Tabular files only.
+    pub fn has_header(mut self, has_header: bool) -> IndexBuilder {
+        self.has_header = has_header;
+        return self;
+    }
+
+    /// Lines starting with `comment_char` are skipped. Tabular files only.
+    pub fn comment_char(mut self, comment_char: char) -> IndexBuilder {
+        self.comment_char = Some(comment_char);
+        return self;
+    }
+
+    /// Remove leading and trailing whitespace from keys, and from searched keys.
+    /// The fields of composite keys are trimmed one by one.
+    pub fn trim_keys(mut self, trim: bool) -> IndexBuilder {
+        self.normalization.trim = trim;
+        return self;
+    }
+
+    /// Ignore the case of keys: keys and searched keys are lowercased, defaults to [`CaseFolding::None`].
+    pub fn case_folding(mut self, case: CaseFolding) -> IndexBuilder {
+        self.normalization.case = case;
+        return self;
+    }
+
+    /// Remove a pair of matching quotes, `"` or `'`, enclosing keys and searched keys.
+    /// Applied after trimming, so quotes surrounded by whitespace are removed only with [`trim_keys`](IndexBuilder::trim_keys).
+    pub fn strip_quotes(mut self, strip_quotes: bool) -> IndexBuilder {
+        self.normalization.strip_quotes = strip_quotes;
+        return self;
+    }
+
+    /// Also write an ordered index of the keys to `<index>.sorted`, in the same pass over the file,
+    /// for prefix and range searches ([`Searcher::search_prefix`](crate::Searcher::search_prefix),
+    /// [`Searcher::search_range`](crate::Searcher::search_range)). With [`KeyOrder::Numeric`],
+    /// records whose key is not a number are reported as malformed.
+    pub fn ordered(mut self, order: KeyOrder) -> IndexBuilder {
+        self.ordered = Some(order);
+        return self;
+    }
+
+    /// Also write the block index of the BGZF-compressed file to `<filename>.gzi`, as `bgzip -i` does,
+    /// in the same format. Fails if the file is not BGZF-compressed.
+    pub fn gzi(mut self, gzi: bool) -> IndexBuilder {
+        self.gzi = gzi;
+        return self;
+    }
+
+    /// Write the indexed file first, BGZF-compressed, from `source`: a compressed or uncompressed file, or the
+    /// indexed file itself, replaced once converted. The records are counted while converting, so the file is read
+    /// as many times as when indexing a BGZF file. Indexes built together must have the same source.
+    pub fn bgzip_source(mut self, source: &str) -> IndexBuilder {
+        self.bgzip_source = Some(source.to_string());
+        return self;
+    }
+
+    /// Uncompressed bytes between two checkpoints of a plain (not BGZF) gzip-compressed file, 4 MiB by default.
+    /// Checkpoints are written to `<filename>.gzc` while indexing, and searches decompress from the last one
+    /// before a record: a smaller interval makes searches faster and the checkpoints file larger.
+    pub fn checkpoint_interval(mut self, bytes: u64) -> IndexBuilder {
+        self.checkpoint_interval = bytes.max(1);
+        return self;
+    }
+
+    /// Column to index, by its name in the header line. Requires [`has_header`](IndexBuilder::has_header).
+    pub fn column_name(self, name: &str) -> IndexBuilder {
+        return self.column_names(&[name]);
+    }
+
+    /// Columns to index, by their names in the header line, as [`columns`](IndexBuilder::columns).
+    /// Requires [`has_header`](IndexBuilder::has_header).
+    pub fn column_names(mut self, names: &[&str]) -> IndexBuilder {
+        self.column_names = names.iter().map(|name| name.to_string()).collect();
+        return self;
+    }
+
+    /// Name of the index, written to `<filename>.<name>.index` instead of `<filename>.index`.
+    /// Files can have several indexes with different names.
+    pub fn name(mut self, name: &str) -> IndexBuilder {
+        self.name = Some(name.to_string());
+        return self;
+    }
+
+    /// Path of the index file, instead of the default one given by [`index_path`](crate::index_path)
+    pub fn index_file(mut self, path: &str) -> IndexBuilder {
+        self.index_file = Some(path.to_string());
+        return self;
+    }
+
+    /// Directory where the index is written, instead of the directory of the indexed file
+    pub fn index_dir(mut self, index_dir: &str) -> IndexBuilder {
+        self.index_dir = Some(index_dir.to_string());
+        return self;
+    }
+
+    /// Command line that built the index, recorded in its header. Empty by default.
+    pub fn command_line(mut self, command_line: &str) -> IndexBuilder {
+        self.command_line = command_line.to_string();
+        return self;
+    }
+
+    pub fn build(self) -> Result<()> {
+        return IndexBuilder::build_all(vec![self]);
+    }
+
+    /// Builds several indexes of the same file in a single pass over it, e.g. a fastQ file by sequence-id
+    /// and by sequence. Every builder must have the same filename and format, and a different index path.
+    /// Each index keeps its own options, except for threads: the largest number is used.
+    pub fn build_all(builders: Vec<IndexBuilder>) -> Result<()> {
+        let builders = IndexBuilder::convert_source(builders)?;
+        let builders = builders.into_iter().map(IndexBuilder::resolve_column_names).collect::<Result<Vec<IndexBuilder>>>()?;
+        let first = match builders.first() {
+            Some(first) => first,
+            None => return Ok(()),
+        };
+        for (i, builder) in builders.iter().enumerate() {
+            if builder.filename != first.filename || builder.format != first.format {
+                return Err(FastSeekError::InvalidArgument("indexes built together must have the same file and format".to_string()));
+            }
+            if (builder.quote, builder.has_header, builder.comment_char) != (first.quote, first.has_header, first.comment_char) {
+                return Err(FastSeekError::InvalidArgument("indexes built together must split the file in records the same way".to_string()));
+            }
+            if builders[..i].iter().any(|other| other.index_path() == builder.index_path()) {
+                return Err(FastSeekError::InvalidArgument(format!("index {} built twice", builder.index_path())));
+            }
+        }
+        //Taken before reading the file, so that changes made while indexing are detected too
+        let fingerprint = Fingerprint::of_file(&first.filename)?;
+        //Read before indexing, to fail early if the file isn't BGZF
+        let block_index = match builders.iter().any(|builder| builder.gzi) {
+            true => Some(BlockIndex::scan(&first.filename)?),
+            false => None,
+        };
+        let mut plans: Vec<IndexPlan> = vec![];
+        let header_line = match first.has_header {
+            true => first.tabular_reader()?.header_line()?.unwrap_or_default(),
+            false => String::new(),
+        };
+        for builder in builders.iter() {
+            let input_reader = builder.input_reader()?;
+            let mut header = builder.header(input_reader.as_ref());
+            header.fingerprint = fingerprint.clone();
+            header.header_line = header_line.clone();
+            let index_path = builder.index_path();
+            header.source_path = recorded_source_path(&builder.filename, &index_path);
+            let write_path = match builder.compression {
+                IndexCompression::None => index_path.clone(),
+                _ => format!("{}.tmp", index_path),
+            };
+            let ordered = builder.ordered.map(|order| OrderedIndexWriter::new(ordered_index_path(&index_path), order, fingerprint.clone()));
+            plans.push(IndexPlan{
+                index_path: write_path, header, key_extractor: input_reader.key_extractor().clone(), in_memory_map_size: builder.in_memory_map_size, ordered
+            });
+        }
+        //Records are read the same way whatever the key, so one reader serves every index.
+        //It reads the whole file, so it records the checkpoints of a plain gzip file.
+        let mut input_reader = first.input_reader_of(first.file_reader(true)?);
+        let threads = builders.iter().map(|builder| builder.threads).max().unwrap();
+        let mut indexes = index(input_reader.as_mut(), plans, threads)?;
+        //Records appended from here on can be added by update_index, if the fingerprint covers exactly the data indexed
+        let updatable = file_compression(&first.filename)? == FileCompression::None && input_reader.position()? == fingerprint.size
+            && Fingerprint::of_file(&first.filename)? == fingerprint;
+        for (builder, (header, blocks_start)) in builders.iter().zip(indexes.iter_mut()) {
+            if builder.compression == IndexCompression::None {
+                if updatable {
+                    header.flags |= FLAG_UPDATABLE;
+                    rewrite_header(&builder.index_path(), header)?;
+                }
+                continue;
+            }
+            let index_path = builder.index_path();
+            let uncompressed_path = format!("{}.tmp", index_path);
+            compress_index(&uncompressed_path, &index_path, header, *blocks_start)?;
+            std::fs::remove_file(&uncompressed_path)?;
+        }
+        if let Some(block_index) = block_index {
+            block_index.write(&gzi_path(&first.filename))?;
+        }
+        return Ok(());
+    }
+
+    //Writes the indexed file from the source given with bgzip_source, if any, and sets the hashmap size
+    //left to the number of records to the number counted while converting
+    fn convert_source(mut builders: Vec<IndexBuilder>) -> Result<Vec<IndexBuilder>> {
+        let first = match builders.first() {
+            Some(first) => first,
+            None => return Ok(builders),
+        };
+        let source = match &first.bgzip_source {
+            Some(source) => source.clone(),
+            None => return Ok(builders),
+        };
+        for builder in builders.iter() {
+            builder.validate()?;
+            if builder.bgzip_source.as_ref() != Some(&source) || builder.filename != first.filename || builder.format != first.format {
+                return Err(FastSeekError::InvalidArgument("indexes built together must convert the same file".to_string()));
+            }
+        }
+        //Counting the records reads the whole source, and the first seek finishes the converted file
+        let converter = BgzfConverter::new(&source, &first.filename)?;
+        let num_entries = first.input_reader_of(Box::new(converter)).num_entries()?;
+        for builder in builders.iter_mut() {
+            builder.bgzip_source = None;
+            if builder.hashmap_size == 0 {
+                builder.hashmap_size = num_entries.max(1);
+            }
+        }
+        return Ok(builders);
+    }
+
+    //Validates the builder, and sets the columns named by column_names from the header line
+    fn resolve_column_names(mut self) -> Result<IndexBuilder> {
+        self.validate()?;
+        if self.column_names.is_empty() {
+            return Ok(self);
+        }
+        let header_line = match self.tabular_reader()?.header_line()? {
+            Some(header_line) => header_line,
+            None => return Err(FastSeekError::InvalidArgument(format!("{} has no header line", self.filename))),
+        };
+        let fields: Vec<String> = match self.quote {
+            Some(quote) => split_quoted(&header_line, &self.separator, quote)
+                .map_err(|reason| FastSeekError::InvalidArgument(format!("invalid header line: {}", reason)))?
+                .into_iter().map(|field| field.trim().to_string()).collect(),
+            None => self.tabular_separator().split(&header_line).map(|field| field.trim().to_string()).collect(),
+        };
+        let mut columns = vec![];
+        for name in self.column_names.iter() {
+            match fields.iter().position(|field| field == name) {
+                Some(column) => columns.push(column),
+                None => return Err(FastSeekError::InvalidArgument(format!("column {:?} not found in the header line", name))),
+            }
+        }
+        self.columns = columns;
+        self.validate()?;
+        return Ok(self);
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.in_memory_map_size == 0 {
+            return Err(FastSeekError::InvalidArgument("in-memory map size must be greater than 0".to_string()));
+        }
+        if self.threads == 0 {
+            return Err(FastSeekError::InvalidArgument("number of threads must be greater than 0".to_string()));
+        }
+        if self.format == Format::Tabular {
+            if self.separator.is_empty() || self.separator.len() > u16::MAX as usize || self.separator.contains(['\0', '\n', '\r']) {
+                return Err(FastSeekError::InvalidArgument(format!("invalid separator {:?}", self.separator)));
+            }
+            if self.columns.is_empty() || self.columns.len() > u16::MAX as usize {
+                return Err(FastSeekError::InvalidArgument(format!("number of columns must be between 1 and {}", u16::MAX)));
+            }
+            if self.columns.iter().any(|&column| column > u32::MAX as usize) {
+                return Err(FastSeekError::InvalidArgument(format!("column must be at most {}", u32::MAX)));
+            }
+        }
+        if let Some(token) = self.id_token {
+            if self.format == Format::Tabular || self.by_sequence {
+                return Err(FastSeekError::InvalidArgument("id token keys are only available for fasta and fastq headers".to_string()));
+            }
+            if token > u8::MAX as usize {
+                return Err(FastSeekError::InvalidArgument(format!("id token must be at most {}", u8::MAX)));
+            }
+        }
+        if let Some(quote) = self.quote {
+            if self.format != Format::Tabular {
+                return Err(FastSeekError::InvalidArgument("quote character is only available for tabular files".to_string()));
+            }
+            if !quote.is_ascii() || quote == '\0' || quote == '\n' || quote == '\r' || self.separator.contains(quote) {
+                return Err(FastSeekError::InvalidArgument(format!("invalid quote character {:?}", quote)));
+            }
+        }
+        if self.whitespace && self.quote.is_some() {
+            return Err(FastSeekError::InvalidArgument("quoted fields can't be separated by whitespace".to_string()));
+        }
+        if self.format != Format::Tabular && (self.whitespace || self.has_header || self.comment_char.is_some() || !self.column_names.is_empty()) {
+            return Err(FastSeekError::InvalidArgument("header lines, comments and column names are only available for tabular files".to_string()));
+        }
+        if !self.column_names.is_empty() && !self.has_header {
+            return Err(FastSeekError::InvalidArgument("columns can only be selected by name in files with a header line".to_string()));
+        }
+        if let Some(comment_char) = self.comment_char {
+            if !comment_char.is_ascii() || comment_char == '\0' || comment_char == '\n' || comment_char == '\r' {
+                return Err(FastSeekError::InvalidArgument(format!("invalid comment character {:?}", comment_char)));
+            }
+        }
+        if let Some(name) = &self.name {
+            if name.is_empty() || name.contains(['/', '\\']) {
+                return Err(FastSeekError::InvalidArgument(format!("invalid index name {:?}", name)));
+            }
+        }
+        return Ok(());
+    }
+
+    fn index_path(&self) -> String {
+        return match &self.index_file {
+            Some(index_file) => index_file.clone(),
+            None => index_path(&self.filename, self.index_dir.as_deref(), self.name.as_deref()),
+        };
+    }
+
+    fn tabular_separator(&self) -> Separator {
+        return match self.whitespace {
+            true => Separator::Whitespace,
+            false => Separator::Text(self.separator.clone()),
+        };
+    }
+
+    //Reader of the file, which records the checkpoints of a plain gzip file if record is set.
+    //Without, a plain gzip file needs no checkpoints: it is read from its start.
+    fn file_reader(&self, record: bool) -> Result<Box<dyn FileReader>> {
+        return Ok(match file_compression(&self.filename)? {
+            FileCompression::Gzip => Box::new(GzipCheckpointReader::new(&self.filename, record.then_some(self.checkpoint_interval))?),
+            _ => open_file_reader(&self.filename)?,
+        });
+    }
+
+    fn tabular_reader(&self) -> Result<TabularInputReader> {
+        return Ok(self.tabular_reader_of(self.file_reader(false)?));
+    }
+
+    fn tabular_reader_of(&self, file_reader: Box<dyn FileReader>) -> TabularInputReader {
+        let options = TabularOptions{
+            separator: self.tabular_separator(), quote: self.quote, has_header: self.has_header, comment_char: self.comment_char
+        };
+        return TabularInputReader::new(file_reader, options, &self.columns, self.normalization);
+    }
+
+    fn input_reader(&self) -> Result<Box<dyn InputReader>> {
+        return Ok(self.input_reader_of(self.file_reader(false)?));
+    }
+
+    fn input_reader_of(&self, file_reader: Box<dyn FileReader>) -> Box<dyn InputReader> {
+        return match self.format {
+            Format::Tabular => Box::new(self.tabular_reader_of(file_reader)),
+            Format::Fasta => Box::new(MultiFastaInputReader::new(file_reader, self.by_sequence, self.id_token, self.normalization)),
+            Format::Fastq => Box::new(FastqInputReader::new(file_reader, self.by_sequence, self.id_token, self.normalization)),
+        };
+    }
+
+    fn header(&self, input_reader: &dyn InputReader) -> Header {
+        let mut flags = match self.contiguous_blocks {
+            true => FLAG_CONTIGUOUS_BLOCKS,
+            false => 0,
+        };
+        flags |= match self.compression {
+            IndexCompression::None => 0,
+            IndexCompression::Blocks => FLAG_COMPRESSED_BLOCKS,
+            IndexCompression::Full => FLAG_COMPRESSED_BLOCKS | FLAG_COMPRESSED_HASHMAP,
+        };
+        if self.id_token.is_some() {
+            flags |= FLAG_ID_TOKEN;
+        }
+        if self.format == Format::Tabular && self.columns.len() > 1 {
+            flags |= FLAG_COMPOSITE_KEY;
+        }
+        if self.quote.is_some() {
+            flags |= FLAG_QUOTED_FIELDS;
+        }
+        if self.has_header {
+            flags |= FLAG_HAS_HEADER;
+        }
+        if self.whitespace {
+            flags |= FLAG_WHITESPACE_SEPARATOR;
+        }
+        if self.comment_char.is_some() {
+            flags |= FLAG_COMMENT_CHAR;
+        }
+        if self.normalization != KeyNormalization::default() {
+            flags |= FLAG_NORMALIZED_KEYS;
+        }
+        if self.ordered.is_some() {
+            flags |= FLAG_ORDERED_KEYS;
+        }
+        flags |= FLAG_FINGERPRINT | FLAG_BGZF_VIRTUAL_OFFSETS;
+        let input_details = input_reader.get_types_for_header();
+        let mut header = Header::new(CURRENT_VERSION, self.hashmap_size, input_details.0, input_details.1, input_details.2, flags);
+        header.id_token = self.id_token.unwrap_or(0) as u8;
+        header.quote = self.quote.unwrap_or('\0') as u8;
+        header.comment_char = self.comment_char.unwrap_or('\0') as u8;
+        header.normalization = self.normalization.to_bits();
+        header.tool_version = env!("CARGO_PKG_VERSION").to_string();
+        header.command_line = self.command_line.clone();
+        return header;
+    }
+}
+
+//An index to write: its uncompressed file, header and how keys are extracted from records
+struct IndexPlan {
+    index_path: String,
+    header: Header,
+    key_extractor: KeyExtractor,
+    in_memory_map_size: u64,
+    ordered: Option<OrderedIndexWriter>,
+}
+
+//Write the uncompressed indexes, iterating over the file once for all of them (or more, if a hashmap
+//doesn't fit in memory), and their ordered indexes during the first pass. Returns the header and the address of the block area of each index.
+//If the hashmap size of a header is 0, the number of entries of the file is used.
+fn index(input_reader: &mut dyn InputReader, plans: Vec<IndexPlan>, threads: usize) -> Result<Vec<(Header, u64)>> {
+    let mut num_entries: Option<u64> = None;
+    let mut targets: Vec<IndexTarget> = vec![];
+    for mut plan in plans {
+        //If unspecified, set hashmap_size to number of lines
+        if plan.header.hashmap_size == 0 {
+            if num_entries.is_none() {
+                num_entries = Some(input_reader.num_entries()?);
+            }
+            plan.header.hashmap_size = num_entries.unwrap();
+        }
+        //An empty file still gets a valid index
+        plan.header.hashmap_size = plan.header.hashmap_size.max(1);
+
+        //Create the index structure
+        let structure = IndexStructure::new(plan.index_path, plan.header, plan.in_memory_map_size)?;
+        let hashmap_size = structure.header.hashmap_size as u128;
+        targets.push(IndexTarget{key_extractor: plan.key_extractor, structure, hashmap_size, ordered: plan.ordered});
+    }
+    //Indexes whose hashmap is not complete yet
+    let mut pending: Vec<bool> = vec![true; targets.len()];
+    loop{
+        let mut pass_targets: Vec<&mut IndexTarget> = targets.iter_mut().zip(pending.iter())
+            .filter(|(_, &pending)| pending)
+            .map(|(target, _)| target)
+            .collect();
+        index_pass(input_reader, &mut pass_targets, threads)?;
+        for target in targets.iter_mut() {
+            if let Some(ordered) = target.ordered.take() {
+                ordered.finish()?;
+            }
+        }
+        for (target, pending) in targets.iter_mut().zip(pending.iter_mut()).filter(|(_, pending)| **pending) {
+            *pending = target.structure.next()?;
+        }
+        if !pending.iter().any(|&pending| pending) {
+            break;
+        }
+        input_reader.reset()?;
+    }
+    return Ok(targets.into_iter().map(|target| (target.structure.header, target.structure.blocks_start)).collect());
+}
@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[command(author, version, about)]
@@ -15,24 +15,14 @@ pub enum Commands {
         /// Input file to index
         filename: String,
 
-        /// Column number to index (0-based)
-        #[arg(short = 'c', long, required = true)]
-        column: usize,
+        #[command(flatten)]
+        key: TabularArgs,
 
-        /// Field separator
-        #[arg(long, short = 's', default_value = "\t")]
-        separator: String,
+        #[command(flatten)]
+        compressed_file: CompressedFileArgs,
 
-        /// Pre-allocated hashmap size (defaults to file line count)
-        /// NOTE: specifying this value instead of using the default value will speed up the indexing process.
-        /// It is recommended to set this value to a value similar to the number of lines in the original file.
-        #[arg(long, default_value = "0")]
-        hashmap_size: u128,
-
-        /// Max size of in-memory hashmap, number of entries. Each entry takes 8 bytes.
-        /// If the hashmap is larger than the allowed in-memory hashmap, input file will be iterated multiple times.
-        #[arg(long, default_value = "2000000000")]
-        in_memory_map_size: u64,
+        #[command(flatten)]
+        options: IndexOptions,
     },
 
     /// Index a multi-fasta file by sequence-id or sequence
@@ -40,20 +30,14 @@ pub enum Commands {
         /// Input file to index
         filename: String,
 
-        /// If set, index by raw sequence, otherwise index by sequence-id
-        #[arg(long)]
-        by_sequence: bool,
-
-        /// Pre-allocated hashmap size (defaults to file line count)
-        /// NOTE: specifying this value instead of using the default value will speed up the indexing process.
-        /// It is recommended to set this value to a value similar to the number of lines in the original file.
-        #[arg(long, default_value = "0")]
-        hashmap_size: u128,
-
-        /// Max size of in-memory hashmap, number of entries. Each entry takes 8 bytes.
-        /// If the hashmap is larger than the allowed in-memory hashmap, input file will be iterated multiple times.
-        #[arg(long, default_value = "2000000000")]
-        in_memory_map_size: u64,
+        #[command(flatten)]
+        key: SequenceArgs,
+
+        #[command(flatten)]
+        compressed_file: CompressedFileArgs,
+
+        #[command(flatten)]
+        options: IndexOptions,
     },
 
     /// Index a fastq file by sequence-id or sequence
@@ -61,37 +45,356 @@ pub enum Commands {
         /// Input file to index
         filename: String,
 
-        /// If set, index by raw sequence, otherwise index by sequence-id
-        #[arg(long)]
-        by_sequence: bool,
-
-        /// Pre-allocated hashmap size (defaults to file line count)
-        /// NOTE: specifying this value instead of using the default value will speed up the indexing process.
-        /// It is recommended to set this value to a value similar to the number of lines in the original file.
-        #[arg(long, default_value = "0")]
-        hashmap_size: u128,
-
-        /// Max size of in-memory hashmap, number of entries. Each entry takes 8 bytes.
-        /// If the hashmap is larger than the allowed in-memory hashmap, input file will be iterated multiple times.
-        #[arg(long, default_value = "2000000000")]
-        in_memory_map_size: u64,
+        #[command(flatten)]
+        key: SequenceArgs,
+
+        #[command(flatten)]
+        compressed_file: CompressedFileArgs,
+
+        #[command(flatten)]
+        options: IndexOptions,
     },
 
     /// Search for keywords in an indexed file
     Search {
-        /// Input file to search
-        filename: String,
+        /// Input file to search. Not given with --index, which records the input file:
+        /// every positional argument is then a keyword.
+        #[arg(required_unless_present = "index")]
+        filename: Option<String>,
+
+        /// Keyword to search for. With a composite key, either the fields joined by the separator
+        /// or one argument per field.
+        #[arg(required_unless_present_any = ["queries", "index", "prefix", "range"])]
+        keyword: Vec<String>,
 
-        /// Keywords to search for
-        keyword: String,
+        /// File with one keyword per line to search in a single run ("-" to read from stdin).
+        /// Results are printed in query order, keywords not found are reported to stderr.
+        #[arg(long, conflicts_with = "keyword")]
+        queries: Option<String>,
+
+        /// Print every record whose key starts with PREFIX, in key order.
+        /// Requires an index built with --ordered.
+        #[arg(long, conflicts_with_all = ["keyword", "queries", "range"])]
+        prefix: Option<String>,
+
+        /// Print every record whose key is between LOW and HIGH, both included, in key order,
+        /// e.g. --range 1000..2000. Either bound can be left out. Requires an index built with --ordered.
+        #[arg(long, value_name = "LOW..HIGH", conflicts_with_all = ["keyword", "queries"])]
+        range: Option<String>,
 
         /// Print all matching lines when duplicates exist.
         /// Note: this tool is not particularly optimized for dataset with many duplicates.
         #[arg(long)]
         print_duplicates: bool,
+
+        /// Print the number of matching lines instead of the lines themselves
+        #[arg(long)]
+        count: bool,
+
+        /// Print the header line of the file above results, if it was indexed with --has-header. Raw format only
+        #[arg(long, conflicts_with = "count")]
+        with_header: bool,
+
+        /// Output format: "raw" prints the records, "json" one JSON object per line and "tsv" one line per match,
+        /// with the query, whether it was found, the key, the byte offset and the record. Misses are printed too.
+        #[arg(long, value_enum, default_value = "raw", conflicts_with = "count")]
+        format: FormatArg,
+
+        /// Print only these columns of tabular records (0-based), e.g. --fields 0,3,5
+        #[arg(long, value_delimiter = ',')]
+        fields: Vec<usize>,
+
+        /// Name of the index to use, as given when indexing
+        #[arg(long)]
+        index_name: Option<String>,
+
+        /// Path of the index file to use, instead of <filename>.index
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["index_dir", "index_name"])]
+        index: Option<String>,
+
+        /// Directory of the index, if not the directory of the input file
+        #[arg(long, value_name = "DIR")]
+        index_dir: Option<String>,
+    },
+
+    /// Check that the index of a file is up to date
+    Verify {
+        /// Indexed file to check. With --index, defaults to the file recorded in the index.
+        #[arg(required_unless_present = "index")]
+        filename: Option<String>,
+
+        /// Number of hashmap slots to check, spread over the whole hashmap.
+        /// Every record stored in a checked slot is read back from the file.
+        #[arg(long, default_value = "1000")]
+        samples: u64,
+
+        /// Name of the index to use, as given when indexing
+        #[arg(long)]
+        index_name: Option<String>,
+
+        /// Path of the index file to use, instead of <filename>.index
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["index_dir", "index_name"])]
+        index: Option<String>,
+
+        /// Directory of the index, if not the directory of the input file
+        #[arg(long, value_name = "DIR")]
+        index_dir: Option<String>,
+    },
+
+    /// Add the records appended to a file since it was indexed to its index, without reading the rest of the file.
+    /// Uncompressed files and indexes only.
+    Update {
+        /// Indexed file. With --index, defaults to the file recorded in the index.
+        #[arg(required_unless_present = "index")]
+        filename: Option<String>,
+
+        /// Name of the index to update, as given when indexing
+        #[arg(long)]
+        index_name: Option<String>,
+
+        /// Path of the index file to update, instead of <filename>.index
+        #[arg(long, value_name = "PATH", conflicts_with_all = ["index_dir", "index_name"])]
+        index: Option<String>,
+
+        /// Directory of the index, if not the directory of the input file
+        #[arg(long, value_name = "DIR")]
+        index_dir: Option<String>,
+    },
+
+    /// Recompress a gzip-compressed, zstd-compressed or uncompressed file to BGZF, the gzip variant that FastSeek can index
+    Bgzip {
+        /// File to recompress
+        filename: String,
+
+        /// BGZF file to write. Defaults to <filename> itself if it ends in .gz, replaced once converted,
+        /// and to <filename>.gz otherwise.
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<String>,
+
+        /// Also write the block index of the BGZF file to <output>.gzi, as `bgzip -i` does
+        #[arg(long)]
+        gzi: bool,
+
+        #[command(subcommand)]
+        build_index: Option<BgzipIndex>,
+    },
+
+    /// Recompress a zstd-compressed, gzip-compressed or uncompressed file to the zstd seekable format, which FastSeek can index
+    ZstdSeekable {
+        /// File to recompress
+        filename: String,
+
+        /// Seekable zstd file to write. Defaults to <filename> itself if it ends in .zst, replaced once converted,
+        /// and to <filename>.zst otherwise.
+        #[arg(long, short, value_name = "PATH")]
+        output: Option<String>,
+
+        /// MiB of uncompressed data per frame. Searches decompress a whole frame: smaller frames make them faster,
+        /// and compress worse.
+        #[arg(long, default_value_t = 1, value_name = "MIB")]
+        frame_size: usize,
     },
 
     Test {
 
     }
 }
+
+//Indexes built by `bgzip` while converting, with the options of the index commands
+#[derive(Subcommand)]
+pub enum BgzipIndex {
+    /// Also index the BGZF file as a tabular file, as `index-tabular` does. Records are counted while converting,
+    /// which saves a read of the file compared to indexing it afterwards.
+    #[command(name = "index-tabular")]
+    Tabular {
+        #[command(flatten)]
+        key: TabularArgs,
+
+        #[command(flatten)]
+        options: IndexOptions,
+    },
+
+    /// Also index the BGZF file as a multi-fasta file, as `index-fasta` does
+    #[command(name = "index-fasta")]
+    Fasta {
+        #[command(flatten)]
+        key: SequenceArgs,
+
+        #[command(flatten)]
+        options: IndexOptions,
+    },
+
+    /// Also index the BGZF file as a fastq file, as `index-fastq` does
+    #[command(name = "index-fastq")]
+    Fastq {
+        #[command(flatten)]
+        key: SequenceArgs,
+
+        #[command(flatten)]
+        options: IndexOptions,
+    },
+}
+
+//Key of the index of a tabular file
+#[derive(Args)]
+pub struct TabularArgs {
+    /// Column number to index (0-based). Several comma-separated columns, e.g. 0,3,
+    /// index a composite key made of their fields joined by the separator.
+    #[arg(short = 'c', long, required_unless_present = "column_name", value_delimiter = ',')]
+    pub column: Vec<usize>,
+
+    /// Column to index by its name in the header line, instead of --column. Several comma-separated names
+    /// index a composite key, as with --column.
+    #[arg(long, requires = "has_header", conflicts_with = "column", value_delimiter = ',')]
+    pub column_name: Vec<String>,
+
+    /// The first line of the file (after comments) is a header line: it is not indexed,
+    /// and `search --with-header` prints it above results
+    #[arg(long)]
+    pub has_header: bool,
+
+    /// Skip lines starting with this character, e.g. --comment-char '#'
+    #[arg(long)]
+    pub comment_char: Option<char>,
+
+    /// Field separator, of one or more characters, e.g. '||'
+    #[arg(long, short = 's', default_value = "\t")]
+    pub separator: String,
+
+    /// Split fields on any run of spaces and tabs instead of the separator, as awk does.
+    /// Leading and trailing blanks are ignored.
+    #[arg(long, conflicts_with_all = ["separator", "quote"])]
+    pub whitespace: bool,
+
+    /// Parse the file as csv (RFC 4180), with fields optionally enclosed in this quote character, e.g. --quote '"'.
+    /// Quoted fields may contain the separator, escaped quotes ("") and line breaks.
+    #[arg(long)]
+    pub quote: Option<char>,
+
+    /// Also build the index NAME, keyed by KEY, in the same pass over the file. Can be repeated.
+    /// KEY is a column or comma-separated list of columns, by number or by name, e.g. --also by_name=2
+    #[arg(long, value_name = "NAME=KEY", conflicts_with = "index")]
+    pub also: Vec<String>,
+}
+
+//Key of the index of a multi-fasta or fastq file
+#[derive(Args)]
+pub struct SequenceArgs {
+    /// If set, index by raw sequence, otherwise index by sequence-id
+    #[arg(long)]
+    pub by_sequence: bool,
+
+    /// Index by the first whitespace-separated token of the header, without the leading sigil,
+    /// instead of the whole header line. Searched keys are normalised the same way.
+    #[arg(long, conflicts_with = "by_sequence")]
+    pub id_token: bool,
+
+    /// With --id-token, index by this token of the header instead of the first one (0-based)
+    #[arg(long, requires = "id_token", default_value = "0")]
+    pub token: usize,
+
+    /// Also build the index NAME, keyed by KEY, in the same pass over the file. Can be repeated.
+    /// KEY is "id", "sequence" or "token:N" (Nth token of the header), e.g. --also by_sequence=sequence
+    #[arg(long, value_name = "NAME=KEY", conflicts_with = "index")]
+    pub also: Vec<String>,
+}
+
+//Options of every index command
+#[derive(Args)]
+pub struct IndexOptions {
+    /// Pre-allocated hashmap size (defaults to file line count)
+    /// NOTE: specifying this value instead of using the default value will speed up the indexing process.
+    /// It is recommended to set this value to a value similar to the number of lines in the original file.
+    #[arg(long, default_value = "0")]
+    pub hashmap_size: u64,
+
+    /// Max size of in-memory hashmap, number of entries. Each entry takes 8 bytes.
+    /// If the hashmap is larger than the allowed in-memory hashmap, input file will be iterated multiple times.
+    #[arg(long, default_value = "2000000000")]
+    pub in_memory_map_size: u64,
+
+    /// Store entries sharing a hashmap slot as contiguous blocks instead of linked lists.
+    /// Recommended for datasets with many duplicates: searching is faster and the index is smaller,
+    /// but colliding entries are sorted at the end of each iteration over the file, in temporary files if large.
+    #[arg(long)]
+    pub contiguous_blocks: bool,
+
+    /// Compress the index: "blocks" compresses the collision blocks and keeps the hashmap plain for random access,
+    /// "full" also compresses the hashmap, which is then loaded entirely in memory when searching.
+    #[arg(long, value_enum, default_value = "none")]
+    pub compress_index: CompressionArg,
+
+    /// Number of threads extracting and hashing keys. With more than one,
+    /// reading and writing the index run on two additional threads. The index is the same.
+    #[arg(long, default_value = "1")]
+    pub threads: usize,
+
+    /// Remove leading and trailing whitespace from keys, and from searched keys
+    #[arg(long)]
+    pub trim: bool,
+
+    /// Ignore the case of keys, lowercasing ASCII letters or, with "unicode", every letter
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "ascii", value_name = "CASE")]
+    pub ignore_case: Option<CaseArg>,
+
+    /// Remove a pair of quotes, " or ', enclosing keys and searched keys
+    #[arg(long)]
+    pub strip_quotes: bool,
+
+    /// Also write an ordered index of the keys to <index>.sorted, for `search --prefix` and `search --range`.
+    /// Keys are compared as strings or, with "numeric", as numbers.
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "lexicographic", value_name = "ORDER")]
+    pub ordered: Option<OrderArg>,
+
+    /// Name of the index, written to <filename>.<name>.index instead of <filename>.index,
+    /// so that a file can have several indexes
+    #[arg(long)]
+    pub index_name: Option<String>,
+
+    /// Path of the index file, instead of <filename>.index
+    #[arg(long, value_name = "PATH", conflicts_with_all = ["index_dir", "index_name"])]
+    pub index: Option<String>,
+
+    /// Directory where indexes are written, instead of the directory of the input file
+    #[arg(long, value_name = "DIR")]
+    pub index_dir: Option<String>,
+}
+
+//Options of index commands for compressed input files
+#[derive(Args)]
+pub struct CompressedFileArgs {
+    /// Also write the block index of the BGZF-compressed file to <filename>.gzi, as `bgzip -i` does
+    #[arg(long)]
+    pub gzi: bool,
+
+    /// MiB of uncompressed data between two checkpoints of a plain (not BGZF) gzip-compressed file,
+    /// written to <filename>.gzc: searches decompress from the last checkpoint before a record
+    #[arg(long, default_value_t = 4, value_name = "MIB")]
+    pub checkpoint_interval: u64,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CompressionArg {
+    None,
+    Blocks,
+    Full,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum FormatArg {
+    Raw,
+    Json,
+    Tsv,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum OrderArg {
+    Lexicographic,
+    Numeric,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum CaseArg {
+    Ascii,
+    Unicode,
+}
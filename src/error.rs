@@ -0,0 +1,58 @@
+use std::fmt;
+use std::io;
+
+/// Errors returned by FastSeek.
+/// A key that is not found is not an error: searches return an empty result instead.
+#[derive(Debug)]
+pub enum FastSeekError {
+    /// Error reading or writing a file
+    Io(io::Error),
+    /// The index file is truncated or contains invalid data
+    CorruptIndex(String),
+    /// The index was created with a version of the format this build cannot read
+    UnsupportedVersion(u8),
+    /// A record of the input file could not be parsed. Lines are counted from 1.
+    MalformedRecord { line: u64, reason: String },
+    /// A line of a tabular file does not have the indexed column. Lines are counted from 1.
+    MissingColumn { line: u64, column: usize },
+    /// Invalid indexing or search options
+    InvalidArgument(String),
+    /// The indexed file changed after the index was built, the index must be rebuilt
+    StaleIndex(String),
+}
+
+pub type Result<T> = std::result::Result<T, FastSeekError>;
+
+impl fmt::Display for FastSeekError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FastSeekError::Io(error) => write!(f, "I/O error: {}", error),
+            FastSeekError::CorruptIndex(reason) => write!(f, "corrupt index: {}", reason),
+            FastSeekError::UnsupportedVersion(version) => write!(f, "unsupported index version: {}", version),
+            FastSeekError::MalformedRecord { line, reason } => write!(f, "malformed record at line {}: {}", line, reason),
+            FastSeekError::MissingColumn { line, column } => write!(f, "line {} has no column {} (columns are 0-based)", line, column),
+            FastSeekError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            FastSeekError::StaleIndex(reason) => write!(f, "index is out of date, rebuild it: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for FastSeekError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FastSeekError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FastSeekError {
+    fn from(error: io::Error) -> FastSeekError {
+        return FastSeekError::Io(error);
+    }
+}
+
+//Adds the file name to the message of an I/O error
+pub fn with_filename(error: io::Error, action: &str, filename: &str) -> FastSeekError {
+    return FastSeekError::Io(io::Error::new(error.kind(), format!("couldn't {} {}: {}", action, filename, error)));
+}
@@ -1,276 +1,484 @@
 use std::io::Read;
 use std::fs::File;
 use std::path::Path;
-use bgzip::BGZFReader;
 use std::io::{self, BufRead, Seek};
+use crate::bgzf::{file_compression, BgzfReader, BlockIndex, FileCompression};
+use crate::error::{with_filename, FastSeekError, Result};
+use crate::gzip_checkpoints::GzipCheckpointReader;
+use crate::zstd_seekable::ZstdSeekableReader;
+use crate::key_extractor::{KeyExtractor, KeyKind, KeyNormalization, Separator};
 
 
 pub trait FileReader{
-    fn seek(&mut self, pos: u64) -> ();
-    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), io::Error>;
-    fn read_line(&mut self, buffer: &mut String) -> Result<usize, io::Error>;
-    fn num_lines(&mut self) -> u64;
+    //Offsets are the ones returned by position: byte offsets in plain files, virtual offsets in BGZF files
+    fn seek(&mut self, pos: u64) -> Result<()>;
+    //Offset of the next byte read
+    fn position(&mut self) -> Result<u64>;
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()>;
+    fn read_line(&mut self, buffer: &mut String) -> Result<usize>;
+    fn num_lines(&mut self) -> Result<u64>;
+    //From now on, offsets are positions in the uncompressed data, as stored by indexes of
+    //compressed files built before virtual offsets were
+    fn use_uncompressed_offsets(&mut self, _filename: &str) -> Result<()>{
+        return Ok(());
+    }
 }
 
 pub struct StandardFileReader{
-    buf_reader: io::BufReader<File>
+    buf_reader: io::BufReader<File>,
+    position: u64,
 }
 impl StandardFileReader{
-    pub fn new(filename: &String) -> StandardFileReader{
-        let path = Path::new(&filename);
-        let file = match File::open(&path) {
-            Err(why) => panic!("couldn't open {}: {}", filename, why),
-            Ok(file) => file,
-        };
-        return StandardFileReader{buf_reader: io::BufReader::new(file)};
+    pub fn new(filename: &str) -> Result<StandardFileReader>{
+        let path = Path::new(filename);
+        let file = File::open(path).map_err(|e| with_filename(e, "open", filename))?;
+        return Ok(StandardFileReader{buf_reader: io::BufReader::new(file), position: 0});
     }
 }
 
 impl FileReader for StandardFileReader{
-    fn seek(&mut self, pos: u64) -> () {
-        let _ = self.buf_reader.seek(io::SeekFrom::Start(pos));
-    } 
-    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), io::Error> {
-        self.buf_reader.read_exact(buffer)
+    fn seek(&mut self, pos: u64) -> Result<()> {
+        self.buf_reader.seek(io::SeekFrom::Start(pos))?;
+        self.position = pos;
+        return Ok(());
+    }
+    fn position(&mut self) -> Result<u64> {
+        return Ok(self.position);
+    }
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.buf_reader.read_exact(buffer)?;
+        self.position += buffer.len() as u64;
+        return Ok(());
     }
-    fn read_line(&mut self, buffer: &mut String) -> Result<usize, io::Error> {
-        self.buf_reader.read_line(buffer)
+    fn read_line(&mut self, buffer: &mut String) -> Result<usize> {
+        let bytes_read = self.buf_reader.read_line(buffer)?;
+        self.position += bytes_read as u64;
+        return Ok(bytes_read);
     }
-    fn num_lines(&mut self) -> u64 {
+    fn num_lines(&mut self) -> Result<u64> {
         let mut number_lines = 0;
         let mut buffer = [0; 8192];
-        while let Ok(n) = self.buf_reader.read(&mut buffer) {
+        loop {
+            let n = self.buf_reader.read(&mut buffer)?;
             if n == 0 { break; }
             number_lines += buffer[..n].iter()
                 .filter(|&&byte| byte == b'\n')
                 .count();
         }
-        self.seek(0);
-        return number_lines as u64;
+        self.seek(0)?;
+        return Ok(number_lines as u64);
     }
 }
 
 pub struct GzFileReader{
-    bgzf_reader: BGZFReader<io::BufReader<File>>
+    bgzf_reader: BgzfReader,
+    //Set if offsets are positions in the uncompressed data, translated to virtual offsets by it
+    block_index: Option<BlockIndex>,
 }
 impl GzFileReader{
-    pub fn new(filename: &String) -> GzFileReader {
-        let file = File::open(&filename).unwrap();
-        let buf_reader = io::BufReader::new(file);
-        return GzFileReader{bgzf_reader: BGZFReader::new(buf_reader).unwrap()};
+    pub fn new(filename: &str) -> Result<GzFileReader> {
+        return Ok(GzFileReader{bgzf_reader: BgzfReader::new(filename)?, block_index: None});
     }
 }
 
 impl FileReader for GzFileReader{
-    fn seek(&mut self, pos: u64) -> () {
-        self.bgzf_reader.bgzf_seek(pos).unwrap();
-    } 
-    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<(), io::Error> {
-        self.bgzf_reader.read_exact(buffer)
+    fn seek(&mut self, pos: u64) -> Result<()> {
+        let pos = match &self.block_index {
+            Some(block_index) => block_index.virtual_offset(pos),
+            None => pos,
+        };
+        return self.bgzf_reader.seek_virtual(pos);
+    }
+    fn position(&mut self) -> Result<u64> {
+        let pos = self.bgzf_reader.virtual_position()?;
+        return Ok(match &self.block_index {
+            Some(block_index) => block_index.uncompressed_position(pos),
+            None => pos,
+        });
+    }
+    fn use_uncompressed_offsets(&mut self, filename: &str) -> Result<()> {
+        self.block_index = Some(BlockIndex::of_file(filename)?);
+        return Ok(());
+    }
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.bgzf_reader.read_exact(buffer)?;
+        return Ok(());
     }
-    fn read_line(&mut self, buffer: &mut String) -> Result<usize, io::Error> {
-        self.bgzf_reader.read_line(buffer)
+    fn read_line(&mut self, buffer: &mut String) -> Result<usize> {
+        return Ok(self.bgzf_reader.read_line(buffer)?);
     }
-    fn num_lines(&mut self) -> u64 {
+    fn num_lines(&mut self) -> Result<u64> {
         let mut number_lines = 0;
         let mut buffer = [0; 8192];
-        while let Ok(n) = self.bgzf_reader.read(&mut buffer) {
+        loop {
+            let n = self.bgzf_reader.read(&mut buffer)?;
             if n == 0 { break; }
             number_lines += buffer[..n].iter()
                 .filter(|&&byte| byte == b'\n')
                 .count();
         }
-        self.seek(0);
-        return number_lines as u64;
+        self.seek(0)?;
+        return Ok(number_lines as u64);
     }
 }
 
+//Open filename with the reader matching its compression, told by its content whatever its extension
+pub fn open_file_reader(filename: &str) -> Result<Box<dyn FileReader>>{
+    return Ok(match file_compression(filename)? {
+        FileCompression::Bgzf => Box::new(GzFileReader::new(filename)?),
+        FileCompression::Gzip => Box::new(GzipCheckpointReader::open(filename)?),
+        FileCompression::Zstd | FileCompression::SeekableZstd => Box::new(ZstdSeekableReader::new(filename)?),
+        FileCompression::None => Box::new(StandardFileReader::new(filename)?),
+    });
+}
+
 
 pub trait InputReader{
-    //Returns offset of the entry and the indexing keyword
-    fn get_entry(&mut self, buffer: &mut String) -> usize;
+    //Reads the next record to the buffer and returns its offset. None at end of file
+    fn read_record(&mut self, buffer: &mut String) -> Result<Option<u64>>;
+    //Line of the input file where the last record read starts
+    fn line(&self) -> u64;
+    //Extracts the indexing keyword from the records
+    fn key_extractor(&self) -> &KeyExtractor;
+    fn seek(&mut self, offset: u64) -> Result<()>;
+    //Offset of the next record read, or of the end of the file once it is read
+    fn position(&mut self) -> Result<u64>;
+    fn reset(&mut self) -> Result<()>;
+    fn num_entries(&mut self) -> Result<u64>;
+    //Index type, separator and columns stored in the header
+    fn get_types_for_header(&self) -> (u8, String, Vec<u32>);
     //Test entry with value, returns true if found and set the entire entry to the buffer if found
     //if test fails, buffer is left dirty - caller must clear it
-    fn test_and_return_entry(&mut self, offset: u64, value: &String, buffer: &mut String) -> bool;
-    fn reset(&mut self);
-    fn num_entries(&mut self) -> u64;
-    fn get_types_for_header(&self) -> (u8, u8, u8);
+    fn test_and_return_entry(&mut self, offset: u64, value: &str, buffer: &mut String) -> Result<bool>{
+        self.seek(offset)?;
+        match self.read_record(buffer) {
+            Ok(Some(_)) => {}
+            //Offset not pointing to a valid record: not a match
+            Ok(None) | Err(FastSeekError::MalformedRecord{..}) => return Ok(false),
+            Err(e) => return Err(e),
+        }
+        let key_extractor = self.key_extractor();
+        return Ok(match key_extractor.extract(buffer, 0) {
+            Ok(key) => key_extractor.key_matches(&key, value),
+            Err(_) => false,
+        });
+    }
 }
 
-pub struct TabularInputReader<'a>{
-    file_reader: &'a mut dyn FileReader,
-    offset: usize,
-    separator: &'a str,
-    column: usize
+//Layout of the lines of a tabular file
+#[derive(Clone)]
+pub struct TabularOptions{
+    pub separator: Separator,
+    //Fields may be enclosed in this character (csv, RFC 4180): records then span as many lines as their quoted fields
+    pub quote: Option<char>,
+    //The first line that isn't a comment is a header line, not a record
+    pub has_header: bool,
+    //Lines starting with this character are skipped
+    pub comment_char: Option<char>,
 }
-impl<'a> TabularInputReader<'a>{
-    pub fn new(file_reader: &'a mut dyn FileReader, separator: &'a str, column: usize) -> TabularInputReader<'a>{
-        return TabularInputReader{file_reader:file_reader, offset:0, separator:separator, column:column};
+
+pub struct TabularInputReader{
+    file_reader: Box<dyn FileReader>,
+    line: u64,
+    //First line of the record, when the record starts
+    record_line: u64,
+    //Header line not skipped yet: reading from the start of the file
+    skip_header: bool,
+    options: TabularOptions,
+    columns: Vec<usize>,
+    key_extractor: KeyExtractor
+}
+impl TabularInputReader{
+    //With more than one column, the key is the fields joined by the separator.
+    //normalization: applied to the keys, field by field
+    pub fn new(file_reader: Box<dyn FileReader>, options: TabularOptions, columns: &[usize], normalization: KeyNormalization) -> TabularInputReader{
+        let key_kind = match options.quote {
+            Some(quote) => KeyKind::QuotedColumns{separator: options.separator.as_str().to_string(), columns: columns.to_vec(), quote},
+            None => KeyKind::Columns{separator: options.separator.clone(), columns: columns.to_vec()},
+        };
+        let key_extractor = KeyExtractor::new(key_kind, normalization);
+        return TabularInputReader{
+            file_reader:file_reader, line:0, record_line:0, skip_header: options.has_header, options, columns:columns.to_vec(), key_extractor
+        };
+    }
+
+    //First record of the file, comments excluded, without its line terminator: the header line if has_header is set.
+    //The reader is left at the start of the file.
+    pub fn header_line(&mut self) -> Result<Option<String>>{
+        self.reset()?;
+        self.skip_header = false;
+        let mut line = String::new();
+        let found = self.read_record(&mut line)?.is_some();
+        self.reset()?;
+        if !found {
+            return Ok(None);
+        }
+        let line = line.strip_suffix('\n').unwrap_or(&line);
+        return Ok(Some(line.strip_suffix('\r').unwrap_or(line).to_string()));
     }
 }
 
-impl InputReader for TabularInputReader<'_>{
-    fn get_types_for_header(&self) -> (u8, u8, u8) {
-        return (0, self.separator.to_string().as_bytes()[0], self.column as u8);
-    }
-    fn get_entry(&mut self, buffer: &mut String) -> usize{
-        //read entire line - take advantage of user-provided buffer to store it
-        let return_value = self.offset;
-        let bytes_read = self.file_reader.read_line(buffer).unwrap();
-        if bytes_read == 0{
-            return 0xFFFFFFFFFFFFFFFF;
+impl InputReader for TabularInputReader{
+    fn get_types_for_header(&self) -> (u8, String, Vec<u32>) {
+        let separator = match &self.options.separator {
+            Separator::Text(separator) => separator.clone(),
+            Separator::Whitespace => String::new(),
         };
-        self.offset = self.offset + bytes_read;
-        //Split and return only the column of interest
-        let mut parts = (& buffer).split(&self.separator);
-        let value = parts.nth(self.column).unwrap().to_string();
-        buffer.clear();
-        buffer.push_str(&value);
-        return return_value;
-    }
-    fn reset(&mut self){
-        self.file_reader.seek(0);
-        self.offset = 0;
-    }
-    fn num_entries(&mut self) -> u64{
-        return self.file_reader.num_lines();
+        return (0, separator, self.columns.iter().map(|&column| column as u32).collect());
+    }
+    fn read_record(&mut self, buffer: &mut String) -> Result<Option<u64>>{
+        loop{
+            let return_value = self.file_reader.position()?;
+            let bytes_read = self.file_reader.read_line(buffer)?;
+            if bytes_read == 0{
+                return Ok(None);
+            };
+            self.line += 1;
+            self.record_line = self.line;
+            if self.options.comment_char.is_some_and(|comment_char| buffer.starts_with(comment_char)) {
+                buffer.clear();
+                continue;
+            }
+            //Quotes come in pairs, escaped ones too: while their number is odd, a quoted field goes on in the next line
+            if let Some(quote) = self.options.quote {
+                let mut quotes = buffer.matches(quote).count();
+                while quotes % 2 == 1 {
+                    let line_start = buffer.len();
+                    let bytes_read = self.file_reader.read_line(buffer)?;
+                    if bytes_read == 0 {
+                        return Err(FastSeekError::MalformedRecord{line: self.record_line, reason: "unterminated quoted field".to_string()});
+                    }
+                    self.line += 1;
+                    quotes += buffer[line_start..].matches(quote).count();
+                }
+            }
+            if self.skip_header {
+                self.skip_header = false;
+                buffer.clear();
+                continue;
+            }
+            return Ok(Some(return_value));
+        }
+    }
+    fn line(&self) -> u64{
+        return self.record_line;
+    }
+    fn key_extractor(&self) -> &KeyExtractor{
+        return &self.key_extractor;
+    }
+    fn seek(&mut self, offset: u64) -> Result<()>{
+        //Offsets point to records, never to the header line
+        self.skip_header = false;
+        return self.file_reader.seek(offset);
+    }
+    fn position(&mut self) -> Result<u64>{
+        return self.file_reader.position();
     }
-    fn test_and_return_entry(&mut self, offset: u64, value: &String, buffer: &mut String) -> bool{
-        self.file_reader.seek(offset);
-        self.file_reader.read_line(buffer).unwrap();
-        let mut parts = buffer.split(self.separator);
-        let key = parts.nth(self.column).unwrap().trim();
-        return key == value;
+    fn reset(&mut self) -> Result<()>{
+        self.file_reader.seek(0)?;
+        self.line = 0;
+        self.record_line = 0;
+        self.skip_header = self.options.has_header;
+        return Ok(());
+    }
+    //Lines, as an estimate when records span several lines
+    fn num_entries(&mut self) -> Result<u64>{
+        return self.file_reader.num_lines();
     }
 }
 
 
-pub struct MultiFastaInputReader<'a>{
-    file_reader: &'a mut dyn FileReader,
+pub struct MultiFastaInputReader{
+    file_reader: Box<dyn FileReader>,
     is_indexing_sequence: bool,
-    offset: usize
+    line: u64,
+    //First line of the record, when the record starts
+    record_line: u64,
+    //Header line of the next record, read while looking for the end of the previous one, and its offset
+    next_header: String,
+    next_header_offset: u64,
+    key_extractor: KeyExtractor
 }
-impl<'a> MultiFastaInputReader<'a>{
-    pub fn new(file_reader: &'a mut dyn FileReader, is_indexing_sequence: bool) -> MultiFastaInputReader<'a>{
-        return MultiFastaInputReader{file_reader:file_reader, is_indexing_sequence:is_indexing_sequence, offset:0};
+impl MultiFastaInputReader{
+    //id_token: key records by this token of the header line instead of the whole line
+    pub fn new(file_reader: Box<dyn FileReader>, is_indexing_sequence: bool, id_token: Option<usize>, normalization: KeyNormalization) -> MultiFastaInputReader{
+        let key_kind = match (is_indexing_sequence, id_token) {
+            (true, _) => KeyKind::Sequence,
+            (false, Some(n)) => KeyKind::IdToken(n),
+            (false, None) => KeyKind::Line(0),
+        };
+        let key_extractor = KeyExtractor::new(key_kind, normalization);
+        return MultiFastaInputReader{
+            file_reader:file_reader, is_indexing_sequence:is_indexing_sequence, line:0, record_line:0,
+            next_header: String::new(), next_header_offset: 0, key_extractor
+        };
     }
 }
 
-impl InputReader for MultiFastaInputReader<'_>{
-    fn get_types_for_header(&self) -> (u8, u8, u8) {
+impl InputReader for MultiFastaInputReader{
+    fn get_types_for_header(&self) -> (u8, String, Vec<u32>) {
         return (
             match self.is_indexing_sequence {true => 2, false => 1},
-            0, 
-            0
+            String::new(),
+            vec![0]
         );
     }
-    fn get_entry(&mut self, buffer: &mut String) -> usize{
-        //read entire line - take advantage of user-provided buffer to store it
-        let return_value = self.offset;
-        let mut throwaway = String::new();
-        let mut bytes_read = 0;
-        if !self.is_indexing_sequence{
-            bytes_read += self.file_reader.read_line(buffer).unwrap();
-            bytes_read += self.file_reader.read_line(&mut throwaway).unwrap();
+    //A record is its header line and every line up to the next header line.
+    //Sequences may be wrapped on any number of lines.
+    fn read_record(&mut self, buffer: &mut String) -> Result<Option<u64>>{
+        let mut header_bytes = self.next_header.len();
+        let mut return_value = self.next_header_offset;
+        if header_bytes > 0 {
+            buffer.push_str(&self.next_header);
+            self.next_header.clear();
         } else {
-            bytes_read += self.file_reader.read_line(&mut throwaway).unwrap();
-            bytes_read += self.file_reader.read_line(buffer).unwrap();
+            return_value = self.file_reader.position()?;
+            header_bytes = self.file_reader.read_line(buffer)?;
+            //Skip blank lines between records
+            while header_bytes > 0 && buffer.trim().is_empty(){
+                self.line += 1;
+                buffer.clear();
+                return_value = self.file_reader.position()?;
+                header_bytes = self.file_reader.read_line(buffer)?;
+            }
         }
-        if bytes_read == 0{
-            return 0xFFFFFFFFFFFFFFFF;
+        if header_bytes == 0{
+            return Ok(None);
         };
-        self.offset = self.offset + bytes_read;
-        return return_value;
+        self.line += 1;
+        self.record_line = self.line;
+        if !buffer.starts_with('>'){
+            return Err(FastSeekError::MalformedRecord{line: self.line, reason: "expected a header line starting with '>'".to_string()});
+        }
+        loop{
+            let line_offset = self.file_reader.position()?;
+            let bytes_read = self.file_reader.read_line(&mut self.next_header)?;
+            if bytes_read == 0 {
+                break;
+            }
+            if self.next_header.starts_with('>') {
+                self.next_header_offset = line_offset;
+                break;
+            }
+            self.line += 1;
+            buffer.push_str(&self.next_header);
+            self.next_header.clear();
+        }
+        return Ok(Some(return_value));
     }
-    fn reset(&mut self){
-        self.file_reader.seek(0);
-        self.offset = 0;
+    fn line(&self) -> u64{
+        return self.record_line;
     }
-    fn num_entries(&mut self) -> u64{
-        return self.file_reader.num_lines()/2;
+    fn key_extractor(&self) -> &KeyExtractor{
+        return &self.key_extractor;
     }
-    fn test_and_return_entry(&mut self, offset: u64, value: &String, buffer: &mut String) -> bool{
-        self.file_reader.seek(offset);
-        _ = self.file_reader.read_line(buffer).unwrap();
-        if !self.is_indexing_sequence && buffer.trim() != value {
-            return false;
-        }
-        let header_size = buffer.len();
-        _ = self.file_reader.read_line(buffer).unwrap();
-        if self.is_indexing_sequence{
-            if &buffer[header_size..].trim() != value {
-                return false;
+    fn seek(&mut self, offset: u64) -> Result<()>{
+        self.next_header.clear();
+        return self.file_reader.seek(offset);
+    }
+    fn position(&mut self) -> Result<u64>{
+        //The header line of the next record may be read already
+        return match self.next_header.is_empty() {
+            true => self.file_reader.position(),
+            false => Ok(self.next_header_offset),
+        };
+    }
+    fn reset(&mut self) -> Result<()>{
+        self.seek(0)?;
+        self.line = 0;
+        return Ok(());
+    }
+    fn num_entries(&mut self) -> Result<u64>{
+        let mut records = 0;
+        let mut line = String::new();
+        while self.file_reader.read_line(&mut line)? > 0 {
+            if line.starts_with('>') {
+                records += 1;
             }
+            line.clear();
         }
-        return true;
+        self.file_reader.seek(0)?;
+        return Ok(records);
     }
 }
 
 
 
-pub struct FastqInputReader<'a>{
-    file_reader: &'a mut dyn FileReader,
+pub struct FastqInputReader{
+    file_reader: Box<dyn FileReader>,
     is_indexing_sequence: bool,
-    offset: usize
+    line: u64,
+    key_extractor: KeyExtractor
 }
-impl<'a> FastqInputReader<'a>{
-    pub fn new(file_reader: &'a mut dyn FileReader, is_indexing_sequence: bool) -> FastqInputReader<'a>{
-        return FastqInputReader{file_reader:file_reader, is_indexing_sequence:is_indexing_sequence, offset:0};
+impl FastqInputReader{
+    //id_token: key records by this token of the header line instead of the whole line
+    pub fn new(file_reader: Box<dyn FileReader>, is_indexing_sequence: bool, id_token: Option<usize>, normalization: KeyNormalization) -> FastqInputReader{
+        let key_kind = match (is_indexing_sequence, id_token) {
+            (true, _) => KeyKind::Line(1),
+            (false, Some(n)) => KeyKind::IdToken(n),
+            (false, None) => KeyKind::Line(0),
+        };
+        let key_extractor = KeyExtractor::new(key_kind, normalization);
+        return FastqInputReader{file_reader:file_reader, is_indexing_sequence:is_indexing_sequence, line:0, key_extractor};
     }
 }
 
-impl InputReader for FastqInputReader<'_>{
-    fn get_types_for_header(&self) -> (u8, u8, u8) {
+impl InputReader for FastqInputReader{
+    fn get_types_for_header(&self) -> (u8, String, Vec<u32>) {
         return (
             match self.is_indexing_sequence {true => 4, false => 3},
-            0, 
-            0
+            String::new(),
+            vec![0]
         );
     }
-    fn get_entry(&mut self, buffer: &mut String) -> usize{
-        //read entire line - take advantage of user-provided buffer to store it
-        let return_value = self.offset;
-        let mut throwaway = String::new();
-        let mut bytes_read = 0;
-        if !self.is_indexing_sequence{
-            bytes_read += self.file_reader.read_line(buffer).unwrap();
-            bytes_read += self.file_reader.read_line(&mut throwaway).unwrap();
-            bytes_read += self.file_reader.read_line(&mut throwaway).unwrap();
-            bytes_read += self.file_reader.read_line(&mut throwaway).unwrap();
-        } else {
-            bytes_read += self.file_reader.read_line(&mut throwaway).unwrap();
-            bytes_read += self.file_reader.read_line(buffer).unwrap();
-            bytes_read += self.file_reader.read_line(&mut throwaway).unwrap();
-            bytes_read += self.file_reader.read_line(&mut throwaway).unwrap();
+    fn read_record(&mut self, buffer: &mut String) -> Result<Option<u64>>{
+        //read entire record - header, sequence, separator and quality lines
+        let mut line_starts = [0; 4];
+        let mut return_value = self.file_reader.position()?;
+        for (i, line_start) in line_starts.iter_mut().enumerate() {
+            *line_start = buffer.len();
+            let mut n = self.file_reader.read_line(buffer)?;
+            //Skip blank lines between records
+            while i == 0 && n > 0 && buffer.trim().is_empty(){
+                self.line += 1;
+                buffer.clear();
+                return_value = self.file_reader.position()?;
+                n = self.file_reader.read_line(buffer)?;
+            }
+            if n == 0 {
+                if i == 0 {
+                    return Ok(None);
+                }
+                return Err(FastSeekError::MalformedRecord{line: self.line + 1, reason: "truncated record, expected 4 lines".to_string()});
+            }
         }
-        if bytes_read == 0{
-            return 0xFFFFFFFFFFFFFFFF;
-        };
-        self.offset = self.offset + bytes_read;
-        return return_value;
+        if !buffer.starts_with('@'){
+            return Err(FastSeekError::MalformedRecord{line: self.line + 1, reason: "expected a header line starting with '@'".to_string()});
+        }
+        if !buffer[line_starts[2]..].starts_with('+'){
+            return Err(FastSeekError::MalformedRecord{line: self.line + 3, reason: "expected a separator line starting with '+'".to_string()});
+        }
+        self.line += 4;
+        return Ok(Some(return_value));
     }
-    fn reset(&mut self){
-        self.file_reader.seek(0);
-        self.offset = 0;
+    fn line(&self) -> u64{
+        return self.line.saturating_sub(3);
     }
-    fn num_entries(&mut self) -> u64{
-        return self.file_reader.num_lines()/4;
+    fn key_extractor(&self) -> &KeyExtractor{
+        return &self.key_extractor;
     }
-    fn test_and_return_entry(&mut self, offset: u64, value: &String, buffer: &mut String) -> bool{
-        self.file_reader.seek(offset);
-        _ = self.file_reader.read_line(buffer).unwrap();
-        if !self.is_indexing_sequence && buffer.trim() != value {
-            return false;
-        }
-        let header_size = buffer.len();
-        _ = self.file_reader.read_line(buffer).unwrap();
-        if self.is_indexing_sequence{
-            if &buffer[header_size..].trim() != value {
-                return false;
-            }
-        }
-        _ = self.file_reader.read_line(buffer).unwrap();
-        _ = self.file_reader.read_line(buffer).unwrap();
-        return true;
+    fn seek(&mut self, offset: u64) -> Result<()>{
+        return self.file_reader.seek(offset);
+    }
+    fn position(&mut self) -> Result<u64>{
+        return self.file_reader.position();
     }
-}
\ No newline at end of file
+    fn reset(&mut self) -> Result<()>{
+        self.file_reader.seek(0)?;
+        self.line = 0;
+        return Ok(());
+    }
+    fn num_entries(&mut self) -> Result<u64>{
+        return Ok(self.file_reader.num_lines()?/4);
+    }
+}
@@ -2,18 +2,16 @@ use std::io::{Seek, Write};
 use std::fs::File;
 use std::path::Path;
 use std::io::{self};
+use crate::error::{self, with_filename};
 
 pub enum FileWriter{
     Standard(io::BufWriter<File>)
 }
 impl FileWriter{
-    pub fn get_writer(filename: String) -> FileWriter {
+    pub fn get_writer(filename: String) -> error::Result<FileWriter> {
         let path = Path::new(&filename);
-        let file = match File::create(&path) {
-            Err(why) => panic!("couldn't create {}: {}", filename, why),
-            Ok(file) => file,
-        };
-        return FileWriter::Standard(io::BufWriter::new(file));
+        let file = File::create(path).map_err(|e| with_filename(e, "create", &filename))?;
+        return Ok(FileWriter::Standard(io::BufWriter::new(file)));
     }
 
     pub fn write_all(&mut self, buffer: &[u8]) -> Result<(), io::Error> {
@@ -27,4 +25,22 @@ impl FileWriter{
             FileWriter::Standard(writer) => writer.seek(pos),
         }
     }
-}
\ No newline at end of file
+
+    pub fn flush(&mut self) -> Result<(), io::Error> {
+        match self {
+            FileWriter::Standard(writer) => writer.flush(),
+        }
+    }
+}
+
+impl Write for FileWriter{
+    fn write(&mut self, buffer: &[u8]) -> Result<usize, io::Error> {
+        match self {
+            FileWriter::Standard(writer) => writer.write(buffer),
+        }
+    }
+
+    fn flush(&mut self) -> Result<(), io::Error> {
+        FileWriter::flush(self)
+    }
+}
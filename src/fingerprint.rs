@@ -0,0 +1,95 @@
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom};
+use std::time::UNIX_EPOCH;
+use flate2::Crc;
+use crate::error::{with_filename, Result};
+
+//Bytes checksummed at the start and at the end of the file
+const FINGERPRINT_BLOCK_SIZE: u64 = 64*1024;
+pub const FINGERPRINT_SIZE: u8 = 24;
+
+//Identifies the version of the indexed file, to detect indexes left stale by a change of the file
+#[derive(Clone, PartialEq, Eq, Default)]
+pub struct Fingerprint{
+    pub size: u64,
+    //Modification time in nanoseconds since the epoch, 0 if unknown
+    pub mtime: u64,
+    pub first_block_crc: u32,
+    pub last_block_crc: u32
+}
+
+impl Fingerprint{
+    pub fn of_file(filename: &str) -> Result<Fingerprint>{
+        let size = fs::metadata(filename).map_err(|e| with_filename(e, "read", filename))?.len();
+        return Fingerprint::of_prefix(filename, size);
+    }
+
+    //Fingerprint of the first size bytes of the file, as if it ended there
+    pub fn of_prefix(filename: &str, size: u64) -> Result<Fingerprint>{
+        let metadata = fs::metadata(filename).map_err(|e| with_filename(e, "read", filename))?;
+        let mtime = match metadata.modified().map(|time| time.duration_since(UNIX_EPOCH)) {
+            Ok(Ok(duration)) => duration.as_nanos() as u64,
+            _ => 0,
+        };
+        let mut file = File::open(filename).map_err(|e| with_filename(e, "open", filename))?;
+        let first_block_crc = block_crc(&mut file, 0, size.min(FINGERPRINT_BLOCK_SIZE))?;
+        let last_block_start = size.saturating_sub(FINGERPRINT_BLOCK_SIZE);
+        let last_block_crc = block_crc(&mut file, last_block_start, size - last_block_start)?;
+        return Ok(Fingerprint{size, mtime, first_block_crc, last_block_crc});
+    }
+
+    //Returns why other, the fingerprint of the file now, doesn't match. The modification time is not compared.
+    pub fn mismatch(&self, other: &Fingerprint) -> Option<String>{
+        if self.size != other.size {
+            return Some(format!("file size changed from {} to {} bytes", self.size, other.size));
+        }
+        if self.first_block_crc != other.first_block_crc || self.last_block_crc != other.last_block_crc {
+            return Some("file content changed".to_string());
+        }
+        return None;
+    }
+
+    //Returns why filename is not the file fingerprinted with data appended to it: the blocks checksummed must be
+    //unchanged, at the same positions
+    pub fn append_mismatch(&self, filename: &str) -> Result<Option<String>>{
+        let size = fs::metadata(filename).map_err(|e| with_filename(e, "read", filename))?.len();
+        if size < self.size {
+            return Ok(Some(format!("file size went down from {} to {} bytes", self.size, size)));
+        }
+        let mut file = File::open(filename).map_err(|e| with_filename(e, "open", filename))?;
+        let first_block_crc = block_crc(&mut file, 0, self.size.min(FINGERPRINT_BLOCK_SIZE))?;
+        let last_block_start = self.size.saturating_sub(FINGERPRINT_BLOCK_SIZE);
+        let last_block_crc = block_crc(&mut file, last_block_start, self.size - last_block_start)?;
+        if first_block_crc != self.first_block_crc || last_block_crc != self.last_block_crc {
+            return Ok(Some("file content changed before its end".to_string()));
+        }
+        return Ok(None);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8>{
+        let mut bytes: Vec<u8> = Vec::with_capacity(FINGERPRINT_SIZE as usize);
+        bytes.extend_from_slice(&self.size.to_be_bytes());
+        bytes.extend_from_slice(&self.mtime.to_be_bytes());
+        bytes.extend_from_slice(&self.first_block_crc.to_be_bytes());
+        bytes.extend_from_slice(&self.last_block_crc.to_be_bytes());
+        return bytes;
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Fingerprint{
+        return Fingerprint{
+            size: u64::from_be_bytes(bytes[0..8].try_into().unwrap()),
+            mtime: u64::from_be_bytes(bytes[8..16].try_into().unwrap()),
+            first_block_crc: u32::from_be_bytes(bytes[16..20].try_into().unwrap()),
+            last_block_crc: u32::from_be_bytes(bytes[20..24].try_into().unwrap()),
+        };
+    }
+}
+
+fn block_crc(file: &mut File, start: u64, len: u64) -> Result<u32>{
+    let mut buffer = vec![0; len as usize];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut buffer)?;
+    let mut crc = Crc::new();
+    crc.update(&buffer);
+    return Ok(crc.sum());
+}
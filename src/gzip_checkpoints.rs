@@ -0,0 +1,688 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::{Compression, Crc};
+use crate::error::{with_filename, FastSeekError, Result};
+use crate::file_reader::FileReader;
+use crate::file_writer::FileWriter;
+use crate::fingerprint::{Fingerprint, FINGERPRINT_SIZE};
+
+//Random access into plain gzip files, as zran.c from zlib does: decompression restarts from the last checkpoint
+//before the position sought. A checkpoint is the start of a deflate block, in bits in the compressed data and in bytes
+//in the uncompressed data, with the 32 KiB of uncompressed data before it, which the block can refer back to.
+//Checkpoints are recorded while the file is read through when indexing, and stored in <file>.gzc:
+//  magic (8 bytes) | version (u8) | fingerprint of the file | interval (u64) | checkpoint count (u64) | entries address (u64)
+//  | windows, deflate-compressed
+//  | entries: uncompressed offset (u64) | bit offset (u64) | window address (u64) | window size (u32) | compressed window size (u32)
+const MAGIC: [u8; 8] = [0x89, b'F', b'S', b'K', b'G', b'Z', b'C', b'\n'];
+const VERSION: u8 = 1;
+const HEADER_SIZE: u64 = 8 + 1 + FINGERPRINT_SIZE as u64 + 8 + 8 + 8;
+const ENTRY_SIZE: usize = 32;
+//Largest distance of a back-reference of deflate
+const WINDOW_SIZE: usize = 32*1024;
+//Default uncompressed bytes between two checkpoints
+pub const DEFAULT_CHECKPOINT_INTERVAL: u64 = 4*1024*1024;
+
+//Path of the checkpoints of a plain gzip file
+pub fn checkpoints_path(filename: &str) -> String{
+    return format!("{}.gzc", filename);
+}
+
+#[derive(Clone, Debug)]
+struct Checkpoint{
+    uncompressed_offset: u64,
+    bit_offset: u64,
+    window_address: u64,
+    window_size: u32,
+    compressed_window_size: u32,
+}
+
+//Checkpoints stored for filename, with their interval. None if there are none, or they were recorded for another
+//version of the file.
+fn read_checkpoints(filename: &str) -> Result<Option<(u64, Vec<Checkpoint>)>>{
+    let path = checkpoints_path(filename);
+    if !Path::new(&path).exists() {
+        return Ok(None);
+    }
+    let mut file = io::BufReader::new(File::open(&path).map_err(|e| with_filename(e, "open", &path))?);
+    let mut header = [0; HEADER_SIZE as usize];
+    file.read_exact(&mut header).map_err(|_| FastSeekError::CorruptIndex(format!("invalid checkpoints file {}", path)))?;
+    if header[0..8] != MAGIC {
+        return Err(FastSeekError::CorruptIndex(format!("invalid checkpoints file {}", path)));
+    }
+    if header[8] != VERSION {
+        return Err(FastSeekError::UnsupportedVersion(header[8]));
+    }
+    let fingerprint_end = 9 + FINGERPRINT_SIZE as usize;
+    if Fingerprint::from_bytes(&header[9..fingerprint_end]).mismatch(&Fingerprint::of_file(filename)?).is_some() {
+        return Ok(None);
+    }
+    let read_u64 = |start: usize| u64::from_be_bytes(header[start..start + 8].try_into().unwrap());
+    let (interval, count, entries_address) = (read_u64(fingerprint_end), read_u64(fingerprint_end + 8), read_u64(fingerprint_end + 16));
+    file.seek(SeekFrom::Start(entries_address))?;
+    let mut checkpoints = vec![];
+    let mut entry = [0; ENTRY_SIZE];
+    for _ in 0..count {
+        file.read_exact(&mut entry).map_err(|_| FastSeekError::CorruptIndex(format!("truncated checkpoints file {}", path)))?;
+        checkpoints.push(Checkpoint{
+            uncompressed_offset: u64::from_be_bytes(entry[0..8].try_into().unwrap()),
+            bit_offset: u64::from_be_bytes(entry[8..16].try_into().unwrap()),
+            window_address: u64::from_be_bytes(entry[16..24].try_into().unwrap()),
+            window_size: u32::from_be_bytes(entry[24..28].try_into().unwrap()),
+            compressed_window_size: u32::from_be_bytes(entry[28..32].try_into().unwrap()),
+        });
+    }
+    if checkpoints.windows(2).any(|pair| pair[0].uncompressed_offset >= pair[1].uncompressed_offset)
+        || checkpoints.iter().any(|checkpoint| checkpoint.window_size as usize > WINDOW_SIZE) {
+        return Err(FastSeekError::CorruptIndex(format!("invalid checkpoints file {}", path)));
+    }
+    return Ok(Some((interval, checkpoints)));
+}
+
+fn invalid_data(reason: &str) -> io::Error{
+    return io::Error::new(io::ErrorKind::InvalidData, format!("invalid gzip data: {}", reason));
+}
+
+//Bits of the compressed data, least significant first as deflate packs them
+struct BitReader{
+    file: io::BufReader<File>,
+    bits: u64,
+    count: u32,
+    //Bytes of the file moved to bits
+    consumed: u64,
+}
+
+impl BitReader{
+    //Moves bytes of the file to bits until there are at least n, or the file ends
+    fn fill(&mut self, n: u32) -> io::Result<()>{
+        while self.count < n {
+            let available = self.file.fill_buf()?;
+            if available.is_empty() {
+                return Ok(());
+            }
+            let bytes = (((64 - self.count) / 8) as usize).min(available.len());
+            for &byte in available[..bytes].iter() {
+                self.bits |= (byte as u64) << self.count;
+                self.count += 8;
+            }
+            self.file.consume(bytes);
+            self.consumed += bytes as u64;
+        }
+        return Ok(());
+    }
+
+    fn read_bits(&mut self, n: u32) -> io::Result<u32>{
+        self.fill(n)?;
+        if self.count < n {
+            return Err(invalid_data("unexpected end of file"));
+        }
+        let value = (self.bits & ((1 << n) - 1)) as u32;
+        self.consume(n);
+        return Ok(value);
+    }
+
+    fn consume(&mut self, n: u32){
+        self.bits >>= n;
+        self.count -= n;
+    }
+
+    fn align_to_byte(&mut self){
+        self.consume(self.count % 8);
+    }
+
+    //Appends the next n bytes to output, once aligned to a byte
+    fn read_bytes(&mut self, output: &mut Vec<u8>, mut n: usize) -> io::Result<()>{
+        while n > 0 && self.count >= 8 {
+            output.push(self.bits as u8);
+            self.consume(8);
+            n -= 1;
+        }
+        let start = output.len();
+        output.resize(start + n, 0);
+        self.file.read_exact(&mut output[start..]).map_err(|_| invalid_data("unexpected end of file"))?;
+        self.consumed += n as u64;
+        return Ok(());
+    }
+
+    fn decode(&mut self, huffman: &Huffman) -> io::Result<u32>{
+        self.fill(huffman.max_bits)?;
+        let entry = huffman.table[(self.bits & ((1 << huffman.max_bits) - 1)) as usize];
+        let length = entry & 15;
+        if length == 0 || length > self.count {
+            return Err(invalid_data("invalid Huffman code"));
+        }
+        self.consume(length);
+        return Ok(entry >> 4);
+    }
+
+    fn bit_offset(&self) -> u64{
+        return self.consumed * 8 - self.count as u64;
+    }
+
+    fn seek_bit(&mut self, bit_offset: u64) -> io::Result<()>{
+        self.file.seek(SeekFrom::Start(bit_offset / 8))?;
+        self.bits = 0;
+        self.count = 0;
+        self.consumed = bit_offset / 8;
+        self.read_bits((bit_offset % 8) as u32)?;
+        return Ok(());
+    }
+
+    fn at_end(&mut self) -> io::Result<bool>{
+        return Ok(self.count < 8 && self.file.fill_buf()?.is_empty());
+    }
+}
+
+//Canonical Huffman code of deflate, decoded by looking up the next max_bits bits
+struct Huffman{
+    //Symbol << 4 | code length, for every value of max_bits bits. 0 if no code is a prefix of the value.
+    table: Vec<u32>,
+    max_bits: u32,
+}
+
+impl Huffman{
+    //Code of the symbols with these code lengths, 0 for symbols not used
+    fn new(lengths: &[u8]) -> io::Result<Huffman>{
+        let max_bits = *lengths.iter().max().unwrap_or(&0) as u32;
+        let mut count = [0u32; 16];
+        for &length in lengths.iter() {
+            count[length as usize] += 1;
+        }
+        count[0] = 0;
+        let mut next_code = [0u32; 16];
+        let mut code = 0;
+        let mut left: i64 = 1;
+        for bits in 1..16 {
+            code = (code + count[bits - 1]) << 1;
+            next_code[bits] = code;
+            left = (left << 1) - count[bits] as i64;
+            if left < 0 {
+                return Err(invalid_data("over-subscribed Huffman code"));
+            }
+        }
+        let mut table = vec![0; 1 << max_bits];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length == 0 {
+                continue;
+            }
+            let length = length as u32;
+            let code = next_code[length as usize];
+            next_code[length as usize] += 1;
+            //Codes are packed starting from their most significant bit
+            let mut i = (code.reverse_bits() >> (32 - length)) as usize;
+            while i < table.len() {
+                table[i] = (symbol as u32) << 4 | length;
+                i += 1 << length;
+            }
+        }
+        return Ok(Huffman{table, max_bits});
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258];
+const LENGTH_EXTRA_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+//Order of the code lengths of the code lengths code of dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+//Decoder of a gzip file one deflate block at a time, so that it can stop and restart at the start of any block
+struct Inflater{
+    input: BitReader,
+    literals: Huffman,
+    distances: Huffman,
+}
+
+impl Inflater{
+    //Appends the data of the next block to output, which holds the data before it. True if it was the last block.
+    fn inflate_block(&mut self, output: &mut Vec<u8>) -> io::Result<bool>{
+        let header = self.input.read_bits(3)?;
+        match header >> 1 {
+            0 => self.stored_block(output)?,
+            1 => {
+                let mut lengths = [8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                self.literals = Huffman::new(&lengths)?;
+                self.distances = Huffman::new(&[5; 30])?;
+                self.compressed_block(output)?;
+            }
+            2 => {
+                self.read_dynamic_codes()?;
+                self.compressed_block(output)?;
+            }
+            _ => return Err(invalid_data("invalid block type")),
+        }
+        return Ok(header & 1 == 1);
+    }
+
+    fn stored_block(&mut self, output: &mut Vec<u8>) -> io::Result<()>{
+        self.input.align_to_byte();
+        let length = self.input.read_bits(16)?;
+        if self.input.read_bits(16)? != !length & 0xffff {
+            return Err(invalid_data("invalid stored block length"));
+        }
+        return self.input.read_bytes(output, length as usize);
+    }
+
+    fn read_dynamic_codes(&mut self) -> io::Result<()>{
+        let literal_count = self.input.read_bits(5)? as usize + 257;
+        let distance_count = self.input.read_bits(5)? as usize + 1;
+        let code_length_count = self.input.read_bits(4)? as usize + 4;
+        let mut code_lengths = [0; 19];
+        for &symbol in CODE_LENGTH_ORDER[..code_length_count].iter() {
+            code_lengths[symbol] = self.input.read_bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&code_lengths)?;
+        let mut lengths: Vec<u8> = Vec::with_capacity(literal_count + distance_count);
+        while lengths.len() < literal_count + distance_count {
+            let (length, repeat) = match self.input.decode(&code_length_code)? {
+                symbol @ 0..=15 => (symbol as u8, 1),
+                16 => match lengths.last() {
+                    Some(&previous) => (previous, 3 + self.input.read_bits(2)? as usize),
+                    None => return Err(invalid_data("repeated code length without a previous one")),
+                },
+                17 => (0, 3 + self.input.read_bits(3)? as usize),
+                _ => (0, 11 + self.input.read_bits(7)? as usize),
+            };
+            if lengths.len() + repeat > literal_count + distance_count {
+                return Err(invalid_data("too many code lengths"));
+            }
+            lengths.resize(lengths.len() + repeat, length);
+        }
+        if lengths[256] == 0 {
+            return Err(invalid_data("missing end-of-block code"));
+        }
+        self.literals = Huffman::new(&lengths[..literal_count])?;
+        self.distances = Huffman::new(&lengths[literal_count..])?;
+        return Ok(());
+    }
+
+    fn compressed_block(&mut self, output: &mut Vec<u8>) -> io::Result<()>{
+        loop {
+            let symbol = self.input.decode(&self.literals)? as usize;
+            if symbol < 256 {
+                output.push(symbol as u8);
+                continue;
+            }
+            if symbol == 256 {
+                return Ok(());
+            }
+            if symbol - 257 >= LENGTH_BASE.len() {
+                return Err(invalid_data("invalid length code"));
+            }
+            let length = LENGTH_BASE[symbol - 257] as usize + self.input.read_bits(LENGTH_EXTRA_BITS[symbol - 257] as u32)? as usize;
+            let distance_symbol = self.input.decode(&self.distances)? as usize;
+            if distance_symbol >= DISTANCE_BASE.len() {
+                return Err(invalid_data("invalid distance code"));
+            }
+            let distance = DISTANCE_BASE[distance_symbol] as usize + self.input.read_bits(DISTANCE_EXTRA_BITS[distance_symbol] as u32)? as usize;
+            if distance > output.len() {
+                return Err(invalid_data("distance too far back"));
+            }
+            let start = output.len() - distance;
+            if distance >= length {
+                output.extend_from_within(start..start + length);
+            } else {
+                for i in 0..length {
+                    output.push(output[start + i]);
+                }
+            }
+        }
+    }
+
+    fn read_member_header(&mut self) -> io::Result<()>{
+        let mut fixed = [0; 10];
+        for byte in fixed.iter_mut() {
+            *byte = self.input.read_bits(8)? as u8;
+        }
+        if fixed[0..3] != [0x1f, 0x8b, 8] {
+            return Err(invalid_data("not a gzip member"));
+        }
+        let flags = fixed[3];
+        //FEXTRA
+        if flags & 0x04 != 0 {
+            let extra_size = self.input.read_bits(16)?;
+            for _ in 0..extra_size {
+                self.input.read_bits(8)?;
+            }
+        }
+        //FNAME, FCOMMENT: zero-terminated
+        for flag in [0x08, 0x10] {
+            if flags & flag != 0 {
+                while self.input.read_bits(8)? != 0 {}
+            }
+        }
+        //FHCRC
+        if flags & 0x02 != 0 {
+            self.input.read_bits(16)?;
+        }
+        return Ok(());
+    }
+}
+
+//Where the decoder stands in the file
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State{
+    MemberStart,
+    BlockStart,
+    //After the last block of a member
+    Trailer,
+    End,
+}
+
+//Checkpoints being recorded while the file is read through from its start
+struct Recorder{
+    interval: u64,
+    //Writer of <file>.gzc.tmp, created at the first checkpoint, and size of the windows written to it
+    writer: Option<FileWriter>,
+    windows_size: u64,
+}
+
+//Reader of a plain gzip file, seeking to positions in the uncompressed data from its checkpoints
+pub struct GzipCheckpointReader{
+    filename: String,
+    inflater: Inflater,
+    //Up to 32 KiB of data already read, for back-references, then the data of the last block decoded
+    buffer: Vec<u8>,
+    //Uncompressed offset of the start of buffer, and position of the next byte to read in it
+    buffer_offset: u64,
+    position: usize,
+    state: State,
+    //CRC of the data of the current member, if decoded from its start
+    crc: Option<Crc>,
+    member_size: u64,
+    checkpoints: Vec<Checkpoint>,
+    //Checkpoints file, once written or if read, to read windows from
+    windows: Option<File>,
+    recorder: Option<Recorder>,
+}
+
+impl GzipCheckpointReader{
+    //Reads filename with its checkpoints, if they match it and, with record_interval, have that interval.
+    //Otherwise, with record_interval, they are recorded the first time the file is read through, and written
+    //when its end is read. Without checkpoints, every seek decompresses the file from its start.
+    pub fn new(filename: &str, record_interval: Option<u64>) -> Result<GzipCheckpointReader>{
+        let file = File::open(filename).map_err(|e| with_filename(e, "open", filename))?;
+        let (checkpoints, windows, recorder) = match read_checkpoints(filename)? {
+            Some((interval, checkpoints)) if record_interval.is_none() || record_interval == Some(interval) => {
+                let path = checkpoints_path(filename);
+                (checkpoints, Some(File::open(&path).map_err(|e| with_filename(e, "open", &path))?), None)
+            }
+            _ => (vec![], None, record_interval.map(|interval| Recorder{interval: interval.max(1), writer: None, windows_size: 0})),
+        };
+        return Ok(GzipCheckpointReader{
+            filename: filename.to_string(),
+            inflater: Inflater{
+                input: BitReader{file: io::BufReader::new(file), bits: 0, count: 0, consumed: 0},
+                literals: Huffman{table: vec![0], max_bits: 0},
+                distances: Huffman{table: vec![0], max_bits: 0},
+            },
+            buffer: vec![], buffer_offset: 0, position: 0, state: State::MemberStart, crc: None, member_size: 0,
+            checkpoints, windows, recorder
+        });
+    }
+
+    //Reads filename with its checkpoints, which must exist
+    pub fn open(filename: &str) -> Result<GzipCheckpointReader>{
+        let reader = GzipCheckpointReader::new(filename, None)?;
+        if reader.windows.is_none() {
+            return Err(FastSeekError::InvalidArgument(format!(
+                "{} is gzip-compressed but not BGZF-compressed, and has no checkpoints to read it from random positions: \
+                index it again, or recompress it with `fastseek bgzip {}`", filename, filename
+            )));
+        }
+        return Ok(reader);
+    }
+
+    //Decodes the next block that has data, false at the end of the file
+    fn decode_block(&mut self) -> io::Result<bool>{
+        loop {
+            match self.state {
+                State::End => return Ok(false),
+                State::MemberStart => {
+                    self.inflater.input.align_to_byte();
+                    if self.inflater.input.at_end()? {
+                        self.state = State::End;
+                        continue;
+                    }
+                    self.inflater.read_member_header()?;
+                    self.crc = Some(Crc::new());
+                    self.member_size = 0;
+                    self.state = State::BlockStart;
+                }
+                State::Trailer => {
+                    self.inflater.input.align_to_byte();
+                    let crc = self.inflater.input.read_bits(32)?;
+                    let size = self.inflater.input.read_bits(32)?;
+                    if let Some(data_crc) = &self.crc {
+                        if data_crc.sum() != crc || self.member_size as u32 != size {
+                            return Err(invalid_data("CRC mismatch"));
+                        }
+                    }
+                    self.state = State::MemberStart;
+                }
+                State::BlockStart => {
+                    //Only the data back-references can reach is kept
+                    if self.buffer.len() > WINDOW_SIZE {
+                        let dropped = self.buffer.len() - WINDOW_SIZE;
+                        self.buffer.drain(..dropped);
+                        self.buffer_offset += dropped as u64;
+                        self.position = self.position.saturating_sub(dropped);
+                    }
+                    self.record_checkpoint()?;
+                    let start = self.buffer.len();
+                    let last = self.inflater.inflate_block(&mut self.buffer)?;
+                    if let Some(crc) = &mut self.crc {
+                        crc.update(&self.buffer[start..]);
+                    }
+                    self.member_size += (self.buffer.len() - start) as u64;
+                    if last {
+                        self.state = State::Trailer;
+                    }
+                    if self.buffer.len() > start {
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+    }
+
+    fn record_checkpoint(&mut self) -> io::Result<()>{
+        let recorder = match &mut self.recorder {
+            Some(recorder) => recorder,
+            None => return Ok(()),
+        };
+        let uncompressed_offset = self.buffer_offset + self.buffer.len() as u64;
+        let previous = self.checkpoints.last().map(|checkpoint| checkpoint.uncompressed_offset).unwrap_or(0);
+        if uncompressed_offset < previous + recorder.interval {
+            return Ok(());
+        }
+        let window = &self.buffer[self.buffer.len().saturating_sub(WINDOW_SIZE)..];
+        let mut encoder = DeflateEncoder::new(vec![], Compression::default());
+        encoder.write_all(window)?;
+        let compressed_window = encoder.finish()?;
+        if recorder.writer.is_none() {
+            let temp_path = format!("{}.tmp", checkpoints_path(&self.filename));
+            let mut writer = FileWriter::get_writer(temp_path).map_err(io::Error::other)?;
+            writer.write_all(&[0; HEADER_SIZE as usize])?;
+            recorder.writer = Some(writer);
+        }
+        recorder.writer.as_mut().unwrap().write_all(&compressed_window)?;
+        self.checkpoints.push(Checkpoint{
+            uncompressed_offset,
+            bit_offset: self.inflater.input.bit_offset(),
+            window_address: HEADER_SIZE + recorder.windows_size,
+            window_size: window.len() as u32,
+            compressed_window_size: compressed_window.len() as u32,
+        });
+        recorder.windows_size += compressed_window.len() as u64;
+        return Ok(());
+    }
+
+    //Writes the checkpoints recorded, once the end of the file is read
+    fn finish_recording(&mut self) -> Result<()>{
+        if self.state != State::End {
+            return Ok(());
+        }
+        let mut recorder = match self.recorder.take() {
+            Some(recorder) => recorder,
+            None => return Ok(()),
+        };
+        let path = checkpoints_path(&self.filename);
+        let temp_path = format!("{}.tmp", path);
+        let mut writer = match recorder.writer.take() {
+            Some(writer) => writer,
+            None => {
+                let mut writer = FileWriter::get_writer(temp_path.clone())?;
+                writer.write_all(&[0; HEADER_SIZE as usize])?;
+                writer
+            }
+        };
+        for checkpoint in self.checkpoints.iter() {
+            writer.write_all(&checkpoint.uncompressed_offset.to_be_bytes())?;
+            writer.write_all(&checkpoint.bit_offset.to_be_bytes())?;
+            writer.write_all(&checkpoint.window_address.to_be_bytes())?;
+            writer.write_all(&checkpoint.window_size.to_be_bytes())?;
+            writer.write_all(&checkpoint.compressed_window_size.to_be_bytes())?;
+        }
+        writer.seek(SeekFrom::Start(0))?;
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION])?;
+        writer.write_all(&Fingerprint::of_file(&self.filename)?.to_bytes())?;
+        writer.write_all(&recorder.interval.to_be_bytes())?;
+        writer.write_all(&(self.checkpoints.len() as u64).to_be_bytes())?;
+        writer.write_all(&(HEADER_SIZE + recorder.windows_size).to_be_bytes())?;
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temp_path, &path).map_err(|e| with_filename(e, "replace", &path))?;
+        self.windows = Some(File::open(&path).map_err(|e| with_filename(e, "open", &path))?);
+        return Ok(());
+    }
+
+    //Restarts decompression from a checkpoint, or from the start of the file
+    fn restart(&mut self, checkpoint: Option<usize>) -> Result<()>{
+        let checkpoint = match (checkpoint, &mut self.windows) {
+            (Some(i), Some(_)) => self.checkpoints[i].clone(),
+            _ => {
+                self.inflater.input.seek_bit(0)?;
+                self.buffer.clear();
+                self.buffer_offset = 0;
+                self.state = State::MemberStart;
+                //The file is read through again
+                if let Some(recorder) = &mut self.recorder {
+                    self.checkpoints.clear();
+                    recorder.writer = None;
+                    recorder.windows_size = 0;
+                }
+                return Ok(());
+            }
+        };
+        let windows = self.windows.as_mut().unwrap();
+        windows.seek(SeekFrom::Start(checkpoint.window_address))?;
+        let mut window = Vec::with_capacity(checkpoint.window_size as usize);
+        DeflateDecoder::new(windows.take(checkpoint.compressed_window_size as u64)).read_to_end(&mut window)?;
+        if window.len() != checkpoint.window_size as usize || checkpoint.uncompressed_offset < window.len() as u64 {
+            return Err(FastSeekError::CorruptIndex(format!("invalid checkpoints file {}", checkpoints_path(&self.filename))));
+        }
+        self.inflater.input.seek_bit(checkpoint.bit_offset)?;
+        self.buffer_offset = checkpoint.uncompressed_offset - window.len() as u64;
+        self.buffer = window;
+        self.state = State::BlockStart;
+        self.crc = None;
+        return Ok(());
+    }
+
+    //Stops recording checkpoints: the file is no longer read through
+    fn stop_recording(&mut self) -> Result<()>{
+        if let Some(recorder) = self.recorder.take() {
+            self.checkpoints.clear();
+            if recorder.writer.is_some() {
+                drop(recorder);
+                fs::remove_file(format!("{}.tmp", checkpoints_path(&self.filename)))?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl BufRead for GzipCheckpointReader{
+    fn fill_buf(&mut self) -> io::Result<&[u8]>{
+        while self.position >= self.buffer.len() {
+            if !self.decode_block()? {
+                break;
+            }
+        }
+        return Ok(&self.buffer[self.position..]);
+    }
+
+    fn consume(&mut self, amount: usize){
+        self.position = (self.position + amount).min(self.buffer.len());
+    }
+}
+
+impl Read for GzipCheckpointReader{
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize>{
+        let data = self.fill_buf()?;
+        let n = data.len().min(buffer.len());
+        buffer[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        return Ok(n);
+    }
+}
+
+impl FileReader for GzipCheckpointReader{
+    fn seek(&mut self, pos: u64) -> Result<()> {
+        if pos != 0 {
+            self.stop_recording()?;
+        }
+        let checkpoint = match self.checkpoints.partition_point(|checkpoint| checkpoint.uncompressed_offset <= pos) {
+            0 => None,
+            i => Some(i - 1),
+        };
+        let checkpoint_offset = checkpoint.map(|i| self.checkpoints[i].uncompressed_offset).unwrap_or(0);
+        //Decoding on is faster than restarting, unless the position is behind or a checkpoint is closer to it
+        let decoded_end = self.buffer_offset + self.buffer.len() as u64;
+        if pos < self.buffer_offset || decoded_end < checkpoint_offset {
+            self.restart(checkpoint)?;
+        }
+        while self.buffer_offset + (self.buffer.len() as u64) <= pos {
+            self.position = self.buffer.len();
+            if !self.decode_block()? {
+                break;
+            }
+        }
+        self.position = (pos.saturating_sub(self.buffer_offset) as usize).min(self.buffer.len());
+        return Ok(());
+    }
+    fn position(&mut self) -> Result<u64> {
+        return Ok(self.buffer_offset + self.position as u64);
+    }
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        Read::read_exact(self, buffer)?;
+        return Ok(());
+    }
+    fn read_line(&mut self, buffer: &mut String) -> Result<usize> {
+        let bytes_read = BufRead::read_line(self, buffer)?;
+        if bytes_read == 0 {
+            self.finish_recording()?;
+        }
+        return Ok(bytes_read);
+    }
+    fn num_lines(&mut self) -> Result<u64> {
+        let mut number_lines = 0;
+        let mut buffer = [0; 8192];
+        loop {
+            let n = self.read(&mut buffer)?;
+            if n == 0 { break; }
+            number_lines += buffer[..n].iter()
+                .filter(|&&byte| byte == b'\n')
+                .count();
+        }
+        self.finish_recording()?;
+        FileReader::seek(self, 0)?;
+        return Ok(number_lines as u64);
+    }
+}
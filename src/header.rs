@@ -1,38 +1,314 @@
+use crate::error::{FastSeekError, Result};
+use crate::fingerprint::{Fingerprint, FINGERPRINT_SIZE};
 
-const HEADER_SIZE: [u8; 1] = [13];
+//Header v0, written before version 1:
+//  header size (u8) | version (u8) | hashmap size (u64) | index type (u8) | separator (u8) | column (u8)
+//followed by optional fields, read only if the header size stored in the first byte covers them:
+//  flags (u8) | id token (u8) if FLAG_ID_TOKEN | fingerprint if FLAG_FINGERPRINT
+const V0_HEADER_SIZE: u8 = 13;
+const V0_FLAGS_SIZE: u8 = 1;
+const ID_TOKEN_SIZE: u8 = 1;
+
+//Header v1:
+//  magic (8 bytes) | version (u8) | header size (u32) | flags (u32) | hash algorithm (u8) | hashmap size (u64)
+//  | index type (u8) | separator (u8) | column (u32) | id token (u8) | fingerprint
+//  | tool version, command line (u16 length + utf-8 each)
+//Fields added later are appended, and read only if the header size covers them:
+//  | columns (u16 count + u32 each) | source path (u16 length + utf-8) | quote (u8)
+//  | header line (u16 length + utf-8) | comment character (u8) | separator (u16 length + utf-8)
+//  | key normalisation (u8)
+//The separator byte of the fixed part holds the first byte of the separator, for tools reading only that part.
+//The first byte of the magic can't be the size of a v0 header, so the two are told apart by it.
+pub const MAGIC: [u8; 8] = [0x89, b'F', b'S', b'K', b'\r', b'\n', 0x1a, b'\n'];
+pub const V1_FIXED_SIZE: usize = 57;
+//Bytes needed to know the size of a header, of any version
+pub const HEADER_PREFIX_SIZE: usize = 13;
+//Sanity limit on the header size stored in the file
+const MAX_HEADER_SIZE: u64 = 1024*1024;
+const MAX_INDEX_TYPE: u8 = 4;
+
+//Hash algorithms
+pub const HASH_STABLE_HASH: u8 = 0; //fast_stable_hash modulo the hashmap size
+
+//Header flags
+pub const FLAG_CONTIGUOUS_BLOCKS: u32 = 0x01; //Collisions stored as contiguous blocks instead of linked lists
+pub const FLAG_COMPRESSED_BLOCKS: u32 = 0x02; //Block area stored as compressed chunks
+pub const FLAG_COMPRESSED_HASHMAP: u32 = 0x04; //Hashmap stored compressed, requires FLAG_COMPRESSED_BLOCKS
+pub const FLAG_ID_TOKEN: u32 = 0x08; //Fasta/fastq keyed by one token of the header line
+pub const FLAG_FINGERPRINT: u32 = 0x10; //Fingerprint of the indexed file stored
+pub const FLAG_COMPOSITE_KEY: u32 = 0x20; //Tabular key made of more than one column
+pub const FLAG_QUOTED_FIELDS: u32 = 0x40; //Tabular file parsed as csv, with fields enclosed in the quote character
+pub const FLAG_HAS_HEADER: u32 = 0x80; //Tabular file starting with a header line, stored in the header
+pub const FLAG_COMMENT_CHAR: u32 = 0x100; //Tabular file lines starting with the comment character skipped
+pub const FLAG_WHITESPACE_SEPARATOR: u32 = 0x200; //Tabular fields separated by runs of blanks instead of the separator
+pub const FLAG_NORMALIZED_KEYS: u32 = 0x400; //Keys normalised as stored in the header before hashing
+pub const FLAG_ORDERED_KEYS: u32 = 0x800; //Ordered index of the keys written to <index>.sorted
+pub const FLAG_BGZF_VIRTUAL_OFFSETS: u32 = 0x1000; //Offsets in BGZF files are virtual offsets instead of uncompressed positions
+pub const FLAG_UPDATABLE: u32 = 0x2000; //Fingerprint of exactly the data indexed, so records appended later can be added to the index
+const KNOWN_FLAGS: u32 = FLAG_CONTIGUOUS_BLOCKS | FLAG_COMPRESSED_BLOCKS | FLAG_COMPRESSED_HASHMAP | FLAG_ID_TOKEN | FLAG_FINGERPRINT
+    | FLAG_COMPOSITE_KEY | FLAG_QUOTED_FIELDS | FLAG_HAS_HEADER | FLAG_COMMENT_CHAR | FLAG_WHITESPACE_SEPARATOR | FLAG_NORMALIZED_KEYS
+    | FLAG_ORDERED_KEYS | FLAG_BGZF_VIRTUAL_OFFSETS | FLAG_UPDATABLE;
+
+//Key normalisation steps, if FLAG_NORMALIZED_KEYS is set
+pub const NORMALIZE_LINE_END: u8 = 0x01; //Whole line terminator removed, "\r\n" included
+pub const NORMALIZE_TRIM: u8 = 0x02; //Leading and trailing whitespace removed
+pub const NORMALIZE_STRIP_QUOTES: u8 = 0x04; //Enclosing quotes removed
+pub const NORMALIZE_ASCII_LOWERCASE: u8 = 0x08; //ASCII letters lowercased
+pub const NORMALIZE_UNICODE_LOWERCASE: u8 = 0x10; //Every letter lowercased
+const KNOWN_NORMALIZATION: u8 = NORMALIZE_LINE_END | NORMALIZE_TRIM | NORMALIZE_STRIP_QUOTES | NORMALIZE_ASCII_LOWERCASE | NORMALIZE_UNICODE_LOWERCASE;
 
 pub struct Header{
     pub version: u8,
     pub hashmap_size: u64,
     pub index_type: u8, //0: tabular, 1,2 fasta with id and seq, 3,4 fastq with id and seq
-    pub separator: u8,
-    pub column: u8
+    //Field separator of tabular files, empty for other files or with FLAG_WHITESPACE_SEPARATOR
+    pub separator: String,
+    //Indexed columns of tabular files, the key is their fields joined by the separator
+    pub columns: Vec<u32>,
+    pub flags: u32,
+    pub hash_algorithm: u8,
+    //Whitespace-separated token of the header line used as key, if FLAG_ID_TOKEN is set
+    pub id_token: u8,
+    //Indexed file when the index was built, if FLAG_FINGERPRINT is set
+    pub fingerprint: Fingerprint,
+    //Version of FastSeek and command line that created the index, empty in v0 headers
+    pub tool_version: String,
+    pub command_line: String,
+    //Indexed file, relative to the directory of the index or absolute. Empty if not recorded.
+    pub source_path: String,
+    //Quote character of tabular files, if FLAG_QUOTED_FIELDS is set
+    pub quote: u8,
+    //Header line of tabular files, if FLAG_HAS_HEADER is set
+    pub header_line: String,
+    //Comment character of tabular files, if FLAG_COMMENT_CHAR is set
+    pub comment_char: u8,
+    //Key normalisation steps, if FLAG_NORMALIZED_KEYS is set
+    pub normalization: u8
 }
 impl Header{
-    pub fn new(version: u8, hashmap_size: u64, index_type: u8, separator: u8, column: u8) -> Header{
-        return Header{version, hashmap_size, index_type: index_type, separator, column};
+    pub fn new(version: u8, hashmap_size: u64, index_type: u8, separator: String, columns: Vec<u32>, flags: u32) -> Header{
+        return Header{
+            version, hashmap_size, index_type: index_type, separator, columns, flags, hash_algorithm: HASH_STABLE_HASH,
+            id_token: 0, fingerprint: Fingerprint::default(), tool_version: String::new(), command_line: String::new(),
+            source_path: String::new(), quote: 0, header_line: String::new(), comment_char: 0,
+            normalization: 0
+        };
+    }
+    pub fn get_header_size(&self) -> Result<u64>{
+        return Ok(self.to_bytes()?.len() as u64);
     }
-    pub fn get_header_size(&self) -> u8{
-        HEADER_SIZE[self.version as usize]
+    pub fn has_flag(&self, flag: u32) -> bool{
+        return self.flags & flag != 0;
     }
-    pub fn to_bytes(&self) -> Vec<u8>{
-        let mut bytes: Vec<u8> = Vec::with_capacity(self.get_header_size() as usize);
-        bytes.push(self.get_header_size());
+    //Only v1 headers are written
+    pub fn to_bytes(&self) -> Result<Vec<u8>>{
+        let mut bytes: Vec<u8> = Vec::with_capacity(V1_FIXED_SIZE + self.tool_version.len() + self.command_line.len() + self.source_path.len() + self.header_line.len() + self.separator.len() + 11);
+        bytes.extend_from_slice(&MAGIC);
         bytes.push(self.version);
-        let _ = &self.hashmap_size.to_be_bytes().map(|x| bytes.push(x));
+        //Header size, set at the end
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&self.flags.to_be_bytes());
+        bytes.push(self.hash_algorithm);
+        bytes.extend_from_slice(&self.hashmap_size.to_be_bytes());
         bytes.push(self.index_type);
-        bytes.push(self.separator);
-        bytes.push(self.column);
-        assert!(bytes.len() == self.get_header_size() as usize);
-        return bytes;
+        bytes.push(self.separator.as_bytes().first().copied().unwrap_or(0));
+        bytes.extend_from_slice(&self.columns.first().copied().unwrap_or(0).to_be_bytes());
+        bytes.push(self.id_token);
+        bytes.extend_from_slice(&self.fingerprint.to_bytes());
+        assert!(bytes.len() == V1_FIXED_SIZE);
+        push_string(&mut bytes, &self.tool_version, "tool version")?;
+        push_string(&mut bytes, &self.command_line, "command line")?;
+        bytes.extend_from_slice(&(self.columns.len() as u16).to_be_bytes());
+        for column in self.columns.iter() {
+            bytes.extend_from_slice(&column.to_be_bytes());
+        }
+        push_string(&mut bytes, &self.source_path, "source path")?;
+        bytes.push(self.quote);
+        push_string(&mut bytes, &self.header_line, "header line")?;
+        bytes.push(self.comment_char);
+        push_string(&mut bytes, &self.separator, "separator")?;
+        bytes.push(self.normalization);
+        let size = bytes.len() as u32;
+        bytes[9..13].copy_from_slice(&size.to_be_bytes());
+        return Ok(bytes);
+    }
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Header>{
+        if bytes.len() < 2 {
+            return Err(FastSeekError::CorruptIndex("header is truncated".to_string()));
+        }
+        let header = match bytes.starts_with(&MAGIC) {
+            true => Header::from_v1_bytes(&bytes)?,
+            false => Header::from_v0_bytes(&bytes)?,
+        };
+        if header.hashmap_size == 0 {
+            return Err(FastSeekError::CorruptIndex("hashmap size is 0".to_string()));
+        }
+        if header.index_type > MAX_INDEX_TYPE {
+            return Err(FastSeekError::CorruptIndex(format!("unknown index type {}", header.index_type)));
+        }
+        if header.hash_algorithm != HASH_STABLE_HASH {
+            return Err(FastSeekError::CorruptIndex(format!("unknown hash algorithm {}", header.hash_algorithm)));
+        }
+        if header.flags & !KNOWN_FLAGS != 0 {
+            return Err(FastSeekError::CorruptIndex(format!("unknown header flags {:#010x}", header.flags)));
+        }
+        if header.has_flag(FLAG_COMPRESSED_HASHMAP) && !header.has_flag(FLAG_COMPRESSED_BLOCKS) {
+            return Err(FastSeekError::CorruptIndex("compressed hashmap without compressed blocks".to_string()));
+        }
+        if header.has_flag(FLAG_UPDATABLE) && (!header.has_flag(FLAG_FINGERPRINT) || header.has_flag(FLAG_COMPRESSED_BLOCKS)) {
+            return Err(FastSeekError::CorruptIndex("updatable index without fingerprint or with compressed blocks".to_string()));
+        }
+        if header.has_flag(FLAG_ID_TOKEN) && header.index_type != 1 && header.index_type != 3 {
+            return Err(FastSeekError::CorruptIndex(format!("id token key for index type {}", header.index_type)));
+        }
+        if header.has_flag(FLAG_QUOTED_FIELDS) && (header.index_type != 0 || !header.quote.is_ascii() || header.quote == 0) {
+            return Err(FastSeekError::CorruptIndex(format!("invalid quote character {} for index type {}", header.quote, header.index_type)));
+        }
+        if (header.has_flag(FLAG_HAS_HEADER) || header.has_flag(FLAG_COMMENT_CHAR)) && header.index_type != 0 {
+            return Err(FastSeekError::CorruptIndex(format!("header line or comments for index type {}", header.index_type)));
+        }
+        if header.has_flag(FLAG_COMMENT_CHAR) && (!header.comment_char.is_ascii() || header.comment_char == 0) {
+            return Err(FastSeekError::CorruptIndex(format!("invalid comment character {}", header.comment_char)));
+        }
+        if header.has_flag(FLAG_WHITESPACE_SEPARATOR) && (header.index_type != 0 || header.has_flag(FLAG_QUOTED_FIELDS)) {
+            return Err(FastSeekError::CorruptIndex(format!("whitespace separator for index type {} or quoted fields", header.index_type)));
+        }
+        if header.index_type == 0 && header.separator.is_empty() != header.has_flag(FLAG_WHITESPACE_SEPARATOR) {
+            return Err(FastSeekError::CorruptIndex(format!("invalid separator {:?}", header.separator)));
+        }
+        if header.has_flag(FLAG_NORMALIZED_KEYS) != (header.normalization != 0) || header.normalization & !KNOWN_NORMALIZATION != 0
+            || header.normalization & NORMALIZE_ASCII_LOWERCASE != 0 && header.normalization & NORMALIZE_UNICODE_LOWERCASE != 0 {
+            return Err(FastSeekError::CorruptIndex(format!("invalid key normalisation {:#04x}", header.normalization)));
+        }
+        if header.columns.is_empty() || header.has_flag(FLAG_COMPOSITE_KEY) != (header.columns.len() > 1) {
+            return Err(FastSeekError::CorruptIndex(format!("invalid number of columns {}", header.columns.len())));
+        }
+        return Ok(header);
     }
-    pub fn from_bytes(bytes: Vec<u8>) -> Header{
-        let _ = bytes[0];
+
+    fn from_v0_bytes(bytes: &[u8]) -> Result<Header>{
         let version = bytes[1];
-        let hashmap_size = u64::from_be_bytes([bytes[2], bytes[3], bytes[4], bytes[5], bytes[6], bytes[7], bytes[8], bytes[9]]);
-        let index_type = bytes[10];
-        let separator = bytes[11];
-        let column = bytes[12];
-        return Header{version, hashmap_size, index_type, separator, column};
+        if version != 0 {
+            return Err(FastSeekError::UnsupportedVersion(version));
+        }
+        if bytes[0] < V0_HEADER_SIZE || bytes.len() < bytes[0] as usize {
+            return Err(FastSeekError::CorruptIndex(format!("invalid header size {}", bytes[0])));
+        }
+        let hashmap_size = u64::from_be_bytes(bytes[2..10].try_into().unwrap());
+        let mut header = Header::new(version, hashmap_size, bytes[10], separator_from_byte(bytes[11]), vec![bytes[12] as u32], 0);
+        //Indexes created before flags existed have none set
+        if bytes[0] > V0_HEADER_SIZE {
+            header.flags = bytes[13] as u32;
+        }
+        //Fields stored after the flags, in this order, if their flag is set
+        let mut size = V0_HEADER_SIZE + V0_FLAGS_SIZE;
+        if header.has_flag(FLAG_ID_TOKEN) {
+            size += ID_TOKEN_SIZE;
+        }
+        if header.has_flag(FLAG_FINGERPRINT) {
+            size += FINGERPRINT_SIZE;
+        }
+        if header.flags != 0 && bytes[0] < size {
+            return Err(FastSeekError::CorruptIndex(format!("invalid header size {}", bytes[0])));
+        }
+        let mut position = (V0_HEADER_SIZE + V0_FLAGS_SIZE) as usize;
+        if header.has_flag(FLAG_ID_TOKEN) {
+            header.id_token = bytes[position];
+            position += ID_TOKEN_SIZE as usize;
+        }
+        if header.has_flag(FLAG_FINGERPRINT) {
+            header.fingerprint = Fingerprint::from_bytes(&bytes[position..position + FINGERPRINT_SIZE as usize]);
+        }
+        return Ok(header);
+    }
+
+    fn from_v1_bytes(bytes: &[u8]) -> Result<Header>{
+        if bytes.len() < HEADER_PREFIX_SIZE {
+            return Err(FastSeekError::CorruptIndex("header is truncated".to_string()));
+        }
+        let version = bytes[8];
+        if version != 1 {
+            return Err(FastSeekError::UnsupportedVersion(version));
+        }
+        let size = u32::from_be_bytes(bytes[9..13].try_into().unwrap()) as usize;
+        if size < V1_FIXED_SIZE || bytes.len() < size {
+            return Err(FastSeekError::CorruptIndex(format!("invalid header size {}", size)));
+        }
+        let bytes = &bytes[..size];
+        let flags = u32::from_be_bytes(bytes[13..17].try_into().unwrap());
+        let hashmap_size = u64::from_be_bytes(bytes[18..26].try_into().unwrap());
+        let column = u32::from_be_bytes(bytes[28..32].try_into().unwrap());
+        let mut header = Header::new(version, hashmap_size, bytes[26], separator_from_byte(bytes[27]), vec![column], flags);
+        header.hash_algorithm = bytes[17];
+        header.id_token = bytes[32];
+        header.fingerprint = Fingerprint::from_bytes(&bytes[33..33 + FINGERPRINT_SIZE as usize]);
+        let mut position = V1_FIXED_SIZE;
+        header.tool_version = read_string(bytes, &mut position)?;
+        header.command_line = read_string(bytes, &mut position)?;
+        if position < bytes.len() {
+            let count = u16::from_be_bytes(read_bytes(bytes, &mut position, 2)?.try_into().unwrap());
+            let columns = read_bytes(bytes, &mut position, count as usize * 4)?;
+            header.columns = columns.chunks(4).map(|column| u32::from_be_bytes(column.try_into().unwrap())).collect();
+        }
+        if position < bytes.len() {
+            header.source_path = read_string(bytes, &mut position)?;
+        }
+        if position < bytes.len() {
+            header.quote = read_bytes(bytes, &mut position, 1)?[0];
+        }
+        if position < bytes.len() {
+            header.header_line = read_string(bytes, &mut position)?;
+            header.comment_char = read_bytes(bytes, &mut position, 1)?[0];
+        }
+        if position < bytes.len() {
+            header.separator = read_string(bytes, &mut position)?;
+        }
+        if position < bytes.len() {
+            header.normalization = read_bytes(bytes, &mut position, 1)?[0];
+        }
+        return Ok(header);
+    }
+}
+
+//Separator stored in a single byte, by v0 headers and the fixed part of v1 headers.
+//The first byte of a longer utf-8 separator isn't one: the separator is then read from the appended field.
+fn separator_from_byte(byte: u8) -> String{
+    return match byte {
+        byte if byte != 0 && byte.is_ascii() => (byte as char).to_string(),
+        _ => String::new(),
+    };
+}
+
+//Size of the header starting with prefix, the first HEADER_PREFIX_SIZE bytes of an index
+pub fn header_size(prefix: &[u8]) -> Result<u64>{
+    let size = match prefix.starts_with(&MAGIC) {
+        true => u32::from_be_bytes(prefix[9..13].try_into().unwrap()) as u64,
+        false => prefix[0] as u64,
+    };
+    if size > MAX_HEADER_SIZE {
+        return Err(FastSeekError::CorruptIndex(format!("invalid header size {}", size)));
+    }
+    return Ok(size);
+}
+
+//Strings are stored as u16 length + utf-8 bytes: field names the string in the error if it doesn't fit
+fn push_string(bytes: &mut Vec<u8>, string: &str, field: &str) -> Result<()>{
+    if string.len() > u16::MAX as usize {
+        return Err(FastSeekError::InvalidArgument(format!("{} too long for the index header: {} bytes, at most {}", field, string.len(), u16::MAX)));
     }
-}
\ No newline at end of file
+    bytes.extend_from_slice(&(string.len() as u16).to_be_bytes());
+    bytes.extend_from_slice(string.as_bytes());
+    return Ok(());
+}
+
+fn read_string(bytes: &[u8], position: &mut usize) -> Result<String>{
+    let len = u16::from_be_bytes(read_bytes(bytes, position, 2)?.try_into().unwrap()) as usize;
+    let string = read_bytes(bytes, position, len)?;
+    return String::from_utf8(string.to_vec()).map_err(|_| FastSeekError::CorruptIndex("invalid string in header".to_string()));
+}
+
+fn read_bytes<'a>(bytes: &'a [u8], position: &mut usize, len: usize) -> Result<&'a [u8]>{
+    let field = bytes.get(*position..*position + len).ok_or_else(|| FastSeekError::CorruptIndex("header is truncated".to_string()))?;
+    *position += len;
+    return Ok(field);
+}
@@ -0,0 +1,188 @@
+use std::fs;
+use std::io::{self, Read, Write};
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use crate::error::{with_filename, FastSeekError, Result};
+use crate::file_reader::{FileReader, StandardFileReader};
+use crate::file_writer::FileWriter;
+use crate::header::{Header, FLAG_COMPRESSED_HASHMAP};
+use crate::index_structure::HASHMAP_ENTRY_SIZE;
+
+//Compressed index layout:
+//  header | hashmap, plain or one deflate stream | block area as deflate chunks | chunk table | footer
+//Pointers to the block area keep the addresses they would have in the uncompressed index:
+//chunk i holds the uncompressed bytes [blocks_start + i*BLOCK_CHUNK_SIZE, blocks_start + (i+1)*BLOCK_CHUNK_SIZE).
+//The chunk table holds the file offset of each chunk, plus the end offset of the last one.
+//Footer: chunk table offset, number of chunks, blocks_start - u64 each.
+pub const BLOCK_CHUNK_SIZE: usize = 64*1024;
+const FOOTER_SIZE: u64 = 24;
+const COPY_BUFFER_SIZE: usize = 1024*1024;
+
+//Rewrite the uncompressed index at uncompressed_path to path, compressing the block area
+//and, if FLAG_COMPRESSED_HASHMAP is set in header, the hashmap.
+pub fn compress_index(uncompressed_path: &str, path: &str, header: &Header, blocks_start: u64) -> Result<()>{
+    let mut reader = StandardFileReader::new(uncompressed_path)?;
+    let uncompressed_len = fs::metadata(uncompressed_path).map_err(|e| with_filename(e, "read", uncompressed_path))?.len();
+    let mut writer = FileWriter::get_writer(path.to_string())?;
+    let header_bytes = header.to_bytes()?;
+    let header_size = header_bytes.len() as u64;
+    writer.write_all(&header_bytes)?;
+    let mut position = header_size;
+
+    //Hashmap
+    reader.seek(header_size)?;
+    let mut remaining = header.hashmap_size * HASHMAP_ENTRY_SIZE as u64;
+    let mut buffer = vec![0; COPY_BUFFER_SIZE];
+    if header.has_flag(FLAG_COMPRESSED_HASHMAP){
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        while remaining > 0 {
+            let n = remaining.min(COPY_BUFFER_SIZE as u64) as usize;
+            reader.read_exact(&mut buffer[..n])?;
+            encoder.write_all(&buffer[..n])?;
+            remaining -= n as u64;
+        }
+        let compressed = encoder.finish()?;
+        writer.write_all(&compressed)?;
+        position += compressed.len() as u64;
+    } else {
+        while remaining > 0 {
+            let n = remaining.min(COPY_BUFFER_SIZE as u64) as usize;
+            reader.read_exact(&mut buffer[..n])?;
+            writer.write_all(&buffer[..n])?;
+            remaining -= n as u64;
+        }
+        position += header.hashmap_size * HASHMAP_ENTRY_SIZE as u64;
+    }
+
+    //Block area
+    reader.seek(blocks_start)?;
+    let mut chunk_offsets: Vec<u64> = vec![];
+    let mut remaining = uncompressed_len.saturating_sub(blocks_start);
+    while remaining > 0 {
+        let n = remaining.min(BLOCK_CHUNK_SIZE as u64) as usize;
+        reader.read_exact(&mut buffer[..n])?;
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&buffer[..n])?;
+        let compressed = encoder.finish()?;
+        chunk_offsets.push(position);
+        writer.write_all(&compressed)?;
+        position += compressed.len() as u64;
+        remaining -= n as u64;
+    }
+    chunk_offsets.push(position);
+
+    //Chunk table and footer
+    let num_chunks = chunk_offsets.len() as u64 - 1;
+    for offset in chunk_offsets.iter(){
+        writer.write_all(&offset.to_be_bytes())?;
+    }
+    writer.write_all(&position.to_be_bytes())?;
+    writer.write_all(&num_chunks.to_be_bytes())?;
+    writer.write_all(&blocks_start.to_be_bytes())?;
+    writer.flush()?;
+    return Ok(());
+}
+
+//Random access to the block area of a compressed index
+pub struct CompressedBlocks{
+    chunk_offsets: Vec<u64>,
+    blocks_start: u64,
+    //Last decompressed chunk
+    chunk_index: usize,
+    chunk: Vec<u8>
+}
+
+impl CompressedBlocks{
+    pub fn open(index_reader: &mut StandardFileReader, index_len: u64) -> Result<CompressedBlocks>{
+        if index_len < FOOTER_SIZE {
+            return Err(FastSeekError::CorruptIndex("missing compressed index footer".to_string()));
+        }
+        let mut footer = [0; FOOTER_SIZE as usize];
+        index_reader.seek(index_len - FOOTER_SIZE)?;
+        index_reader.read_exact(&mut footer)?;
+        let table_offset = u64::from_be_bytes(footer[0..8].try_into().unwrap());
+        let num_chunks = u64::from_be_bytes(footer[8..16].try_into().unwrap());
+        let blocks_start = u64::from_be_bytes(footer[16..24].try_into().unwrap());
+        let table_size = num_chunks.checked_add(1).and_then(|n| n.checked_mul(8));
+        if table_size.and_then(|size| table_offset.checked_add(size)) != Some(index_len - FOOTER_SIZE) {
+            return Err(FastSeekError::CorruptIndex("invalid chunk table".to_string()));
+        }
+        let mut table = vec![0; ((num_chunks + 1) * 8) as usize];
+        index_reader.seek(table_offset)?;
+        index_reader.read_exact(&mut table)?;
+        let chunk_offsets: Vec<u64> = table.chunks(8).map(|bytes| u64::from_be_bytes(bytes.try_into().unwrap())).collect();
+        return Ok(CompressedBlocks{chunk_offsets, blocks_start, chunk_index: usize::MAX, chunk: vec![]});
+    }
+
+    //File offset where the block area starts, which is also where a compressed hashmap ends
+    pub fn first_chunk_offset(&self) -> u64{
+        return self.chunk_offsets[0];
+    }
+
+    //Fill buffer with the uncompressed block area bytes starting at address
+    pub fn read(&mut self, index_reader: &mut StandardFileReader, mut address: u64, buffer: &mut [u8]) -> Result<()>{
+        if address < self.blocks_start {
+            return Err(FastSeekError::CorruptIndex(format!("invalid block address {}", address)));
+        }
+        let mut filled = 0;
+        while filled < buffer.len() {
+            let relative = address - self.blocks_start;
+            let chunk_index = (relative / BLOCK_CHUNK_SIZE as u64) as usize;
+            let in_chunk = (relative % BLOCK_CHUNK_SIZE as u64) as usize;
+            self.load_chunk(index_reader, chunk_index)?;
+            if in_chunk >= self.chunk.len() {
+                return Err(FastSeekError::CorruptIndex(format!("invalid block address {}", address)));
+            }
+            let n = (buffer.len() - filled).min(self.chunk.len() - in_chunk);
+            buffer[filled..filled+n].copy_from_slice(&self.chunk[in_chunk..in_chunk+n]);
+            filled += n;
+            address += n as u64;
+        }
+        return Ok(());
+    }
+
+    fn load_chunk(&mut self, index_reader: &mut StandardFileReader, chunk_index: usize) -> Result<()>{
+        if chunk_index == self.chunk_index {
+            return Ok(());
+        }
+        if chunk_index + 1 >= self.chunk_offsets.len() {
+            return Err(FastSeekError::CorruptIndex(format!("invalid block chunk {}", chunk_index)));
+        }
+        let start = self.chunk_offsets[chunk_index];
+        let end = self.chunk_offsets[chunk_index + 1];
+        if end < start {
+            return Err(FastSeekError::CorruptIndex(format!("invalid block chunk {}", chunk_index)));
+        }
+        let mut compressed = vec![0; (end - start) as usize];
+        index_reader.seek(start)?;
+        index_reader.read_exact(&mut compressed)?;
+        self.chunk.clear();
+        inflate(&compressed, &mut self.chunk)?;
+        self.chunk_index = chunk_index;
+        return Ok(());
+    }
+}
+
+//Decompress the whole hashmap of an index with FLAG_COMPRESSED_HASHMAP
+pub fn load_compressed_hashmap(index_reader: &mut StandardFileReader, header: &Header, header_size: u64, hashmap_end: u64) -> Result<Vec<u8>>{
+    let mut compressed = vec![0; hashmap_end.saturating_sub(header_size) as usize];
+    index_reader.seek(header_size)?;
+    index_reader.read_exact(&mut compressed)?;
+    let mut hashmap = Vec::with_capacity((header.hashmap_size * HASHMAP_ENTRY_SIZE as u64) as usize);
+    inflate(&compressed, &mut hashmap)?;
+    if hashmap.len() as u64 != header.hashmap_size * HASHMAP_ENTRY_SIZE as u64 {
+        return Err(FastSeekError::CorruptIndex("invalid compressed hashmap size".to_string()));
+    }
+    return Ok(hashmap);
+}
+
+fn inflate(compressed: &[u8], output: &mut Vec<u8>) -> Result<()>{
+    return match DeflateDecoder::new(compressed).read_to_end(output) {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::InvalidInput || e.kind() == io::ErrorKind::InvalidData => {
+            Err(FastSeekError::CorruptIndex(format!("invalid compressed data: {}", e)))
+        }
+        Err(e) => Err(e.into()),
+    };
+}
@@ -0,0 +1,135 @@
+use std::fs;
+use std::io;
+use crate::error::{with_filename, FastSeekError, Result};
+use crate::file_reader::{FileReader, StandardFileReader};
+use crate::header::{header_size, Header, HEADER_PREFIX_SIZE, FLAG_COMPRESSED_BLOCKS, FLAG_COMPRESSED_HASHMAP, FLAG_CONTIGUOUS_BLOCKS};
+use crate::index_compression::{load_compressed_hashmap, CompressedBlocks};
+use crate::index_structure::{IndexEntry, IndexEntryType, CONTINUATION_BIT, HASHMAP_ENTRY_SIZE};
+
+//Reads an index file, whatever its collision layout and compression
+pub struct IndexReader{
+    index_reader: StandardFileReader,
+    pub header: Header,
+    //Size of the header in the file, which may be older than the fields Header writes
+    pub header_size: u64,
+    //Whole hashmap, if compressed
+    hashmap: Option<Vec<u8>>,
+    //Block area, if compressed
+    compressed_blocks: Option<CompressedBlocks>
+}
+
+impl IndexReader{
+    pub fn open(path: &str) -> Result<IndexReader>{
+        let mut index_reader = StandardFileReader::new(path)?;
+        //Read the header size
+        let mut prefix = [0; HEADER_PREFIX_SIZE];
+        read_index(&mut index_reader, &mut prefix)?;
+        let header_size = header_size(&prefix)?;
+        index_reader.seek(0)?;
+        let mut buf: Vec<u8> = vec![0; header_size as usize];
+        read_index(&mut index_reader, &mut buf)?;
+        let header = Header::from_bytes(buf)?;
+
+        let mut reader = IndexReader{index_reader, header, header_size, hashmap: None, compressed_blocks: None};
+        if reader.header.has_flag(FLAG_COMPRESSED_BLOCKS){
+            let index_len = fs::metadata(path).map_err(|e| with_filename(e, "read", path))?.len();
+            let compressed_blocks = CompressedBlocks::open(&mut reader.index_reader, index_len).map_err(truncated_as_corrupt)?;
+            if reader.header.has_flag(FLAG_COMPRESSED_HASHMAP){
+                let hashmap = load_compressed_hashmap(
+                    &mut reader.index_reader, &reader.header, reader.header_size, compressed_blocks.first_chunk_offset()
+                ).map_err(truncated_as_corrupt)?;
+                reader.hashmap = Some(hashmap);
+            }
+            reader.compressed_blocks = Some(compressed_blocks);
+        }
+        return Ok(reader);
+    }
+
+    pub fn get_hashmap_entry(&mut self, hash: u64) -> Result<IndexEntry>{
+        if hash >= self.header.hashmap_size {
+            return Err(FastSeekError::CorruptIndex(format!("hash {} out of hashmap", hash)));
+        }
+        let mut buffer = [0; 8];
+        match &self.hashmap {
+            Some(hashmap) => {
+                let start = (hash * HASHMAP_ENTRY_SIZE as u64) as usize;
+                buffer.copy_from_slice(&hashmap[start..start+8]);
+            }
+            None => {
+                self.index_reader.seek(self.header_size + (hash * HASHMAP_ENTRY_SIZE as u64))?;
+                read_index(&mut self.index_reader, &mut buffer)?;
+            }
+        }
+        return Ok(IndexEntry::from_be_bytes(buffer));
+    }
+
+    //Read bytes of the block area at the given address
+    fn read_blocks(&mut self, address: u64, buffer: &mut [u8]) -> Result<()>{
+        return match self.compressed_blocks.as_mut() {
+            Some(compressed_blocks) => compressed_blocks.read(&mut self.index_reader, address, buffer).map_err(truncated_as_corrupt),
+            None => {
+                self.index_reader.seek(address)?;
+                read_index(&mut self.index_reader, buffer)
+            }
+        };
+    }
+
+    //Returns the offsets in the original file of all entries with the given hash, in file order.
+    pub fn get_candidate_offsets(&mut self, hash: u64) -> Result<Vec<u64>>{
+        let mut current_index = self.get_hashmap_entry(hash)?;
+        let mut offsets: Vec<u64> = vec![];
+        let mut buffer = [0; 8];
+        if self.header.has_flag(FLAG_CONTIGUOUS_BLOCKS) && current_index.get_type() == IndexEntryType::Indirect {
+            //Contiguous block, already in file order: read until the entry without continuation bit
+            let mut address = current_index.get_offset();
+            loop{
+                self.read_blocks(address, &mut buffer)?;
+                let entry = u64::from_be_bytes(buffer);
+                offsets.push(entry & !CONTINUATION_BIT);
+                if entry & CONTINUATION_BIT == 0 {
+                    return Ok(offsets);
+                }
+                address += 8;
+            }
+        }
+        //Linked lists are built head-first by IndexStructure::add_entry, so they must be sorted.
+        loop{
+            match current_index.get_type(){
+                IndexEntryType::NULL => {
+                    break;
+                }
+                IndexEntryType::Direct => {
+                    offsets.push(current_index.get_offset());
+                    break;
+                }
+                IndexEntryType::Indirect => {
+                    let mut buffer = [0; 16];
+                    let node_offset = current_index.get_offset();
+                    self.read_blocks(node_offset, &mut buffer)?;
+                    offsets.push(u64::from_be_bytes(buffer[0..8].try_into().unwrap()));
+                    current_index = IndexEntry::from_be_bytes(buffer[8..16].try_into().unwrap());
+                    //Nodes always point to nodes written before them: anything else is a loop
+                    if current_index.get_type() == IndexEntryType::Indirect && current_index.get_offset() >= node_offset {
+                        return Err(FastSeekError::CorruptIndex(format!("invalid chain pointer at offset {}", node_offset)));
+                    }
+                }
+            }
+        }
+        offsets.sort_unstable();
+        return Ok(offsets);
+    }
+}
+
+//Read from the index file, reporting a truncated file as a corrupt index
+fn read_index(index_reader: &mut StandardFileReader, buffer: &mut [u8]) -> Result<()>{
+    return index_reader.read_exact(buffer).map_err(truncated_as_corrupt);
+}
+
+fn truncated_as_corrupt(error: FastSeekError) -> FastSeekError{
+    return match error {
+        FastSeekError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+            FastSeekError::CorruptIndex("index file is truncated".to_string())
+        }
+        error => error,
+    };
+}
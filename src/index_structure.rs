@@ -1,14 +1,23 @@
+use crate::error::{with_filename, FastSeekError, Result};
 use crate::file_writer;
 use crate::header;
 use file_writer::FileWriter;
-use header::Header;
-use std::io::{self};
+use header::{header_size, Header, FLAG_CONTIGUOUS_BLOCKS, HEADER_PREFIX_SIZE, V1_FIXED_SIZE};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
 use std::cmp::min;
 
 pub const HASHMAP_ENTRY_SIZE: u8 = 8;
+//Contiguous blocks layout: set on every entry of a block except the last one
+pub const CONTINUATION_BIT: u64 = 0x8000000000000000;
 const BLOCK_BUFFER_SIZE:usize = 1024*50*8;
 //Allow reducing hashmap_size to a maximum of this percentage to remove the last iteration over file
 const HASHMAP_REDUCING_FACTOR: u64 = 10;//%
+//Contiguous blocks layout: bytes of colliding entries kept in memory, at most what in_memory_map_size leaves
+//to them and at least the minimum. Beyond it, they are sorted and written to a run file, merged at the end of the iteration
+const CONTIGUOUS_RUN_SIZE: u64 = 256*1024*1024;
+const MIN_CONTIGUOUS_RUN_SIZE: u64 = 64*1024;
+const CONTIGUOUS_ENTRY_SIZE: u64 = 16;
 
 #[derive(PartialEq, Eq)]
 pub enum IndexEntryType{
@@ -53,6 +62,7 @@ impl IndexEntry{
 pub struct IndexStructure{
     file_writer: FileWriter,
     pub header: Header,
+    pub blocks_start: u64,
     index_map: Vec<IndexEntry>,
     blocks_buffer: [u8; BLOCK_BUFFER_SIZE],
     block_first_free: u64,
@@ -60,12 +70,45 @@ pub struct IndexStructure{
     block_buffer_output_offset: u64,
     in_memory_map_size: u64,
     margin_l: u64,
-    margin_h: u64
+    margin_h: u64,
+    //Contiguous blocks layout: (hash, file offset) of colliding entries of the current iteration,
+    //written as contiguous blocks at the end of the iteration
+    contiguous_entries: Option<Vec<(u64, u64)>>,
+    //Entries kept in contiguous_entries before writing them to a run, and runs written in this iteration
+    contiguous_run_entries: usize,
+    contiguous_runs: RunFiles,
+    index_path: String,
+}
+
+//Colliding entries of the contiguous blocks layout, sorted by hash: kept in memory, or in a run file as
+//  hash (u64) | file offset (u64)
+enum ContiguousRun{
+    Memory(std::vec::IntoIter<(u64, u64)>),
+    File(io::BufReader<File>),
+}
+
+impl ContiguousRun{
+    fn next(&mut self) -> Result<Option<(u64, u64)>>{
+        let reader = match self {
+            ContiguousRun::Memory(entries) => return Ok(entries.next()),
+            ContiguousRun::File(reader) => reader,
+        };
+        let mut entry = [0; CONTIGUOUS_ENTRY_SIZE as usize];
+        match reader.read_exact(&mut entry) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        return Ok(Some((u64::from_be_bytes(entry[0..8].try_into().unwrap()), u64::from_be_bytes(entry[8..16].try_into().unwrap()))));
+    }
 }
 
 impl IndexStructure{
-    pub fn new(filename: String, mut header: Header, mut in_memory_map_size: u64) -> IndexStructure{
+    pub fn new(index_path: String, mut header: Header, mut in_memory_map_size: u64) -> Result<IndexStructure>{
         let hashmap_size = header.hashmap_size;
+        //Colliding entries of the contiguous blocks layout take the memory the hashmap leaves
+        let contiguous_run_size = (in_memory_map_size.saturating_sub(hashmap_size) * HASHMAP_ENTRY_SIZE as u64)
+            .clamp(MIN_CONTIGUOUS_RUN_SIZE, CONTIGUOUS_RUN_SIZE);
         in_memory_map_size = min(in_memory_map_size, hashmap_size);
         
         //Optimization: if in_map_size < hashmap_size, the program iterates
@@ -79,9 +122,12 @@ impl IndexStructure{
             }
         }
 
+        //Fails before creating the index if the header can't be written
+        let header_bytes = header.to_bytes()?;
         let mut structure =  IndexStructure{
-            file_writer: FileWriter::get_writer(format!("{}.index", filename)),
+            file_writer: FileWriter::get_writer(index_path.clone())?,
             header,
+            blocks_start:0,
             index_map: vec![],
             blocks_buffer: [0; BLOCK_BUFFER_SIZE],
             block_first_free:0,
@@ -89,33 +135,57 @@ impl IndexStructure{
             block_buffer_output_offset:0,
             in_memory_map_size:in_memory_map_size,
             margin_l:0,
-            margin_h:min(in_memory_map_size, hashmap_size)
+            margin_h:min(in_memory_map_size, hashmap_size),
+            contiguous_entries: None,
+            contiguous_run_entries: (contiguous_run_size / CONTIGUOUS_ENTRY_SIZE) as usize,
+            contiguous_runs: RunFiles{paths: vec![]},
+            index_path,
         };
+        if structure.header.has_flag(FLAG_CONTIGUOUS_BLOCKS){
+            structure.contiguous_entries = Some(vec![]);
+        }
 
         //Write header to index file
-        structure.file_writer.write_all(&(structure.header).to_bytes()).unwrap();
+        structure.file_writer.write_all(&header_bytes)?;
 
         //In-memory structure for the hashmap (TODO: might have to do on-disk)
         structure.index_map = (0..min(hashmap_size,in_memory_map_size)).map(|_| IndexEntry::new_null()).collect::<Vec<_>>();
 
         //Keep indexes where to write the blocks
-        let block_starting_address: u64 = (hashmap_size as u64 * HASHMAP_ENTRY_SIZE as u64) + (structure.header.get_header_size() as u64);
+        let block_starting_address: u64 = (hashmap_size * HASHMAP_ENTRY_SIZE as u64) + header_bytes.len() as u64;
+        structure.blocks_start = block_starting_address;
         structure.block_first_free = block_starting_address;
         structure.block_buffer_output_offset = block_starting_address;
         //Set buffer for block part of the index
         structure.blocks_buffer_used = 0;
-        let _ = structure.file_writer.seek(io::SeekFrom::Start(block_starting_address));
+        structure.file_writer.seek(io::SeekFrom::Start(block_starting_address))?;
 
-        return structure;
+        return Ok(structure);
     }
 
-    pub fn add_entry(&mut self, mut hash: u64, file_offset: u64){
+    pub fn add_entry(&mut self, mut hash: u64, file_offset: u64) -> Result<()>{
         if hash<self.margin_l || hash>= self.margin_h{
-            return;
+            return Ok(());
         }
         hash -= self.margin_l;
 
         let current_index = &self.index_map[hash as usize];
+        if let Some(contiguous_entries) = self.contiguous_entries.as_mut(){
+            //Blocks are written in next(), keep the entries and mark the hashmap entry as indirect
+            match current_index.get_type(){
+                IndexEntryType::NULL => {self.index_map[hash as usize] = IndexEntry::new_direct(file_offset);}
+                IndexEntryType::Indirect => {contiguous_entries.push((hash, file_offset));}
+                IndexEntryType::Direct => {
+                    contiguous_entries.push((hash, current_index.get_offset()));
+                    contiguous_entries.push((hash, file_offset));
+                    self.index_map[hash as usize] = IndexEntry::new_indirect(0);
+                }
+            }
+            if contiguous_entries.len() >= self.contiguous_run_entries {
+                self.write_contiguous_run()?;
+            }
+            return Ok(());
+        }
         match current_index.get_type(){
             IndexEntryType::NULL => {self.index_map[hash as usize] = IndexEntry::new_direct(file_offset);}
             IndexEntryType::Indirect => {
@@ -134,15 +204,18 @@ impl IndexStructure{
             }
         }
         if self.blocks_buffer_used == BLOCK_BUFFER_SIZE{
-            self.flush_block_buffer(BLOCK_BUFFER_SIZE);
+            self.flush_block_buffer(BLOCK_BUFFER_SIZE)?;
         }
+        return Ok(());
     }
 
-    pub fn next(&mut self) -> bool{
+    pub fn next(&mut self) -> Result<bool>{
+        //Blocks must be written before the hashmap, as they set its indirect entries
+        self.write_contiguous_blocks()?;
         //Write hashmap from memory to file
         self.file_writer.seek(io::SeekFrom::Start(
-            self.header.get_header_size() as u64 + (self.margin_l*(HASHMAP_ENTRY_SIZE as u64))
-        )).unwrap();
+            self.header.get_header_size()? + (self.margin_l*(HASHMAP_ENTRY_SIZE as u64))
+        ))?;
         let buf_capacity = 1080*8;
         let mut buffer: Vec<u8> = Vec::with_capacity(buf_capacity);
         //Using unsafe for direct pointer access and faster runtime
@@ -162,7 +235,7 @@ impl IndexStructure{
                     );
                 }
             }
-            self.file_writer.write_all(&buffer[..bytes_needed]).unwrap();
+            self.file_writer.write_all(&buffer[..bytes_needed])?;
         }
 
         self.margin_l = self.margin_h;
@@ -171,17 +244,135 @@ impl IndexStructure{
         if keep_on{
             self.index_map = (0..self.in_memory_map_size).map(|_| IndexEntry::new_null()).collect::<Vec<_>>();
         }
-        let _ = self.file_writer.seek(io::SeekFrom::Start(self.block_buffer_output_offset));
+        self.file_writer.seek(io::SeekFrom::Start(self.block_buffer_output_offset))?;
         if self.blocks_buffer_used > 0 {
-            self.flush_block_buffer(self.blocks_buffer_used);
+            self.flush_block_buffer(self.blocks_buffer_used)?;
+        }
+        if !keep_on {
+            self.file_writer.flush()?;
+        }
+        return Ok(keep_on)
+    }
+
+    //Sort the colliding entries kept in memory and write them to a run file
+    fn write_contiguous_run(&mut self) -> Result<()>{
+        let contiguous_entries = match self.contiguous_entries.as_mut(){
+            Some(contiguous_entries) => contiguous_entries,
+            None => return Ok(()),
+        };
+        //Stable sort: entries of the same hash stay in file order
+        contiguous_entries.sort_by_key(|(hash, _)| *hash);
+        //Recorded before writing it, so that it is removed even if writing fails
+        let path = format!("{}.run{}", self.index_path, self.contiguous_runs.paths.len());
+        self.contiguous_runs.paths.push(path.clone());
+        let mut writer = FileWriter::get_writer(path)?;
+        for (hash, file_offset) in contiguous_entries.drain(..){
+            writer.write_all(&hash.to_be_bytes())?;
+            writer.write_all(&file_offset.to_be_bytes())?;
+        }
+        writer.flush()?;
+        return Ok(());
+    }
+
+    //Write the colliding entries of the current iteration as contiguous blocks, in file order
+    fn write_contiguous_blocks(&mut self) -> Result<()>{
+        let mut contiguous_entries = match self.contiguous_entries.take(){
+            Some(contiguous_entries) => contiguous_entries,
+            None => return Ok(()),
+        };
+        //Runs in file order, the entries still in memory last
+        let mut runs: Vec<ContiguousRun> = vec![];
+        for path in self.contiguous_runs.paths.iter(){
+            runs.push(ContiguousRun::File(io::BufReader::new(File::open(path).map_err(|e| with_filename(e, "open", path))?)));
+        }
+        contiguous_entries.sort_by_key(|(hash, _)| *hash);
+        runs.push(ContiguousRun::Memory(contiguous_entries.into_iter()));
+        let mut heads: Vec<Option<(u64, u64)>> = vec![];
+        for run in runs.iter_mut(){
+            heads.push(run.next()?);
+        }
+        //Entry waiting for the next one, which tells if it is the last of its block
+        let mut pending: Option<(u64, u64)> = None;
+        loop{
+            //Smallest hash, from the earliest run on ties so that entries of the same hash stay in file order
+            let mut smallest: Option<usize> = None;
+            for (i, head) in heads.iter().enumerate(){
+                if let Some((hash, _)) = head {
+                    if smallest.is_none() || *hash < heads[smallest.unwrap()].unwrap().0 {
+                        smallest = Some(i);
+                    }
+                }
+            }
+            let next = match smallest {
+                Some(i) => {
+                    let next = heads[i].take();
+                    heads[i] = runs[i].next()?;
+                    next
+                }
+                None => None,
+            };
+            if let Some((hash, file_offset)) = pending {
+                let entry = match next {
+                    Some((next_hash, _)) if next_hash == hash => file_offset | CONTINUATION_BIT,
+                    _ => file_offset,
+                };
+                self.blocks_buffer[self.blocks_buffer_used..self.blocks_buffer_used+8].copy_from_slice(&entry.to_be_bytes());
+                self.block_first_free += 8;
+                self.blocks_buffer_used += 8;
+                if self.blocks_buffer_used == BLOCK_BUFFER_SIZE{
+                    self.flush_block_buffer(BLOCK_BUFFER_SIZE)?;
+                }
+            }
+            let (hash, file_offset) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            if pending.map(|(pending_hash, _)| pending_hash) != Some(hash) {
+                self.index_map[hash as usize] = IndexEntry::new_indirect(self.block_first_free);
+            }
+            pending = Some((hash, file_offset));
         }
-        return keep_on
+        for path in self.contiguous_runs.paths.drain(..){
+            fs::remove_file(&path).map_err(|e| with_filename(e, "remove", &path))?;
+        }
+        self.contiguous_entries = Some(vec![]);
+        return Ok(());
     }
 
-    pub fn flush_block_buffer(&mut self, to: usize){
-        let _ = self.file_writer.write_all(&self.blocks_buffer[..to]);
+    pub fn flush_block_buffer(&mut self, to: usize) -> Result<()>{
+        self.file_writer.write_all(&self.blocks_buffer[..to])?;
         self.blocks_buffer_used = 0;
         self.block_buffer_output_offset += to as u64;
+        return Ok(());
+    }
+
+}
+
+//Run files of an iteration, removed when dropped: they are still there only if indexing failed before merging them
+struct RunFiles{
+    paths: Vec<String>
+}
+impl Drop for RunFiles{
+    fn drop(&mut self){
+        for path in self.paths.iter(){
+            let _ = fs::remove_file(path);
+        }
     }
+}
 
-}
\ No newline at end of file
+//Writes the fixed part of header, with the flags and the fingerprint, over the one of the index at index_path,
+//and syncs it to disk. It fits in the first sector of the file, so it is written whole or not at all. The rest of
+//the header must be unchanged.
+pub fn rewrite_header(index_path: &str, header: &Header) -> Result<()>{
+    let bytes = header.to_bytes()?;
+    let mut file = OpenOptions::new().read(true).write(true).open(index_path).map_err(|e| with_filename(e, "open", index_path))?;
+    let mut old_bytes = vec![0; bytes.len()];
+    io::Read::read_exact(&mut file, &mut old_bytes)?;
+    if header_size(&old_bytes[..HEADER_PREFIX_SIZE])? != bytes.len() as u64 || old_bytes[V1_FIXED_SIZE..] != bytes[V1_FIXED_SIZE..] {
+        return Err(FastSeekError::CorruptIndex(format!("header of {} changed other than in its fixed part", index_path)));
+    }
+    file.seek(io::SeekFrom::Start(0))?;
+    file.write_all(&bytes[..V1_FIXED_SIZE])?;
+    file.sync_all()?;
+    return Ok(());
+}
@@ -0,0 +1,340 @@
+use std::borrow::Cow;
+use std::str::{Split, SplitAsciiWhitespace};
+use crate::error::{FastSeekError, Result};
+use crate::header::{NORMALIZE_ASCII_LOWERCASE, NORMALIZE_LINE_END, NORMALIZE_STRIP_QUOTES, NORMALIZE_TRIM, NORMALIZE_UNICODE_LOWERCASE};
+
+//Separator of the fields of tabular records
+#[derive(Clone, PartialEq, Debug)]
+pub enum Separator{
+    Text(String),
+    //Any run of blanks, leading and trailing ones ignored (as awk does)
+    Whitespace,
+}
+
+impl Separator{
+    pub fn split<'s, 'a>(&'s self, record: &'a str) -> Fields<'s, 'a>{
+        return match self {
+            Separator::Text(separator) => Fields::Text(record.split(separator.as_str())),
+            Separator::Whitespace => Fields::Whitespace(record.split_ascii_whitespace()),
+        };
+    }
+
+    //Joins the fields of composite keys
+    pub fn as_str(&self) -> &str{
+        return match self {
+            Separator::Text(separator) => separator,
+            Separator::Whitespace => " ",
+        };
+    }
+}
+
+//Fields of a record, split by a Separator
+pub enum Fields<'s, 'a>{
+    Text(Split<'a, &'s str>),
+    Whitespace(SplitAsciiWhitespace<'a>),
+}
+
+impl<'a> Iterator for Fields<'_, 'a>{
+    type Item = &'a str;
+    fn next(&mut self) -> Option<&'a str>{
+        return match self {
+            Fields::Text(fields) => fields.next(),
+            Fields::Whitespace(fields) => fields.next(),
+        };
+    }
+}
+
+//Normalisation of keys, applied in the same way to the keys of records and to searched keys.
+//Recorded in the index header.
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct KeyNormalization{
+    //Remove the whole line terminator, "\r\n" included. Without it, only its last character is removed,
+    //and keys are compared after trimming them, as indexes built before normalisation existed expect.
+    pub line_end: bool,
+    pub trim: bool,
+    //Remove a pair of matching quotes, '"' or '\'', enclosing the key
+    pub strip_quotes: bool,
+    pub case: CaseFolding,
+}
+
+/// Case of keys ignored when indexing and searching
+#[derive(Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum CaseFolding{
+    /// Keys are case sensitive
+    #[default]
+    None,
+    /// ASCII letters are lowercased
+    Ascii,
+    /// Every letter is lowercased, as defined by Unicode
+    Unicode,
+}
+
+impl KeyNormalization{
+    pub fn to_bits(self) -> u8{
+        let mut bits = 0;
+        if self.line_end {
+            bits |= NORMALIZE_LINE_END;
+        }
+        if self.trim {
+            bits |= NORMALIZE_TRIM;
+        }
+        if self.strip_quotes {
+            bits |= NORMALIZE_STRIP_QUOTES;
+        }
+        bits |= match self.case {
+            CaseFolding::None => 0,
+            CaseFolding::Ascii => NORMALIZE_ASCII_LOWERCASE,
+            CaseFolding::Unicode => NORMALIZE_UNICODE_LOWERCASE,
+        };
+        return bits;
+    }
+
+    //Bits are validated when reading the header
+    pub fn from_bits(bits: u8) -> KeyNormalization{
+        return KeyNormalization{
+            line_end: bits & NORMALIZE_LINE_END != 0,
+            trim: bits & NORMALIZE_TRIM != 0,
+            strip_quotes: bits & NORMALIZE_STRIP_QUOTES != 0,
+            case: match (bits & NORMALIZE_ASCII_LOWERCASE != 0, bits & NORMALIZE_UNICODE_LOWERCASE != 0) {
+                (_, true) => CaseFolding::Unicode,
+                (true, false) => CaseFolding::Ascii,
+                (false, false) => CaseFolding::None,
+            },
+        };
+    }
+
+    //Applies trimming, quote stripping and case folding, in this order
+    fn apply<'a>(&self, key: Cow<'a, str>) -> Cow<'a, str>{
+        let mut key = key;
+        if self.trim {
+            key = map_borrowed(key, str::trim);
+        }
+        if self.strip_quotes {
+            key = map_borrowed(key, strip_quotes);
+        }
+        return match self.case {
+            CaseFolding::Ascii if key.bytes().any(|byte| byte.is_ascii_uppercase()) => Cow::Owned(key.to_ascii_lowercase()),
+            CaseFolding::Unicode if key.chars().any(|c| c.is_uppercase()) => Cow::Owned(key.to_lowercase()),
+            _ => key,
+        };
+    }
+
+    fn strip_line_end<'a>(&self, key: &'a str) -> &'a str{
+        if !self.line_end {
+            return strip_line_end(key);
+        }
+        let key = key.strip_suffix('\n').unwrap_or(key);
+        return key.strip_suffix('\r').unwrap_or(key);
+    }
+}
+
+//Extracts the indexed key from a record read by an InputReader, and normalises it.
+//Kept apart from the readers so that keys can be extracted and hashed on other threads.
+#[derive(Clone)]
+pub struct KeyExtractor{
+    kind: KeyKind,
+    normalization: KeyNormalization,
+}
+
+//Part of the record the key is made of
+#[derive(Clone)]
+pub enum KeyKind{
+    //Columns of a tabular line. With more than one, the key is the fields joined by the separator
+    Columns{separator: Separator, columns: Vec<usize>},
+    //Columns of a csv record (RFC 4180): fields may be enclosed in quotes, and then contain the separator,
+    //line breaks and quotes, escaped by doubling them. The key is made of the fields without quotes.
+    QuotedColumns{separator: String, columns: Vec<usize>, quote: char},
+    //Line of a multi-line record: 0 for the header, 1 for the sequence
+    Line(usize),
+    //Lines following the header line, concatenated (wrapped fasta sequences)
+    Sequence,
+    //Nth whitespace-separated token of the header line, without the leading '>' or '@'
+    IdToken(usize),
+}
+
+impl KeyExtractor{
+    pub fn new(kind: KeyKind, normalization: KeyNormalization) -> KeyExtractor{
+        return KeyExtractor{kind, normalization};
+    }
+
+    //Returns the normalised key of record, without its line terminator.
+    //The fields of composite keys are normalised one by one.
+    //line is the line of the input file where the record starts, used to report errors.
+    pub fn extract<'a>(&self, record: &'a str, line: u64) -> Result<Cow<'a, str>>{
+        let key = match &self.kind {
+            KeyKind::Columns{separator, columns} if columns.len() == 1 => match separator.split(record).nth(columns[0]) {
+                Some(key) => key,
+                None => return Err(FastSeekError::MissingColumn{line, column: columns[0]}),
+            },
+            KeyKind::Columns{separator, columns} => {
+                let fields: Vec<&str> = separator.split(record).collect();
+                let mut key = String::with_capacity(record.len());
+                for (i, &column) in columns.iter().enumerate() {
+                    let field = match fields.get(column) {
+                        Some(field) => field,
+                        None => return Err(FastSeekError::MissingColumn{line, column}),
+                    };
+                    if i > 0 {
+                        key.push_str(separator.as_str());
+                    }
+                    key.push_str(&self.normalization.apply(Cow::Borrowed(self.normalization.strip_line_end(field))));
+                }
+                return Ok(Cow::Owned(key));
+            }
+            KeyKind::QuotedColumns{separator, columns, quote} => {
+                let fields = match split_quoted(record, separator, *quote) {
+                    Ok(fields) => fields,
+                    Err(reason) => return Err(FastSeekError::MalformedRecord{line, reason}),
+                };
+                let mut key = String::with_capacity(record.len());
+                for (i, &column) in columns.iter().enumerate() {
+                    let field = match fields.get(column) {
+                        Some(field) => field,
+                        None => return Err(FastSeekError::MissingColumn{line, column}),
+                    };
+                    if i > 0 {
+                        key.push_str(separator);
+                    }
+                    key.push_str(&self.normalization.apply(Cow::Borrowed(field)));
+                }
+                return Ok(Cow::Owned(key));
+            }
+            KeyKind::Line(n) => record.split_inclusive('\n').nth(*n).unwrap_or(""),
+            KeyKind::IdToken(n) => match id_token(record.lines().next().unwrap_or(""), *n) {
+                Some(key) => key,
+                None => return Err(FastSeekError::MalformedRecord{line, reason: format!("header line has no token {}", n)}),
+            },
+            KeyKind::Sequence => {
+                let mut lines = record.lines().skip(1).filter(|line| !line.is_empty());
+                let first = lines.next().unwrap_or("");
+                return Ok(self.normalization.apply(match lines.next() {
+                    None => Cow::Borrowed(first),
+                    Some(second) => {
+                        let mut sequence = String::with_capacity(record.len());
+                        sequence.push_str(first);
+                        sequence.push_str(second);
+                        lines.for_each(|line| sequence.push_str(line));
+                        Cow::Owned(sequence)
+                    }
+                }));
+            }
+        };
+        return Ok(self.normalization.apply(Cow::Borrowed(self.normalization.strip_line_end(key))));
+    }
+
+    //True if key, extracted from a record, matches query, normalised by normalize_query.
+    //Without recorded normalisation, keys are trimmed and composite keys compared field by field.
+    //Quoted fields keep their whitespace, so they are compared as they are.
+    pub fn key_matches(&self, key: &str, query: &str) -> bool{
+        if self.normalization != KeyNormalization::default() {
+            return key == query;
+        }
+        return match &self.kind {
+            KeyKind::QuotedColumns{..} => key == query,
+            KeyKind::Columns{separator, columns} if columns.len() > 1 => {
+                separator.split(key).map(str::trim).eq(separator.split(query))
+            }
+            _ => key.trim() == query,
+        };
+    }
+
+    //Applies to a searched key the normalisation applied to the records' keys.
+    //With IdToken, a whole header line (starting with '>' or '@') is reduced to its token,
+    //anything else is taken as the token itself.
+    pub fn normalize_query<'a>(&self, query: &'a str) -> Cow<'a, str>{
+        let normalization = &self.normalization;
+        return match &self.kind {
+            KeyKind::IdToken(n) if query.starts_with(['>', '@']) => normalization.apply(Cow::Borrowed(id_token(query, *n).unwrap_or(""))),
+            KeyKind::IdToken(_) => normalization.apply(Cow::Borrowed(query.trim())),
+            KeyKind::Columns{separator, columns} if columns.len() > 1 && *normalization == KeyNormalization::default() => {
+                Cow::Owned(separator.split(query).map(str::trim).collect::<Vec<&str>>().join(separator.as_str()))
+            }
+            KeyKind::Columns{separator, columns} if columns.len() > 1 => {
+                let fields: Vec<Cow<str>> = separator.split(query).map(|field| normalization.apply(Cow::Borrowed(field))).collect();
+                Cow::Owned(fields.join(separator.as_str()))
+            }
+            KeyKind::QuotedColumns{separator, columns, ..} if columns.len() > 1 => {
+                let fields: Vec<Cow<str>> = query.split(separator.as_str()).map(|field| normalization.apply(Cow::Borrowed(field))).collect();
+                Cow::Owned(fields.join(separator))
+            }
+            _ => normalization.apply(Cow::Borrowed(query)),
+        };
+    }
+}
+
+//Applies f, returning a slice of key, without copying borrowed keys
+fn map_borrowed<'a>(key: Cow<'a, str>, f: fn(&str) -> &str) -> Cow<'a, str>{
+    return match key {
+        Cow::Borrowed(key) => Cow::Borrowed(f(key)),
+        Cow::Owned(key) => Cow::Owned(f(&key).to_string()),
+    };
+}
+
+fn strip_quotes(key: &str) -> &str{
+    for quote in ['"', '\''] {
+        if key.len() >= 2 && key.starts_with(quote) && key.ends_with(quote) {
+            return &key[1..key.len() - 1];
+        }
+    }
+    return key;
+}
+
+fn id_token(header: &str, n: usize) -> Option<&str>{
+    return header.strip_prefix(['>', '@']).unwrap_or(header).split_whitespace().nth(n);
+}
+
+//Fields of a csv record, without their quotes. Returns why the quoting is malformed, if it is.
+pub fn split_quoted<'a>(record: &'a str, separator: &str, quote: char) -> std::result::Result<Vec<Cow<'a, str>>, String>{
+    let record = record.strip_suffix('\n').unwrap_or(record);
+    let record = record.strip_suffix('\r').unwrap_or(record);
+    let mut fields = vec![];
+    let mut rest = record;
+    loop {
+        let quoted = match rest.strip_prefix(quote) {
+            Some(quoted) => quoted,
+            //Unquoted field, up to the next separator
+            None => match rest.split_once(separator) {
+                Some((field, next)) => {
+                    fields.push(Cow::Borrowed(field));
+                    rest = next;
+                    continue;
+                }
+                None => {
+                    fields.push(Cow::Borrowed(rest));
+                    return Ok(fields);
+                }
+            },
+        };
+        //Quoted field, up to the next quote that isn't doubled
+        let mut field = String::new();
+        let mut remaining = quoted;
+        loop {
+            let end = match remaining.find(quote) {
+                Some(end) => end,
+                None => return Err("unterminated quoted field".to_string()),
+            };
+            field.push_str(&remaining[..end]);
+            remaining = &remaining[end + quote.len_utf8()..];
+            match remaining.strip_prefix(quote) {
+                Some(after_escape) => {
+                    field.push(quote);
+                    remaining = after_escape;
+                }
+                None => break,
+            }
+        }
+        fields.push(Cow::Owned(field));
+        if remaining.is_empty() {
+            return Ok(fields);
+        }
+        rest = match remaining.strip_prefix(separator) {
+            Some(next) => next,
+            None => return Err("unexpected character after quoted field".to_string()),
+        };
+    }
+}
+
+//Only the last character is removed, as keys have always been hashed this way
+fn strip_line_end(key: &str) -> &str{
+    return key.strip_suffix(['\n', '\r']).unwrap_or(key);
+}
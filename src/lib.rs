@@ -0,0 +1,100 @@
+//! FastSeek: indexer and search tool for tabular data (csv, tsv), multi-fasta and fastQ files.
+//!
+//! Build an index with [`IndexBuilder`], then open it with [`Searcher`] to look up records by key:
+//! ```no_run
+//! use fastseek::{Format, IndexBuilder, Searcher};
+//!
+//! # fn main() -> fastseek::Result<()> {
+//! IndexBuilder::new("data.csv", Format::Tabular).column(1).separator(",").build()?;
+//! let mut searcher = Searcher::open("data.csv")?;
+//! let records: Vec<String> = searcher.search("key")?;
+//! # Ok(())
+//! # }
+//! ```
+
+mod bgzf;
+mod builder;
+mod error;
+mod file_reader;
+mod file_writer;
+mod fingerprint;
+mod gzip_checkpoints;
+mod header;
+mod index_compression;
+mod index_reader;
+mod index_structure;
+mod key_extractor;
+mod ordered_index;
+mod pipeline;
+mod searcher;
+mod updater;
+mod zstd_seekable;
+
+pub use bgzf::bgzip;
+pub use builder::{Format, IndexBuilder, IndexCompression, KeyOrder};
+pub use error::{FastSeekError, Result};
+pub use key_extractor::CaseFolding;
+pub use searcher::{Match, Searcher};
+pub use updater::update_index;
+pub use zstd_seekable::zstd_seekable;
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use stable_hash::fast_stable_hash;
+
+const CURRENT_VERSION: u8 = 1;
+
+/// Default path of the index of `filename`: `<filename>.index`, or `<filename>.<name>.index` for a named index.
+/// With `index_dir`, the index is in that directory instead of the one of `filename`.
+pub fn index_path(filename: &str, index_dir: Option<&str>, name: Option<&str>) -> String {
+    let index_filename = match name {
+        Some(name) => format!("{}.{}.index", filename, name),
+        None => format!("{}.index", filename),
+    };
+    return match index_dir {
+        Some(index_dir) => {
+            let index_filename = Path::new(&index_filename).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or(index_filename);
+            Path::new(index_dir).join(index_filename).to_string_lossy().to_string()
+        }
+        None => index_filename,
+    };
+}
+
+//Path of filename to record in the header of the index at index_path: relative to the directory of the index
+//if the file is in the same directory, so that the two can be moved together, absolute otherwise
+fn recorded_source_path(filename: &str, index_path: &str) -> String {
+    let source_dir = canonical_directory(filename);
+    if source_dir.is_some() && source_dir == canonical_directory(index_path) {
+        if let Some(name) = Path::new(filename).file_name() {
+            return name.to_string_lossy().to_string();
+        }
+    }
+    return match fs::canonicalize(filename) {
+        Ok(path) => path.to_string_lossy().to_string(),
+        Err(_) => filename.to_string(),
+    };
+}
+
+//Path of the source file recorded in the index at index_path, as written by recorded_source_path
+fn resolve_source_path(index_path: &str, recorded: &str) -> String {
+    if Path::new(recorded).is_absolute() {
+        return recorded.to_string();
+    }
+    return match Path::new(index_path).parent() {
+        Some(index_dir) => index_dir.join(recorded).to_string_lossy().to_string(),
+        None => recorded.to_string(),
+    };
+}
+
+fn canonical_directory(path: &str) -> Option<PathBuf> {
+    let directory = match Path::new(path).parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    return fs::canonicalize(directory).ok();
+}
+
+fn hash_function(value: &str, hashmap_size: u128) -> u64 {
+    let r = fast_stable_hash(&value) % hashmap_size;
+    return r as u64;
+}
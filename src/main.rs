@@ -1,299 +1,475 @@
-mod file_reader;
-mod file_writer;
-mod header;
-mod index_structure;
+mod command_line_tool;
+mod self_test;
 
 use std::fs::File;
-use std::io::{self};
-use std::path::Path;
-use std::io::Write;
-use std::str::from_utf8;
-use std::vec;
-use bgzip::{BGZFWriter, Compression};
+use std::io::{self, BufRead};
+use std::process::exit;
 use clap::Parser;
-use stable_hash::fast_stable_hash;
-use file_reader::{FastqInputReader, FileReader, GzFileReader, InputReader, MultiFastaInputReader, StandardFileReader, TabularInputReader};
-use index_structure::{IndexStructure, IndexEntry, IndexEntryType, HASHMAP_ENTRY_SIZE};
+use command_line_tool::{BgzipIndex, CaseArg, Cli, Commands, CompressionArg, FormatArg, IndexOptions, OrderArg, SequenceArgs, TabularArgs};
+use fastseek::{bgzip, index_path, update_index, zstd_seekable, CaseFolding, FastSeekError, Format, IndexBuilder, IndexCompression, KeyOrder, Match, Result, Searcher};
+
+//Exit codes, so that pipelines can tell a missing key from a broken index.
+//2 is also used by clap for invalid command lines.
+const EXIT_NOT_FOUND: i32 = 1;
+const EXIT_INVALID_ARGUMENT: i32 = 2;
+const EXIT_IO: i32 = 3;
+const EXIT_CORRUPT_INDEX: i32 = 4;
+const EXIT_UNSUPPORTED_VERSION: i32 = 5;
+const EXIT_MALFORMED_RECORD: i32 = 6;
+const EXIT_MISSING_COLUMN: i32 = 7;
+const EXIT_STALE_INDEX: i32 = 8;
+
+fn exit_code(error: &FastSeekError) -> i32{
+    return match error {
+        FastSeekError::Io(_) => EXIT_IO,
+        FastSeekError::CorruptIndex(_) => EXIT_CORRUPT_INDEX,
+        FastSeekError::UnsupportedVersion(_) => EXIT_UNSUPPORTED_VERSION,
+        FastSeekError::MalformedRecord{..} => EXIT_MALFORMED_RECORD,
+        FastSeekError::MissingColumn{..} => EXIT_MISSING_COLUMN,
+        FastSeekError::InvalidArgument(_) => EXIT_INVALID_ARGUMENT,
+        FastSeekError::StaleIndex(_) => EXIT_STALE_INDEX,
+    };
+}
 
+fn index_compression(compression: CompressionArg) -> IndexCompression{
+    return match compression {
+        CompressionArg::None => IndexCompression::None,
+        CompressionArg::Blocks => IndexCompression::Blocks,
+        CompressionArg::Full => IndexCompression::Full,
+    };
+}
 
-const CURRENT_VERSION: u8 = 0;
+fn case_folding(ignore_case: Option<CaseArg>) -> CaseFolding{
+    return match ignore_case {
+        None => CaseFolding::None,
+        Some(CaseArg::Ascii) => CaseFolding::Ascii,
+        Some(CaseArg::Unicode) => CaseFolding::Unicode,
+    };
+}
 
+//Sets the order of the ordered index of builder, if any
+fn with_order(builder: IndexBuilder, ordered: Option<OrderArg>) -> IndexBuilder{
+    return match ordered {
+        None => builder,
+        Some(OrderArg::Lexicographic) => builder.ordered(KeyOrder::Lexicographic),
+        Some(OrderArg::Numeric) => builder.ordered(KeyOrder::Numeric),
+    };
+}
 
-fn hash_function(value: &str, hashmap_size: u128) -> u64 {
-    let r = fast_stable_hash(&value) % hashmap_size;
-    return r as u64;
+//Builder of the index of filename, with the options shared by every format
+fn index_builder(filename: &str, format: Format, options: &IndexOptions) -> IndexBuilder{
+    let builder = IndexBuilder::new(filename, format)
+        .command_line(&command_line())
+        .hashmap_size(options.hashmap_size)
+        .in_memory_map_size(options.in_memory_map_size)
+        .contiguous_blocks(options.contiguous_blocks)
+        .compression(index_compression(options.compress_index))
+        .threads(options.threads)
+        .trim_keys(options.trim)
+        .case_folding(case_folding(options.ignore_case))
+        .strip_quotes(options.strip_quotes);
+    return with_order(builder, options.ordered);
 }
 
-fn index(input_reader: &mut impl InputReader, filename: String, mut hashmap_size: u128, in_memory_map_size: u64) {
-    //If unspecified, set hashmap_size to number of lines
-    if hashmap_size == 0 {
-        hashmap_size = input_reader.num_entries() as u128;
+//Open the searcher of filename, with the index at index or else the one named index_name in index_dir.
+//Warns if the file was modified after indexing.
+fn open_searcher(filename: Option<String>, index: Option<String>, index_dir: Option<String>, index_name: Option<String>) -> Result<Searcher>{
+    let index = match (index, &filename) {
+        (Some(index), _) => index,
+        (None, Some(filename)) => index_path(filename, index_dir.as_deref(), index_name.as_deref()),
+        (None, None) => return Err(FastSeekError::InvalidArgument("either a filename or --index is needed".to_string())),
+    };
+    let searcher = Searcher::open_index(&index, filename.as_deref())?;
+    if searcher.source_modified() {
+        eprintln!("Warning: {} was modified after the index was built, run `fastseek verify` to check the index", searcher.filename());
     }
+    return Ok(searcher);
+}
 
-    //Create header object
-    let input_details = input_reader.get_types_for_header();
-    let header = header::Header::new(CURRENT_VERSION, hashmap_size as u64, input_details.0, input_details.1, input_details.2);
-    //Create the index structure
-    let mut index_structure = IndexStructure::new(filename, header, in_memory_map_size);
-    hashmap_size = index_structure.header.hashmap_size as u128;
-    let mut line = String::new();
-    loop{
-        loop {
-            let offset = input_reader.get_entry(&mut line);
-            if offset == 0xFFFFFFFFFFFFFFFF {
-                break;
-            }
-            if line.ends_with("\n") || line.ends_with("\r") {
-                line.pop();
-            }
-            let hash = hash_function(&line, hashmap_size);
-            index_structure.add_entry(hash, offset as u64);
-            line.clear();
-        }
-        if !index_structure.next(){
-            break;
-        }
-        input_reader.reset();
+//Header line printed above results by --with-header
+fn header_line(searcher: &Searcher) -> Option<String>{
+    let header_line = searcher.header_line().map(str::to_string);
+    if header_line.is_none() {
+        eprintln!("Warning: {} was indexed without --has-header, no header line to print", searcher.filename());
     }
-    
+    return header_line;
 }
 
-fn search(keyword: String, filename: String) -> bool{
-    //Get reader for index file
-    let mut index_reader = StandardFileReader::new(&format!("{}.index", filename));
-    //Read the header size
-    let mut buffer = [0; 8];
-    index_reader.read_exact(&mut buffer).unwrap();
-    let header_size: u8 = buffer[0];
-    index_reader.seek(0);
-    let mut buf: Vec<u8> = vec![0; header_size as usize];
-    index_reader.read_exact(&mut buf).unwrap();
-    let header = header::Header::from_bytes(buf);
+//How matches are printed, chosen by --format and --fields
+struct Output{
+    format: FormatArg,
+    //Columns of tabular records printed, the whole record if empty
+    fields: Vec<usize>,
+    //Printed above the first match, in raw format
+    header_line: Option<String>,
+}
 
-    //Get file reader for original file
-    let original_file_reader: &mut dyn FileReader = match filename.ends_with(".gz"){
-        true => &mut GzFileReader::new(&filename),
-        false => &mut StandardFileReader::new(&filename),
-    };
-    let binding = [header.separator];
-    let mut input_reader: Box<dyn InputReader> = match header.index_type {
-        0 => {
-            Box::new(TabularInputReader::new(
-            original_file_reader, &from_utf8(&binding).unwrap(), header.column as usize
-        ))},
-        1 => {
-            Box::new(MultiFastaInputReader::new(
-                original_file_reader, false
-            ))
-        },
-        2  => Box::new(MultiFastaInputReader::new(
-            original_file_reader, true
-        )),
-        3 => {
-            Box::new(FastqInputReader::new(
-                original_file_reader, false
-            ))
-        },
-        4  => Box::new(FastqInputReader::new(
-            original_file_reader, true
-        )),
-        _ => panic!("Index type not supported")
-    };
-    //Initialize variables
-    let hashmap_size = header.hashmap_size as u128;
-    let hashmap_start = header_size as u64;
-    let hash_value = hash_function(&keyword, hashmap_size);
-    let hashmap_offset = hashmap_start + (hash_value * HASHMAP_ENTRY_SIZE as u64);
-    
-    //Read the first block
-    index_reader.seek(hashmap_offset);
-    let mut buffer = [0; 8];
-    index_reader.read_exact(&mut buffer).unwrap();
-    //let mut current_block = u64::from_be_bytes(buffer);
-    let mut current_index = IndexEntry::from_be_bytes(buffer);
-    let mut test_buffer: String = String::new();
-    loop{
-        match current_index.get_type(){
-            IndexEntryType::NULL => {
-                println!("Keyword not found");
-                return false;
+impl Output{
+    //Prints the matches of query. In raw format misses print nothing, callers report them.
+    //Structured formats print one line per match, query, key and offset included, and one for misses:
+    //  json: {"query": ..., "found": true, "key": ..., "offset": ..., "record": ...}, "fields": [...] with --fields
+    //  tsv: query, found, key, offset, then the record or each field, escaped
+    fn print(&mut self, searcher: &Searcher, query: &str, matches: &[Match]) -> Result<()>{
+        if matches.is_empty() {
+            match self.format {
+                FormatArg::Raw => {}
+                FormatArg::Json => println!("{{\"query\":{},\"found\":false}}", json_string(query)),
+                FormatArg::Tsv => println!("{}\tfalse{}", tsv_field(query), "\t".repeat(2 + self.fields.len().max(1))),
             }
-            IndexEntryType::Direct => {
-                let file_offset = current_index.get_offset();
-                if input_reader.test_and_return_entry(file_offset, &keyword, &mut test_buffer){
-                    println!("{}", test_buffer);
-                    return true;
+            return Ok(());
+        }
+        for found in matches.iter() {
+            let fields = self.fields(searcher, found)?;
+            match self.format {
+                FormatArg::Raw => {
+                    if let Some(header_line) = self.header_line.take() {
+                        println!("{}", header_line);
+                    }
+                    match fields {
+                        Some(fields) => println!("{}", join_fields(&fields, searcher.separator(), searcher.quote())),
+                        None => println!("{}", found.record),
+                    }
                 }
-                println!("Keyword not found");
-                return false;
-            }
-            IndexEntryType::Indirect => {
-                let mut buffer = [0; 16];
-                index_reader.seek(current_index.get_offset());
-                index_reader.read_exact(&mut buffer).unwrap();
-                let file_offset = u64::from_be_bytes(buffer[0..8].try_into().unwrap());
-                current_index = IndexEntry::from_be_bytes(buffer[8..16].try_into().unwrap());
-                if input_reader.test_and_return_entry(file_offset, &keyword, &mut test_buffer){
-                    println!("{}", test_buffer);
-                    return true;
+                FormatArg::Json => {
+                    let record = match fields {
+                        Some(fields) => format!("\"fields\":[{}]", fields.iter().map(|field| json_string(field)).collect::<Vec<String>>().join(",")),
+                        None => format!("\"record\":{}", json_string(&found.record)),
+                    };
+                    println!("{{\"query\":{},\"found\":true,\"key\":{},\"offset\":{},{}}}", json_string(query), json_string(&found.key), found.offset, record);
                 }
-                test_buffer.clear();
-                if current_index.get_type() == IndexEntryType::Direct{
-                    if input_reader.test_and_return_entry(current_index.get_offset(), &keyword, &mut test_buffer){
-                        println!("{}", test_buffer);
-                        return true;
-                    }
-                    println!("Keyword not found");
-                    return false;
+                FormatArg::Tsv => {
+                    let record = match fields {
+                        Some(fields) => fields.iter().map(|field| tsv_field(field)).collect::<Vec<String>>().join("\t"),
+                        None => tsv_field(&found.record),
+                    };
+                    println!("{}\ttrue\t{}\t{}\t{}", tsv_field(query), tsv_field(&found.key), found.offset, record);
                 }
             }
         }
+        return Ok(());
     }
-}
 
-fn index_tabular(filename: String, column: usize, separator: String, hashmap_size: u128, in_memory_map_size: u64){
-    assert!(separator.as_bytes().len() == 1);
-    assert!(from_utf8(separator.to_string().as_bytes()) == Ok(&separator));
-    let file_input_reader: &mut dyn FileReader = match filename.ends_with(".gz"){
-        true => &mut GzFileReader::new(&filename),
-        false => &mut StandardFileReader::new(&filename),
-    };
-    let mut input_reader: TabularInputReader = TabularInputReader::new(
-        file_input_reader, &separator, column
-    );
-    index(&mut input_reader, filename, hashmap_size, in_memory_map_size);
-}
-fn index_fasta(filename: String, index_sequence: bool, hashmap_size: u128, in_memory_map_size: u64){
-    let file_input_reader: &mut dyn FileReader = match filename.ends_with(".gz"){
-        true => &mut GzFileReader::new(&filename),
-        false => &mut StandardFileReader::new(&filename),
-    };
-    let mut input_reader: MultiFastaInputReader = MultiFastaInputReader::new(
-        file_input_reader, index_sequence
-    );
-    index(&mut input_reader, filename, hashmap_size, in_memory_map_size);
+    //Fields of the record chosen by --fields, None to print the whole record
+    fn fields(&self, searcher: &Searcher, found: &Match) -> Result<Option<Vec<String>>>{
+        if self.fields.is_empty() {
+            return Ok(None);
+        }
+        let fields = searcher.record_fields(&found.record)?;
+        let mut projected = Vec::with_capacity(self.fields.len());
+        for &column in self.fields.iter() {
+            match fields.get(column) {
+                Some(field) => projected.push(field.clone()),
+                None => return Err(FastSeekError::InvalidArgument(format!("record at offset {} has no column {}", found.offset, column))),
+            }
+        }
+        return Ok(Some(projected));
+    }
 }
-fn index_fastq(filename: String, index_sequence: bool, hashmap_size: u128, in_memory_map_size: u64){
-    let file_input_reader: &mut dyn FileReader = match filename.ends_with(".gz"){
-        true => &mut GzFileReader::new(&filename),
-        false => &mut StandardFileReader::new(&filename),
+
+//Record of the fields printed by raw output. In csv files, fields with the separator, quotes or line breaks are
+//quoted again, their quotes doubled, so that the record can be parsed as the file was
+fn join_fields(fields: &[String], separator: &str, quote: Option<char>) -> String{
+    let quote = match quote {
+        Some(quote) => quote,
+        None => return fields.join(separator),
     };
-    let mut input_reader: FastqInputReader = FastqInputReader::new(
-        file_input_reader, index_sequence
-    );
-    index(&mut input_reader, filename, hashmap_size, in_memory_map_size);
+    let quoted = fields.iter().map(|field| {
+        if !field.contains(separator) && !field.contains(quote) && !field.contains(['\n', '\r']) {
+            return field.clone();
+        }
+        let doubled = field.replace(quote, &format!("{}{}", quote, quote));
+        return format!("{}{}{}", quote, doubled, quote);
+    });
+    return quoted.collect::<Vec<String>>().join(separator);
 }
 
-mod command_line_tool;
-use command_line_tool::{Cli, Commands};
-fn main() {
-    let cli = Cli::parse();
-    match cli.command {
-        Commands::IndexTabular { filename, column, separator, hashmap_size, in_memory_map_size} => {
-            index_tabular(filename, column, separator, hashmap_size, in_memory_map_size);
-        }
-        Commands::IndexFasta { filename, by_sequence, hashmap_size, in_memory_map_size } => {
-            index_fasta(filename, by_sequence, hashmap_size, in_memory_map_size);
+fn json_string(value: &str) -> String{
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
         }
-        Commands::IndexFastq { filename, by_sequence, hashmap_size, in_memory_map_size } => {
-            index_fastq(filename, by_sequence, hashmap_size, in_memory_map_size);
-        }
-        Commands::Search { filename, keyword, print_duplicates } => {
-            search(keyword, filename);
-        }
-        Commands::Test{} => { 
-            test();
-         }
     }
+    escaped.push('"');
+    return escaped;
 }
 
-const TEST_LEN: u32 = 100;
+//Field of tsv output: backslashes, tabs and line breaks escaped as \\, \t, \n and \r
+fn tsv_field(value: &str) -> String{
+    return value.replace('\\', "\\\\").replace('\t', "\\t").replace('\n', "\\n").replace('\r', "\\r");
+}
 
-fn run_test_fastq(in_memory_map_size: u64){
-    let path = Path::new("test_data/test.fastq");
-    let file = File::create(&path).unwrap();
-    let mut writer = io::BufWriter::new(file);
-    for i in 0..TEST_LEN {
-        let string = format!("@prova{}", i);
-        let _ = writer.write_all(format!("{}\nGGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}\n+\n!''*((((***+))%%%++)(%%%%).1***-+*''))**55CCF>>>>>>CCCCCCC65\n", string, i).as_bytes());
+//keyword: the key, or the fields of a composite key
+fn search(mut searcher: Searcher, keyword: Vec<String>, mut output: Output, print_duplicates: bool, count: bool) -> Result<bool>{
+    if keyword.is_empty() {
+        return Err(FastSeekError::InvalidArgument("no keyword to search".to_string()));
     }
-    let _ = writer.flush();
-    index_fastq("test_data/test.fastq".to_string(), false, 0, in_memory_map_size);
-    for i in 0..TEST_LEN {
-        assert! (search(format!("@prova{}", i), "test_data/test.fastq".to_string()));
+    let keyword = searcher.join_key(&keyword)?;
+    let matches = searcher.search_matches(std::slice::from_ref(&keyword), print_duplicates || count)?.pop().unwrap();
+    if count {
+        println!("{}", matches.len());
+        return Ok(!matches.is_empty());
     }
-    assert! (!search("NOT_EXISTING".to_string(), "test_data/test.fastq".to_string()));
-    index_fastq("test_data/test.fastq".to_string(), true, 0, in_memory_map_size);
-    for i in 0..TEST_LEN {
-        assert! (search(format!("GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}", i), "test_data/test.fastq".to_string()));
+    if matches.is_empty() && output.format == FormatArg::Raw {
+        println!("Keyword not found");
+        return Ok(false);
     }
-    assert! (!search("NOT_EXISTING".to_string(), "test_data/test.fastq".to_string()));
+    output.print(&searcher, &keyword, &matches)?;
+    return Ok(!matches.is_empty());
 }
 
-fn run_test_fasta(in_memory_map_size: u64){
-    let path = Path::new("test_data/multi.fasta");
-    let file = File::create(&path).unwrap();
-    let mut writer = io::BufWriter::new(file);
-    for i in 0..TEST_LEN {
-        let string = format!(">prova{}", i);
-        let _ = writer.write_all(format!("{}\nGGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}\n", string, i).as_bytes());
-        //writer.write_line();
+//Search every keyword listed in queries_path (one per line, "-" for stdin).
+//Matches are printed in query order. In raw format, missing keywords are reported to stderr.
+fn search_queries(mut searcher: Searcher, queries_path: String, mut output: Output, print_duplicates: bool, count: bool) -> Result<bool>{
+    let queries_reader: Box<dyn BufRead> = match queries_path.as_str() {
+        "-" => Box::new(io::stdin().lock()),
+        _ => Box::new(io::BufReader::new(File::open(&queries_path)?)),
+    };
+    let mut keywords: Vec<String> = vec![];
+    for line in queries_reader.lines() {
+        let line = line?;
+        let keyword = line.trim_end_matches('\r');
+        if !keyword.is_empty() {
+            keywords.push(keyword.to_string());
+        }
     }
-    let _ = writer.flush();
-    index_fasta("test_data/multi.fasta".to_string(), false, 0, in_memory_map_size);
-    for i in 0..TEST_LEN {
-        assert! (search(format!(">prova{}", i), "test_data/multi.fasta".to_string()));
+    let results = searcher.search_matches(&keywords, print_duplicates || count)?;
+    let mut all_found = true;
+    for (keyword, matches) in keywords.iter().zip(results.iter()) {
+        if matches.is_empty() {
+            if output.format == FormatArg::Raw {
+                eprintln!("{}", keyword);
+            }
+            all_found = false;
+        }
+        if count {
+            println!("{}\t{}", keyword, matches.len());
+            continue;
+        }
+        output.print(&searcher, keyword, matches)?;
+    }
+    return Ok(all_found);
+}
+
+//Search the records whose key starts with prefix, or is in range (LOW..HIGH, either bound may be empty)
+fn search_ordered(mut searcher: Searcher, prefix: Option<String>, range: Option<String>, mut output: Output, count: bool) -> Result<bool>{
+    let (query, matches) = match (prefix, range) {
+        (Some(prefix), _) => {
+            let matches = searcher.prefix_matches(&prefix)?;
+            (prefix, matches)
+        }
+        (None, Some(range)) => {
+            let (low, high) = match range.split_once("..") {
+                Some(bounds) => bounds,
+                None => return Err(FastSeekError::InvalidArgument(format!("expected LOW..HIGH, got {:?}", range))),
+            };
+            let bound = |bound: &str| if bound.is_empty() { None } else { Some(bound.to_string()) };
+            let matches = searcher.range_matches(bound(low).as_deref(), bound(high).as_deref())?;
+            (range, matches)
+        }
+        (None, None) => (String::new(), vec![]),
+    };
+    if count {
+        println!("{}", matches.len());
+        return Ok(!matches.is_empty());
     }
-    assert! (!search("NOT_EXISTING".to_string(), "test_data/multi.fasta".to_string()));
-    index_fasta("test_data/multi.fasta".to_string(), true, 0, in_memory_map_size);
-    for i in 0..TEST_LEN {
-        assert! (search(format!("GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}", i), "test_data/multi.fasta".to_string()));
+    if matches.is_empty() && output.format == FormatArg::Raw {
+        println!("Keyword not found");
+        return Ok(false);
     }
-    assert! (!search("NOT_EXISTING".to_string(), "test_data/multi.fasta".to_string()));
+    output.print(&searcher, &query, &matches)?;
+    return Ok(!matches.is_empty());
 }
 
-fn run_test(in_memory_map_size: u64){
-    let path = Path::new("test_data/test.csv");
-    let file = File::create(&path).unwrap();
-    let mut writer = io::BufWriter::new(file);
-    for i in 0..TEST_LEN {
-        let string = format!("prova{}", i);
-        let _ = writer.write_all(format!("1,{},0,0,0,eruheigrnei,L{}\n", string, string).as_bytes());
-        //writer.write_line();
+fn verify(mut searcher: Searcher, samples: u64) -> Result<bool>{
+    let checked = searcher.verify(samples)?;
+    println!("Index of {} is up to date, {} records checked", searcher.filename(), checked);
+    return Ok(true);
+}
+
+//Builds the index of a tabular file keyed as key says, from builder with the other options
+fn build_tabular(builder: IndexBuilder, key: TabularArgs, options: IndexOptions) -> Result<()>{
+    let builder = builder
+        .separator(&key.separator)
+        .whitespace(key.whitespace)
+        .has_header(key.has_header);
+    let builder = match key.quote {
+        Some(quote) => builder.quote(quote),
+        None => builder,
+    };
+    let builder = match key.comment_char {
+        Some(comment_char) => builder.comment_char(comment_char),
+        None => builder,
+    };
+    let main_builder = match key.column_name.is_empty() {
+        true => builder.clone().columns(&key.column),
+        false => builder.clone().column_names(&key.column_name.iter().map(String::as_str).collect::<Vec<&str>>()),
+    };
+    return build_indexes(Format::Tabular, main_builder, builder, options, key.also);
+}
+
+//Builds the index of a multi-fasta or fastq file keyed as key says, from builder with the other options
+fn build_sequences(format: Format, builder: IndexBuilder, key: SequenceArgs, options: IndexOptions) -> Result<()>{
+    let mut main_builder = builder.clone().by_sequence(key.by_sequence);
+    if key.id_token {
+        main_builder = main_builder.id_token(key.token);
     }
-    let _ = writer.flush();
-    index_tabular("test_data/test.csv".to_string(), 1, ",".to_string(), 0, in_memory_map_size);
-    for i in 0..TEST_LEN {
-        assert! (search(format!("prova{}", i), "test_data/test.csv".to_string()));
+    return build_indexes(format, main_builder, builder, options, key.also);
+}
+
+//Builds the index of main_builder, named or written as options say, together with the indexes listed by --also.
+//These are built by base_builder, which has the options of main_builder but its key.
+fn build_indexes(format: Format, mut main_builder: IndexBuilder, mut base_builder: IndexBuilder, options: IndexOptions, also: Vec<String>) -> Result<()>{
+    if let Some(index_dir) = options.index_dir {
+        main_builder = main_builder.index_dir(&index_dir);
+        base_builder = base_builder.index_dir(&index_dir);
     }
-    assert! (!search("NOT_EXISTING".to_string(), "test_data/test.csv".to_string()));
-    index_tabular("test_data/test.csv".to_string(), 6, ",".to_string(), 0, in_memory_map_size);
-    for i in 0..TEST_LEN {
-        assert! (search(format!("Lprova{}", i), "test_data/test.csv".to_string()));
+    if let Some(index_name) = options.index_name {
+        main_builder = main_builder.name(&index_name);
     }
-    assert! (!search("NOT_EXISTING".to_string(), "test_data/test.csv".to_string()));
+    if let Some(index) = options.index {
+        main_builder = main_builder.index_file(&index);
+    }
+    let mut builders = vec![main_builder];
+    for also in also.iter() {
+        let (name, key) = match also.split_once('=') {
+            Some((name, key)) => (name, key),
+            None => return Err(FastSeekError::InvalidArgument(format!("expected NAME=KEY, got {:?}", also))),
+        };
+        builders.push(with_key(base_builder.clone().name(name), format, key)?);
+    }
+    return IndexBuilder::build_all(builders);
 }
-fn run_test_compressed(){
-    let path = Path::new("test_data/test.csv.gz");
-    let file = File::create(&path).unwrap();
-    let buf_writer = io::BufWriter::new(file);
-    let mut writer = BGZFWriter::new(buf_writer, Compression::default());
-    for i in 0..TEST_LEN {
-        let string = format!("prova{}", i);
-        let _ =writer.write_all(format!("1,{},0,0,0,eruheigrneiugrheriuhg,ergbneirgbeiugberiugberiuhg\n", string).as_bytes());
+
+//Sets the key of builder from the KEY of --also: a list of columns, by number or name, for tabular files,
+//"id", "sequence" or "token:N" for fasta and fastq files
+fn with_key(builder: IndexBuilder, format: Format, key: &str) -> Result<IndexBuilder>{
+    let invalid_key = || FastSeekError::InvalidArgument(format!("invalid key {:?} for --also", key));
+    if format == Format::Tabular {
+        let columns = key.split(',').map(|column| column.trim().parse::<usize>()).collect::<std::result::Result<Vec<usize>, _>>();
+        return Ok(match columns {
+            Ok(columns) => builder.columns(&columns),
+            Err(_) => builder.column_names(&key.split(',').map(str::trim).collect::<Vec<&str>>()),
+        });
     }
-    let _ = writer.flush();
-    let _ = writer.close();
-    index_tabular("test_data/test.csv.gz".to_string(), 1, ",".to_string(), 0, 1000);
-    for i in 0..TEST_LEN {
-        assert! (search(format!("prova{}", i), "test_data/test.csv.gz".to_string()));
+    return match key {
+        "id" => Ok(builder),
+        "sequence" => Ok(builder.by_sequence(true)),
+        _ => match key.strip_prefix("token:").map(str::parse::<usize>) {
+            Some(Ok(token)) => Ok(builder.id_token(token)),
+            _ => Err(invalid_key()),
+        },
+    };
+}
+
+//Recorded in the headers of the indexes built
+fn command_line() -> String{
+    return std::env::args().collect::<Vec<String>>().join(" ");
+}
+
+//Runs the command. Returns false if a searched key was not found
+fn run(command: Commands) -> Result<bool> {
+    match command {
+        Commands::IndexTabular { filename, key, compressed_file, options } => {
+            let builder = index_builder(&filename, Format::Tabular, &options)
+                .gzi(compressed_file.gzi)
+                .checkpoint_interval(compressed_file.checkpoint_interval.saturating_mul(1024*1024));
+            build_tabular(builder, key, options)?;
+        }
+        Commands::IndexFasta { filename, key, compressed_file, options } => {
+            let builder = index_builder(&filename, Format::Fasta, &options)
+                .gzi(compressed_file.gzi)
+                .checkpoint_interval(compressed_file.checkpoint_interval.saturating_mul(1024*1024));
+            build_sequences(Format::Fasta, builder, key, options)?;
+        }
+        Commands::IndexFastq { filename, key, compressed_file, options } => {
+            let builder = index_builder(&filename, Format::Fastq, &options)
+                .gzi(compressed_file.gzi)
+                .checkpoint_interval(compressed_file.checkpoint_interval.saturating_mul(1024*1024));
+            build_sequences(Format::Fastq, builder, key, options)?;
+        }
+        Commands::Search { filename, keyword, queries, prefix, range, print_duplicates, count, with_header, format, fields, index_name, index, index_dir } => {
+            //The index records the input file, so every positional argument is a keyword
+            let (filename, keyword) = match (&index, filename) {
+                (Some(_), Some(first_keyword)) => (None, [vec![first_keyword], keyword].concat()),
+                (_, filename) => (filename, keyword),
+            };
+            if with_header && format != FormatArg::Raw {
+                return Err(FastSeekError::InvalidArgument("--with-header is only available with --format raw".to_string()));
+            }
+            let searcher = open_searcher(filename, index, index_dir, index_name)?;
+            let header_line = match with_header {
+                true => header_line(&searcher),
+                false => None,
+            };
+            if !fields.is_empty() && searcher.separator().is_empty() {
+                return Err(FastSeekError::InvalidArgument("--fields is only available for tabular files".to_string()));
+            }
+            let output = Output{format, fields, header_line};
+            if prefix.is_some() || range.is_some() {
+                return search_ordered(searcher, prefix, range, output, count);
+            }
+            return match queries {
+                Some(queries) => search_queries(searcher, queries, output, print_duplicates, count),
+                None => search(searcher, keyword, output, print_duplicates, count),
+            };
+        }
+        Commands::Verify { filename, samples, index_name, index, index_dir } => {
+            return verify(open_searcher(filename, index, index_dir, index_name)?, samples);
+        }
+        Commands::Update { filename, index_name, index, index_dir } => {
+            let index = match (index, &filename) {
+                (Some(index), _) => index,
+                (None, Some(filename)) => index_path(filename, index_dir.as_deref(), index_name.as_deref()),
+                (None, None) => return Err(FastSeekError::InvalidArgument("either a filename or --index is needed".to_string())),
+            };
+            let added = update_index(&index, filename.as_deref())?;
+            println!("Added {} records to {}", added, index);
+        }
+        Commands::Bgzip { filename, output, gzi, build_index } => {
+            let output = match output {
+                Some(output) => output,
+                None if filename.ends_with(".gz") => filename.clone(),
+                None => format!("{}.gz", filename),
+            };
+            let builder = |format: Format, options: &IndexOptions| index_builder(&output, format, options).bgzip_source(&filename).gzi(gzi);
+            match build_index {
+                None => return bgzip(&filename, &output, gzi).map(|_| true),
+                Some(BgzipIndex::Tabular { key, options }) => build_tabular(builder(Format::Tabular, &options), key, options)?,
+                Some(BgzipIndex::Fasta { key, options }) => build_sequences(Format::Fasta, builder(Format::Fasta, &options), key, options)?,
+                Some(BgzipIndex::Fastq { key, options }) => build_sequences(Format::Fastq, builder(Format::Fastq, &options), key, options)?,
+            }
+        }
+        Commands::ZstdSeekable { filename, output, frame_size } => {
+            let output = match output {
+                Some(output) => output,
+                None if filename.ends_with(".zst") => filename.clone(),
+                None => format!("{}.zst", filename),
+            };
+            zstd_seekable(&filename, &output, frame_size.saturating_mul(1024*1024))?;
+        }
+        Commands::Test{} => {
+            self_test::test();
+        }
     }
-    assert! (!search("NOT_EXISTING".to_string(), "test_data/test.csv.gz".to_string()));
+    return Ok(true);
 }
-fn test(){
-    run_test_fastq(1000);
-    run_test_fasta(1000);
-    run_test(10000);
-    run_test_compressed();
-    run_test(6);
+
+fn main() {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(true) => {}
+        Ok(false) => exit(EXIT_NOT_FOUND),
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            exit(exit_code(&error));
+        }
+    }
 }
@@ -0,0 +1,363 @@
+use std::cmp::Ordering;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use crate::builder::KeyOrder;
+use crate::error::{with_filename, FastSeekError, Result};
+use crate::file_reader::{FileReader, StandardFileReader};
+use crate::file_writer::FileWriter;
+use crate::fingerprint::{Fingerprint, FINGERPRINT_SIZE};
+
+//Ordered index, written next to the hash index to <index>.sorted:
+//  magic (8 bytes) | version (u8) | key order (u8) | fingerprint | entry count (u64) | key area size (u64)
+//  | entries sorted by key, then by offset: address of the key in the key area (u64) | offset in the indexed file (u64)
+//  | key area: the keys, in the order of the entries
+//The fingerprint is the one stored in the header of the hash index, so that an ordered index left by another build is detected.
+const MAGIC: [u8; 8] = [0x89, b'F', b'S', b'K', b'O', b'R', b'D', b'\n'];
+const VERSION: u8 = 1;
+const HEADER_SIZE: u64 = 8 + 1 + 1 + FINGERPRINT_SIZE as u64 + 8 + 8;
+const ENTRY_SIZE: u64 = 16;
+//Bytes of keys kept in memory while building: beyond it, keys are sorted and written to a run file, merged at the end
+const RUN_SIZE: usize = 256*1024*1024;
+
+//Key orders
+const ORDER_LEXICOGRAPHIC: u8 = 0;
+const ORDER_NUMERIC: u8 = 1;
+
+//Path of the ordered index of the index at index_path
+pub fn ordered_index_path(index_path: &str) -> String{
+    return format!("{}.sorted", index_path);
+}
+
+//A key, with its value if keys are compared as numbers (0 otherwise)
+#[derive(Clone, Debug)]
+pub struct SortedKey{
+    key: String,
+    value: f64,
+}
+
+impl SortedKey{
+    //None if keys are numbers and key isn't one
+    pub fn parse(order: KeyOrder, key: &str) -> Option<SortedKey>{
+        let value = match order {
+            KeyOrder::Lexicographic => 0.0,
+            KeyOrder::Numeric => match key.trim().parse::<f64>() {
+                Ok(value) if !value.is_nan() => value,
+                _ => return None,
+            },
+        };
+        return Some(SortedKey{key: key.to_string(), value});
+    }
+
+    //Key of the record starting at line of the indexed file
+    pub fn of_record(order: KeyOrder, key: &str, line: u64) -> Result<SortedKey>{
+        return match SortedKey::parse(order, key) {
+            Some(sorted_key) => Ok(sorted_key),
+            None => Err(FastSeekError::MalformedRecord{line, reason: format!("key {:?} is not a number", key)}),
+        };
+    }
+
+    //Order of keys as seen by searches: keys equal as numbers, e.g. "1" and "1.0", are equal
+    fn compare(&self, other: &SortedKey, order: KeyOrder) -> Ordering{
+        return match order {
+            KeyOrder::Lexicographic => self.key.as_bytes().cmp(other.key.as_bytes()),
+            KeyOrder::Numeric => self.value.partial_cmp(&other.value).unwrap(),
+        };
+    }
+}
+
+struct SortedEntry{
+    key: SortedKey,
+    offset: u64,
+}
+
+//Order of the entries in the index: by key, keys equal as numbers by their text, then in file order
+fn compare_entries(a: &SortedEntry, b: &SortedEntry, order: KeyOrder) -> Ordering{
+    return a.key.compare(&b.key, order).then_with(|| a.key.key.cmp(&b.key.key)).then(a.offset.cmp(&b.offset));
+}
+
+//Sorted entries written while building: kept in memory, or in a run file as
+//  key length (u32) | key | value (f64) | offset (u64)
+enum Run{
+    Memory(std::vec::IntoIter<SortedEntry>),
+    File(io::BufReader<File>),
+}
+
+impl Run{
+    fn next(&mut self) -> Result<Option<SortedEntry>>{
+        let reader = match self {
+            Run::Memory(entries) => return Ok(entries.next()),
+            Run::File(reader) => reader,
+        };
+        let mut len = [0; 4];
+        match reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        let mut key = vec![0; u32::from_be_bytes(len) as usize];
+        reader.read_exact(&mut key)?;
+        let mut numbers = [0; 16];
+        reader.read_exact(&mut numbers)?;
+        let key = String::from_utf8(key).map_err(|_| FastSeekError::CorruptIndex("invalid key in ordered index run".to_string()))?;
+        let value = f64::from_be_bytes(numbers[0..8].try_into().unwrap());
+        let offset = u64::from_be_bytes(numbers[8..16].try_into().unwrap());
+        return Ok(Some(SortedEntry{key: SortedKey{key, value}, offset}));
+    }
+}
+
+//Builds an ordered index from the keys of every record, added in any order
+pub struct OrderedIndexWriter{
+    path: String,
+    pub order: KeyOrder,
+    //Written by finish, so it can be set after the keys are added
+    pub fingerprint: Fingerprint,
+    entries: Vec<SortedEntry>,
+    //Bytes taken by entries
+    buffered: usize,
+    runs: Vec<String>,
+}
+
+impl OrderedIndexWriter{
+    pub fn new(path: String, order: KeyOrder, fingerprint: Fingerprint) -> OrderedIndexWriter{
+        return OrderedIndexWriter{path, order, fingerprint, entries: vec![], buffered: 0, runs: vec![]};
+    }
+
+    pub fn add(&mut self, key: SortedKey, offset: u64) -> Result<()>{
+        self.buffered += key.key.len() + std::mem::size_of::<SortedEntry>();
+        self.entries.push(SortedEntry{key, offset});
+        if self.buffered >= RUN_SIZE {
+            self.write_run()?;
+        }
+        return Ok(());
+    }
+
+    fn sort_entries(&mut self){
+        let order = self.order;
+        self.entries.sort_unstable_by(|a, b| compare_entries(a, b, order));
+    }
+
+    fn write_run(&mut self) -> Result<()>{
+        self.sort_entries();
+        let path = format!("{}.run{}", self.path, self.runs.len());
+        let mut writer = FileWriter::get_writer(path.clone())?;
+        for entry in self.entries.drain(..) {
+            writer.write_all(&(entry.key.key.len() as u32).to_be_bytes())?;
+            writer.write_all(entry.key.key.as_bytes())?;
+            writer.write_all(&entry.key.value.to_be_bytes())?;
+            writer.write_all(&entry.offset.to_be_bytes())?;
+        }
+        writer.flush()?;
+        self.runs.push(path);
+        self.buffered = 0;
+        return Ok(());
+    }
+
+    //Merges the runs into the ordered index. Returns the number of entries.
+    pub fn finish(mut self) -> Result<u64>{
+        let mut runs: Vec<Run> = vec![];
+        if self.runs.is_empty() {
+            self.sort_entries();
+            runs.push(Run::Memory(std::mem::take(&mut self.entries).into_iter()));
+        } else {
+            if !self.entries.is_empty() {
+                self.write_run()?;
+            }
+            for path in self.runs.iter() {
+                runs.push(Run::File(io::BufReader::new(File::open(path).map_err(|e| with_filename(e, "open", path))?)));
+            }
+        }
+        //Entries are written to the index, keys to a temporary file appended to it
+        let keys_path = format!("{}.keys", self.path);
+        let mut writer = FileWriter::get_writer(self.path.clone())?;
+        let mut keys_writer = FileWriter::get_writer(keys_path.clone())?;
+        writer.write_all(&[0; HEADER_SIZE as usize])?;
+        let mut heads: Vec<Option<SortedEntry>> = vec![];
+        for run in runs.iter_mut() {
+            heads.push(run.next()?);
+        }
+        let mut count: u64 = 0;
+        let mut keys_size: u64 = 0;
+        loop{
+            //Runs are few: the smallest head is found by a linear scan
+            let mut smallest: Option<usize> = None;
+            for (i, head) in heads.iter().enumerate() {
+                if let Some(head) = head {
+                    if smallest.is_none() || compare_entries(head, heads[smallest.unwrap()].as_ref().unwrap(), self.order) == Ordering::Less {
+                        smallest = Some(i);
+                    }
+                }
+            }
+            let i = match smallest {
+                Some(i) => i,
+                None => break,
+            };
+            let entry = heads[i].take().unwrap();
+            writer.write_all(&keys_size.to_be_bytes())?;
+            writer.write_all(&entry.offset.to_be_bytes())?;
+            keys_writer.write_all(entry.key.key.as_bytes())?;
+            keys_size += entry.key.key.len() as u64;
+            count += 1;
+            heads[i] = runs[i].next()?;
+        }
+        keys_writer.flush()?;
+        drop(keys_writer);
+        let mut keys_file = File::open(&keys_path).map_err(|e| with_filename(e, "open", &keys_path))?;
+        io::copy(&mut keys_file, &mut writer)?;
+        writer.seek(io::SeekFrom::Start(0))?;
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[VERSION, match self.order {
+            KeyOrder::Lexicographic => ORDER_LEXICOGRAPHIC,
+            KeyOrder::Numeric => ORDER_NUMERIC,
+        }])?;
+        writer.write_all(&self.fingerprint.to_bytes())?;
+        writer.write_all(&count.to_be_bytes())?;
+        writer.write_all(&keys_size.to_be_bytes())?;
+        writer.flush()?;
+        fs::remove_file(&keys_path)?;
+        for path in self.runs.iter() {
+            fs::remove_file(path)?;
+        }
+        return Ok(count);
+    }
+}
+
+//Reads an ordered index, looking up keys by binary search
+pub struct OrderedIndexReader{
+    reader: StandardFileReader,
+    pub order: KeyOrder,
+    count: u64,
+    keys_start: u64,
+    keys_size: u64,
+}
+
+impl OrderedIndexReader{
+    //fingerprint: the one in the header of the hash index
+    pub fn open(path: &str, fingerprint: &Fingerprint) -> Result<OrderedIndexReader>{
+        let mut reader = StandardFileReader::new(path)?;
+        let len = fs::metadata(path).map_err(|e| with_filename(e, "read", path))?.len();
+        let mut header = [0; HEADER_SIZE as usize];
+        if len < HEADER_SIZE {
+            return Err(FastSeekError::CorruptIndex(format!("ordered index {} is truncated", path)));
+        }
+        reader.read_exact(&mut header)?;
+        if header[0..8] != MAGIC {
+            return Err(FastSeekError::CorruptIndex(format!("{} is not an ordered index", path)));
+        }
+        if header[8] != VERSION {
+            return Err(FastSeekError::UnsupportedVersion(header[8]));
+        }
+        let order = match header[9] {
+            ORDER_LEXICOGRAPHIC => KeyOrder::Lexicographic,
+            ORDER_NUMERIC => KeyOrder::Numeric,
+            order => return Err(FastSeekError::CorruptIndex(format!("unknown key order {}", order))),
+        };
+        let position = 10 + FINGERPRINT_SIZE as usize;
+        if Fingerprint::from_bytes(&header[10..position]) != *fingerprint {
+            return Err(FastSeekError::StaleIndex(format!("ordered index {} was not built with the hash index", path)));
+        }
+        let count = u64::from_be_bytes(header[position..position + 8].try_into().unwrap());
+        let keys_size = u64::from_be_bytes(header[position + 8..position + 16].try_into().unwrap());
+        let keys_start = count.checked_mul(ENTRY_SIZE).and_then(|size| size.checked_add(HEADER_SIZE));
+        if keys_start.and_then(|start| start.checked_add(keys_size)) != Some(len) {
+            return Err(FastSeekError::CorruptIndex(format!("ordered index {} is truncated", path)));
+        }
+        return Ok(OrderedIndexReader{reader, order, count, keys_start: keys_start.unwrap(), keys_size});
+    }
+
+    //Key and offset of the i-th entry
+    fn entry(&mut self, i: u64) -> Result<(String, u64)>{
+        let mut buffer = [0; 2 * ENTRY_SIZE as usize];
+        //The key ends where the key of the next entry starts
+        let len = match i + 1 < self.count {
+            true => 2 * ENTRY_SIZE as usize,
+            false => ENTRY_SIZE as usize,
+        };
+        self.reader.seek(HEADER_SIZE + i * ENTRY_SIZE)?;
+        self.reader.read_exact(&mut buffer[..len])?;
+        let key_start = u64::from_be_bytes(buffer[0..8].try_into().unwrap());
+        let offset = u64::from_be_bytes(buffer[8..16].try_into().unwrap());
+        let key_end = match i + 1 < self.count {
+            true => u64::from_be_bytes(buffer[16..24].try_into().unwrap()),
+            false => self.keys_size,
+        };
+        if key_start > key_end || key_end > self.keys_size {
+            return Err(FastSeekError::CorruptIndex(format!("invalid key address {} in ordered index", key_start)));
+        }
+        let mut key = vec![0; (key_end - key_start) as usize];
+        self.reader.seek(self.keys_start + key_start)?;
+        self.reader.read_exact(&mut key)?;
+        return match String::from_utf8(key) {
+            Ok(key) => Ok((key, offset)),
+            Err(_) => Err(FastSeekError::CorruptIndex("invalid key in ordered index".to_string())),
+        };
+    }
+
+    fn sorted_key(&self, key: String) -> Result<SortedKey>{
+        return match SortedKey::parse(self.order, &key) {
+            Some(sorted_key) => Ok(sorted_key),
+            None => Err(FastSeekError::CorruptIndex(format!("key {:?} of ordered index is not a number", key))),
+        };
+    }
+
+    //Index of the first entry whose key is not less than key
+    fn lower_bound(&mut self, key: &SortedKey) -> Result<u64>{
+        let (mut low, mut high) = (0, self.count);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let entry_key = self.entry(middle)?.0;
+            match self.sorted_key(entry_key)?.compare(key, self.order) {
+                Ordering::Less => low = middle + 1,
+                _ => high = middle,
+            }
+        }
+        return Ok(low);
+    }
+
+    //Offsets of the records whose key is between low and high, both included, in key order.
+    //Without a bound, the range is open on that side.
+    pub fn range(&mut self, low: Option<&SortedKey>, high: Option<&SortedKey>) -> Result<Vec<u64>>{
+        let mut i = match low {
+            Some(low) => self.lower_bound(low)?,
+            None => 0,
+        };
+        let mut offsets = vec![];
+        while i < self.count {
+            let (key, offset) = self.entry(i)?;
+            if let Some(high) = high {
+                if self.sorted_key(key)?.compare(high, self.order) == Ordering::Greater {
+                    break;
+                }
+            }
+            offsets.push(offset);
+            i += 1;
+        }
+        return Ok(offsets);
+    }
+
+    //Adds every entry to writer, to write them again with others
+    pub fn copy_to(&mut self, writer: &mut OrderedIndexWriter) -> Result<()>{
+        for i in 0..self.count {
+            let (key, offset) = self.entry(i)?;
+            writer.add(self.sorted_key(key)?, offset)?;
+        }
+        return Ok(());
+    }
+
+    //Offsets of the records whose key starts with prefix, in key order. Lexicographic order only.
+    pub fn prefix(&mut self, prefix: &str) -> Result<Vec<u64>>{
+        if self.order != KeyOrder::Lexicographic {
+            return Err(FastSeekError::InvalidArgument("prefix searches need keys ordered lexicographically".to_string()));
+        }
+        let mut i = self.lower_bound(&SortedKey{key: prefix.to_string(), value: 0.0})?;
+        let mut offsets = vec![];
+        while i < self.count {
+            let (key, offset) = self.entry(i)?;
+            if !key.starts_with(prefix) {
+                break;
+            }
+            offsets.push(offset);
+            i += 1;
+        }
+        return Ok(offsets);
+    }
+}
@@ -0,0 +1,199 @@
+use std::collections::BTreeMap;
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use crate::builder::KeyOrder;
+use crate::error::Result;
+use crate::file_reader::InputReader;
+use crate::hash_function;
+use crate::index_structure::IndexStructure;
+use crate::key_extractor::KeyExtractor;
+use crate::ordered_index::{OrderedIndexWriter, SortedKey};
+
+//Records sent at once from the reader to the hashing threads
+const BATCH_SIZE: usize = 4096;
+//Batches queued between stages, per hashing thread
+const QUEUED_BATCHES: usize = 2;
+
+//Records read from the input file, stored one after the other in a single buffer
+struct RecordBatch{
+    sequence: u64,
+    records: String,
+    //Offset in the input file, first line and end in records of each record
+    entries: Vec<(u64, u64, usize)>
+}
+
+//Hash, offset and, for ordered indexes, key of a record
+type HashedEntry = (u64, u64, Option<SortedKey>);
+//Entries of each record of a batch, in file order
+type HashedEntries = Vec<HashedEntry>;
+
+struct HashedBatch{
+    sequence: u64,
+    //Entries of each target
+    entries: Result<Vec<HashedEntries>>
+}
+
+//An index being built: how its keys are extracted and where they are added
+pub struct IndexTarget{
+    pub key_extractor: KeyExtractor,
+    pub structure: IndexStructure,
+    pub hashmap_size: u128,
+    //Ordered index built together with the hash index, during the first pass
+    pub ordered: Option<OrderedIndexWriter>,
+}
+
+impl IndexTarget{
+    fn add_entry(&mut self, (hash, offset, sorted_key): HashedEntry) -> Result<()>{
+        self.structure.add_entry(hash, offset)?;
+        if let (Some(ordered), Some(sorted_key)) = (self.ordered.as_mut(), sorted_key) {
+            ordered.add(sorted_key, offset)?;
+        }
+        return Ok(());
+    }
+}
+
+//What the hashing threads need of a target
+#[derive(Clone)]
+struct KeyHasher{
+    key_extractor: KeyExtractor,
+    hashmap_size: u128,
+    //Set if the key is added to an ordered index
+    order: Option<KeyOrder>,
+}
+
+impl KeyHasher{
+    fn of_target(target: &IndexTarget) -> KeyHasher{
+        return KeyHasher{
+            key_extractor: target.key_extractor.clone(),
+            hashmap_size: target.hashmap_size,
+            order: target.ordered.as_ref().map(|ordered| ordered.order),
+        };
+    }
+
+    //line is the line of the input file where the record starts, used to report errors
+    fn entry(&self, record: &str, line: u64, offset: u64) -> Result<HashedEntry>{
+        let key = self.key_extractor.extract(record, line)?;
+        let sorted_key = match self.order {
+            Some(order) => Some(SortedKey::of_record(order, &key, line)?),
+            None => None,
+        };
+        return Ok((hash_function(&key, self.hashmap_size), offset, sorted_key));
+    }
+}
+
+//Iterate once over the input file, adding every record to the index structure of each target.
+//With more than one thread, reading, key extraction and hashing, and insertion run as a pipeline:
+//batches are inserted in file order, so the index is the same as the one built by a single thread.
+pub fn index_pass(input_reader: &mut dyn InputReader, targets: &mut [&mut IndexTarget], threads: usize) -> Result<()>{
+    let key_hashers: Vec<KeyHasher> = targets.iter().map(|target| KeyHasher::of_target(target)).collect();
+    if threads <= 1 {
+        let mut record = String::new();
+        while let Some(offset) = input_reader.read_record(&mut record)? {
+            for (target, key_hasher) in targets.iter_mut().zip(key_hashers.iter()) {
+                target.add_entry(key_hasher.entry(&record, input_reader.line(), offset)?)?;
+            }
+            record.clear();
+        }
+        return Ok(());
+    }
+    let targets: Vec<&mut IndexTarget> = targets.iter_mut().map(|target| &mut **target).collect();
+    return thread::scope(|scope| {
+        let (batch_sender, batch_receiver) = sync_channel::<RecordBatch>(threads * QUEUED_BATCHES);
+        let (hashed_sender, hashed_receiver) = sync_channel::<HashedBatch>(threads * QUEUED_BATCHES);
+        //Owned by the hashing threads only: when they all stop, the reader stops too
+        let batch_receiver = Arc::new(Mutex::new(batch_receiver));
+        for _ in 0..threads {
+            let batch_receiver = Arc::clone(&batch_receiver);
+            let hashed_sender = hashed_sender.clone();
+            let key_hashers = key_hashers.clone();
+            scope.spawn(move || {
+                loop {
+                    let batch = match batch_receiver.lock().unwrap().recv() {
+                        Ok(batch) => batch,
+                        Err(_) => break,
+                    };
+                    if hashed_sender.send(hash_batch(&batch, &key_hashers)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(batch_receiver);
+        drop(hashed_sender);
+        let inserter = scope.spawn(move || insert_batches(hashed_receiver, targets));
+        let read_result = read_batches(input_reader, batch_sender);
+        //Errors found while inserting come earlier in the file than reading ones
+        inserter.join().unwrap()?;
+        return read_result;
+    });
+}
+
+//Reader stage. On error, the records read so far are still sent before returning it
+fn read_batches(input_reader: &mut dyn InputReader, sender: SyncSender<RecordBatch>) -> Result<()>{
+    let mut record = String::new();
+    let mut sequence = 0;
+    loop{
+        let mut batch = RecordBatch{sequence, records: String::new(), entries: Vec::with_capacity(BATCH_SIZE)};
+        let mut result = Ok(true);
+        while batch.entries.len() < BATCH_SIZE {
+            record.clear();
+            match input_reader.read_record(&mut record) {
+                Ok(Some(offset)) => {
+                    batch.records.push_str(&record);
+                    batch.entries.push((offset, input_reader.line(), batch.records.len()));
+                }
+                Ok(None) => {
+                    result = Ok(false);
+                    break;
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        //If sending fails, the insertion stage stopped and reports its own error
+        if !batch.entries.is_empty() && sender.send(batch).is_err() {
+            return Ok(());
+        }
+        if !result? {
+            return Ok(());
+        }
+        sequence += 1;
+    }
+}
+
+//Key extraction and hashing stage, with the key hasher of each target
+fn hash_batch(batch: &RecordBatch, key_hashers: &[KeyHasher]) -> HashedBatch{
+    let mut entries: Vec<HashedEntries> = key_hashers.iter().map(|_| Vec::with_capacity(batch.entries.len())).collect();
+    let mut start = 0;
+    for &(offset, line, end) in batch.entries.iter() {
+        for (key_hasher, entries) in key_hashers.iter().zip(entries.iter_mut()) {
+            match key_hasher.entry(&batch.records[start..end], line, offset) {
+                Ok(entry) => entries.push(entry),
+                Err(e) => return HashedBatch{sequence: batch.sequence, entries: Err(e)},
+            }
+        }
+        start = end;
+    }
+    return HashedBatch{sequence: batch.sequence, entries: Ok(entries)};
+}
+
+//Insertion stage: batches may arrive out of order and are inserted by sequence number
+fn insert_batches(receiver: Receiver<HashedBatch>, mut targets: Vec<&mut IndexTarget>) -> Result<()>{
+    let mut pending: BTreeMap<u64, Result<Vec<HashedEntries>>> = BTreeMap::new();
+    let mut next_sequence = 0;
+    for batch in receiver {
+        pending.insert(batch.sequence, batch.entries);
+        while let Some(entries) = pending.remove(&next_sequence) {
+            for (target, entries) in targets.iter_mut().zip(entries?) {
+                for entry in entries {
+                    target.add_entry(entry)?;
+                }
+            }
+            next_sequence += 1;
+        }
+    }
+    return Ok(());
+}
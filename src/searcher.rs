@@ -0,0 +1,352 @@
+use std::borrow::Cow;
+use crate::error::{FastSeekError, Result};
+use crate::file_reader::{open_file_reader, FastqInputReader, FileReader, InputReader, MultiFastaInputReader, TabularInputReader, TabularOptions};
+use crate::{hash_function, index_path, resolve_source_path};
+use crate::fingerprint::Fingerprint;
+use crate::header::{Header, FLAG_BGZF_VIRTUAL_OFFSETS, FLAG_COMMENT_CHAR, FLAG_FINGERPRINT, FLAG_HAS_HEADER, FLAG_ID_TOKEN, FLAG_ORDERED_KEYS, FLAG_QUOTED_FIELDS, FLAG_WHITESPACE_SEPARATOR};
+use crate::index_reader::IndexReader;
+use crate::key_extractor::{split_quoted, KeyNormalization, Separator};
+use crate::ordered_index::{ordered_index_path, OrderedIndexReader, SortedKey};
+
+/// A record found by a search
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Match {
+    /// Offset of the record in the original file
+    pub offset: u64,
+    /// Key of the record, normalised as when indexing
+    pub key: String,
+    /// The record, without its line terminator
+    pub record: String,
+}
+
+/// Searches an indexed file. Index and original file are opened once and reused for every search.
+pub struct Searcher {
+    index_reader: IndexReader,
+    input_reader: Box<dyn InputReader>,
+    source_modified: bool,
+    filename: String,
+    index_path: String,
+    //Layout of the records, for tabular files
+    tabular_options: Option<TabularOptions>,
+    //Opened by the first prefix or range search
+    ordered_index: Option<OrderedIndexReader>,
+}
+
+impl Searcher {
+    /// Open `filename` and its index `<filename>.index`.
+    /// Fails with [`FastSeekError::StaleIndex`] if the size or content of the file changed since the index was built.
+    pub fn open(filename: &str) -> Result<Searcher> {
+        return Searcher::open_named(filename, None);
+    }
+
+    /// Open `filename` and its index named `name`, `<filename>.<name>.index`, or the default index if None.
+    pub fn open_named(filename: &str, name: Option<&str>) -> Result<Searcher> {
+        return Searcher::open_index(&index_path(filename, None, name), Some(filename));
+    }
+
+    /// Open the index at `index_path` and the file it indexes: `filename`, or if None the path
+    /// recorded in the index when it was built.
+    pub fn open_index(index_path: &str, filename: Option<&str>) -> Result<Searcher> {
+        //Get reader for index file
+        let index_reader = IndexReader::open(index_path)?;
+        let header = &index_reader.header;
+        let filename = match filename {
+            Some(filename) => filename.to_string(),
+            None if !header.source_path.is_empty() => resolve_source_path(index_path, &header.source_path),
+            None => return Err(FastSeekError::InvalidArgument(format!("index {} does not record the path of the indexed file", index_path))),
+        };
+        let filename = filename.as_str();
+
+        //Check that the file is the one indexed. Indexes built before fingerprints existed are trusted.
+        let mut source_modified = false;
+        if header.has_flag(FLAG_FINGERPRINT) {
+            let fingerprint = Fingerprint::of_file(filename)?;
+            if let Some(reason) = header.fingerprint.mismatch(&fingerprint) {
+                return Err(FastSeekError::StaleIndex(reason));
+            }
+            source_modified = fingerprint.mtime != header.fingerprint.mtime;
+        }
+
+        //Get reader for original file
+        let mut original_file_reader = open_file_reader(filename)?;
+        //Indexes of BGZF files built before virtual offsets store positions in the uncompressed data
+        if !header.has_flag(FLAG_BGZF_VIRTUAL_OFFSETS) {
+            original_file_reader.use_uncompressed_offsets(filename)?;
+        }
+        let input_reader = input_reader_of_header(header, original_file_reader)?;
+        let tabular_options = match header.index_type {
+            0 => Some(tabular_options(header)),
+            _ => None,
+        };
+        return Ok(Searcher{
+            index_reader, input_reader, source_modified, filename: filename.to_string(), index_path: index_path.to_string(),
+            tabular_options, ordered_index: None
+        });
+    }
+
+    /// Header line of the indexed file, if it was indexed with one
+    pub fn header_line(&self) -> Option<&str> {
+        let header = &self.index_reader.header;
+        return match header.has_flag(FLAG_HAS_HEADER) {
+            true => Some(&header.header_line),
+            false => None,
+        };
+    }
+
+    /// Fields of a record of a tabular file, split as when indexing: csv fields without their quotes
+    pub fn record_fields(&self, record: &str) -> Result<Vec<String>> {
+        let options = match &self.tabular_options {
+            Some(options) => options,
+            None => return Err(FastSeekError::InvalidArgument("fields are only available for tabular files".to_string())),
+        };
+        return match options.quote {
+            Some(quote) => match split_quoted(record, options.separator.as_str(), quote) {
+                Ok(fields) => Ok(fields.into_iter().map(Cow::into_owned).collect()),
+                Err(reason) => Err(FastSeekError::MalformedRecord{line: 0, reason}),
+            },
+            None => Ok(options.separator.split(record.trim_end_matches(['\n', '\r'])).map(str::to_string).collect()),
+        };
+    }
+
+    /// Separator of the fields of a tabular file, a space if fields are separated by whitespace. Empty for other files.
+    pub fn separator(&self) -> &str {
+        return match &self.tabular_options {
+            Some(options) => options.separator.as_str(),
+            None => "",
+        };
+    }
+
+    /// Quote character of the fields of a csv file, if it was indexed with one
+    pub fn quote(&self) -> Option<char> {
+        return self.tabular_options.as_ref().and_then(|options| options.quote);
+    }
+
+    /// Path of the indexed file
+    pub fn filename(&self) -> &str {
+        return &self.filename;
+    }
+
+    /// True if the file was modified after the index was built, although its size and the content
+    /// of its first and last blocks did not change. The index may be stale, see [`Searcher::verify`].
+    pub fn source_modified(&self) -> bool {
+        return self.source_modified;
+    }
+
+    /// Checks up to `samples` hashmap slots spread over the whole hashmap: every offset stored
+    /// must point to a record whose key hashes to the slot. Returns the number of records checked,
+    /// or [`FastSeekError::StaleIndex`] at the first mismatch.
+    pub fn verify(&mut self, samples: u64) -> Result<u64> {
+        let hashmap_size = self.index_reader.header.hashmap_size;
+        let samples = samples.clamp(1, hashmap_size);
+        let mut record = String::new();
+        let mut checked = 0;
+        for i in 0..samples {
+            let start = (i as u128 * hashmap_size as u128 / samples as u128) as u64;
+            let end = ((i + 1) as u128 * hashmap_size as u128 / samples as u128) as u64;
+            //First non-empty slot of the range
+            for hash in start..end {
+                let offsets = self.index_reader.get_candidate_offsets(hash)?;
+                if offsets.is_empty() {
+                    continue;
+                }
+                for offset in offsets {
+                    record.clear();
+                    self.input_reader.seek(offset)?;
+                    let key_hash = match self.input_reader.read_record(&mut record) {
+                        Ok(Some(_)) => self.input_reader.key_extractor().extract(&record, 0).ok()
+                            .map(|key| hash_function(&key, hashmap_size as u128)),
+                        Ok(None) | Err(FastSeekError::MalformedRecord{..}) => None,
+                        Err(e) => return Err(e),
+                    };
+                    if key_hash != Some(hash) {
+                        return Err(FastSeekError::StaleIndex(format!("offset {} does not point to a record of hashmap slot {}", offset, hash)));
+                    }
+                    checked += 1;
+                }
+                break;
+            }
+        }
+        return Ok(checked);
+    }
+
+    /// Builds the key of an index on several columns from the value of each column,
+    /// joining them with the separator. A single value is returned as it is.
+    pub fn join_key(&self, values: &[String]) -> Result<String> {
+        let header = &self.index_reader.header;
+        if values.len() == 1 {
+            return Ok(values[0].clone());
+        }
+        if header.index_type != 0 || values.len() != header.columns.len() {
+            return Err(FastSeekError::InvalidArgument(format!(
+                "got {} key values, the index has {} key columns", values.len(), header.columns.len()
+            )));
+        }
+        return Ok(match header.has_flag(FLAG_WHITESPACE_SEPARATOR) {
+            true => values.join(" "),
+            false => values.join(&header.separator),
+        });
+    }
+
+    /// Returns every record matching key, in file order
+    pub fn search(&mut self, key: &str) -> Result<Vec<String>> {
+        return Ok(self.search_batch(&[key.to_string()], true)?.pop().unwrap());
+    }
+
+    /// Returns the first record matching key, in file order
+    pub fn search_first(&mut self, key: &str) -> Result<Option<String>> {
+        return Ok(self.search_batch(&[key.to_string()], false)?.pop().unwrap().pop());
+    }
+
+    /// Returns the offsets in the original file of every record matching key, in file order
+    pub fn search_offsets(&mut self, key: &str) -> Result<Vec<u64>> {
+        return Ok(self.search_matches(&[key.to_string()], true)?.pop().unwrap()
+            .into_iter().map(|found| found.offset).collect());
+    }
+
+    /// Searches many keys at once, returning the matching records of each key in the same order as keys.
+    /// The hashmap is visited in ascending offset order to keep disk reads sequential.
+    /// If all_matches is false, only the first match of each key is returned.
+    pub fn search_batch(&mut self, keys: &[String], all_matches: bool) -> Result<Vec<Vec<String>>> {
+        return Ok(self.search_matches(keys, all_matches)?.into_iter()
+            .map(|matches| matches.into_iter().map(|found| found.record).collect())
+            .collect());
+    }
+
+    /// As [`search_batch`](Searcher::search_batch), returning the offset and key of each record too
+    pub fn search_matches(&mut self, keys: &[String], all_matches: bool) -> Result<Vec<Vec<Match>>> {
+        let hashmap_size = self.index_reader.header.hashmap_size as u128;
+        //Searched keys get the same normalisation as the indexed ones
+        let key_extractor = self.input_reader.key_extractor();
+        let keys: Vec<Cow<str>> = keys.iter().map(|key| key_extractor.normalize_query(key)).collect();
+        let mut lookups: Vec<(u64, usize)> = keys.iter().enumerate().map(|(i, key)| {
+            (hash_function(key, hashmap_size), i)
+        }).collect();
+        lookups.sort_unstable();
+
+        let mut results: Vec<Vec<Match>> = vec![vec![]; keys.len()];
+        let mut test_buffer: String = String::new();
+        for (hash_value, i) in lookups{
+            for file_offset in self.index_reader.get_candidate_offsets(hash_value)?{
+                test_buffer.clear();
+                if self.input_reader.test_and_return_entry(file_offset, &keys[i], &mut test_buffer)?{
+                    let key = self.input_reader.key_extractor().extract(&test_buffer, 0)?.to_string();
+                    let record = test_buffer.trim_end_matches(['\n', '\r']).to_string();
+                    results[i].push(Match{offset: file_offset, key, record});
+                    if !all_matches {
+                        break;
+                    }
+                }
+            }
+        }
+        return Ok(results);
+    }
+
+    /// Records whose key starts with `prefix`, in key order. The index must have been built with
+    /// [`IndexBuilder::ordered`](crate::IndexBuilder::ordered) and [`KeyOrder::Lexicographic`](crate::KeyOrder::Lexicographic).
+    pub fn search_prefix(&mut self, prefix: &str) -> Result<Vec<String>> {
+        return Ok(self.prefix_matches(prefix)?.into_iter().map(|found| found.record).collect());
+    }
+
+    /// As [`search_prefix`](Searcher::search_prefix), returning the offset and key of each record too
+    pub fn prefix_matches(&mut self, prefix: &str) -> Result<Vec<Match>> {
+        let prefix = self.input_reader.key_extractor().normalize_query(prefix).to_string();
+        let offsets = self.ordered_index()?.prefix(&prefix)?;
+        return self.read_matches(&offsets);
+    }
+
+    /// Records whose key is between `low` and `high`, both included, in key order. Without `low` or `high`,
+    /// the range is open on that side. The index must have been built with [`IndexBuilder::ordered`](crate::IndexBuilder::ordered),
+    /// keys are compared in the order chosen then.
+    pub fn search_range(&mut self, low: Option<&str>, high: Option<&str>) -> Result<Vec<String>> {
+        return Ok(self.range_matches(low, high)?.into_iter().map(|found| found.record).collect());
+    }
+
+    /// As [`search_range`](Searcher::search_range), returning the offset and key of each record too
+    pub fn range_matches(&mut self, low: Option<&str>, high: Option<&str>) -> Result<Vec<Match>> {
+        let low = self.range_bound(low)?;
+        let high = self.range_bound(high)?;
+        let offsets = self.ordered_index()?.range(low.as_ref(), high.as_ref())?;
+        return self.read_matches(&offsets);
+    }
+
+    fn ordered_index(&mut self) -> Result<&mut OrderedIndexReader> {
+        if self.ordered_index.is_none() {
+            let header = &self.index_reader.header;
+            if !header.has_flag(FLAG_ORDERED_KEYS) {
+                return Err(FastSeekError::InvalidArgument(format!("index {} was built without ordered keys", self.index_path)));
+            }
+            self.ordered_index = Some(OrderedIndexReader::open(&ordered_index_path(&self.index_path), &header.fingerprint)?);
+        }
+        return Ok(self.ordered_index.as_mut().unwrap());
+    }
+
+    fn range_bound(&mut self, bound: Option<&str>) -> Result<Option<SortedKey>> {
+        let bound = match bound {
+            Some(bound) => self.input_reader.key_extractor().normalize_query(bound).to_string(),
+            None => return Ok(None),
+        };
+        let order = self.ordered_index()?.order;
+        return match SortedKey::parse(order, &bound) {
+            Some(bound) => Ok(Some(bound)),
+            None => Err(FastSeekError::InvalidArgument(format!("range bound {:?} is not a number", bound))),
+        };
+    }
+
+    //Records at offsets, with their keys
+    fn read_matches(&mut self, offsets: &[u64]) -> Result<Vec<Match>> {
+        let mut matches = vec![];
+        let mut record = String::new();
+        for &offset in offsets {
+            record.clear();
+            self.input_reader.seek(offset)?;
+            if self.input_reader.read_record(&mut record)?.is_none() {
+                return Err(FastSeekError::StaleIndex(format!("offset {} does not point to a record", offset)));
+            }
+            let key = self.input_reader.key_extractor().extract(&record, 0)?.to_string();
+            matches.push(Match{offset, key, record: record.trim_end_matches(['\n', '\r']).to_string()});
+        }
+        return Ok(matches);
+    }
+}
+
+//How the records of the tabular file indexed with header are laid out
+fn tabular_options(header: &Header) -> TabularOptions{
+    return TabularOptions{
+        separator: match header.has_flag(FLAG_WHITESPACE_SEPARATOR) {
+            true => Separator::Whitespace,
+            false => Separator::Text(header.separator.clone()),
+        },
+        quote: match header.has_flag(FLAG_QUOTED_FIELDS) {
+            true => Some(header.quote as char),
+            false => None,
+        },
+        has_header: header.has_flag(FLAG_HAS_HEADER),
+        comment_char: match header.has_flag(FLAG_COMMENT_CHAR) {
+            true => Some(header.comment_char as char),
+            false => None,
+        },
+    };
+}
+
+//Reader of the records of the file indexed with header, extracting their keys as when indexing
+pub fn input_reader_of_header(header: &Header, file_reader: Box<dyn FileReader>) -> Result<Box<dyn InputReader>>{
+    let id_token = match header.has_flag(FLAG_ID_TOKEN) {
+        true => Some(header.id_token as usize),
+        false => None,
+    };
+    //0 if FLAG_NORMALIZED_KEYS is not set: keys are compared as indexes built before normalisation expect
+    let normalization = KeyNormalization::from_bits(header.normalization);
+    return Ok(match header.index_type {
+        0 => Box::new(TabularInputReader::new(
+            file_reader,
+            tabular_options(header),
+            &header.columns.iter().map(|&column| column as usize).collect::<Vec<usize>>(),
+            normalization
+        )),
+        1 => Box::new(MultiFastaInputReader::new(file_reader, false, id_token, normalization)),
+        2 => Box::new(MultiFastaInputReader::new(file_reader, true, None, normalization)),
+        3 => Box::new(FastqInputReader::new(file_reader, false, id_token, normalization)),
+        4 => Box::new(FastqInputReader::new(file_reader, true, None, normalization)),
+        index_type => return Err(FastSeekError::CorruptIndex(format!("unknown index type {}", index_type)))
+    });
+}
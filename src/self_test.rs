@@ -0,0 +1,973 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use bgzip::{BGZFWriter, Compression};
+use flate2::write::GzEncoder;
+use fastseek::{bgzip, update_index, zstd_seekable, CaseFolding, FastSeekError, Format, IndexBuilder, IndexCompression, KeyOrder, Searcher};
+
+const TEST_LEN: u32 = 100;
+
+fn found(filename: &str, key: &str) -> bool{
+    return Searcher::open(filename).unwrap().search_first(key).unwrap().is_some();
+}
+
+fn run_test_fastq(in_memory_map_size: u64){
+    let path = Path::new("test_data/test.fastq");
+    let file = File::create(path).unwrap();
+    let mut writer = io::BufWriter::new(file);
+    for i in 0..TEST_LEN {
+        let string = format!("@prova{}", i);
+        let _ = writer.write_all(format!("{}\nGGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}\n+\n!''*((((***+))%%%++)(%%%%).1***-+*''))**55CCF>>>>>>CCCCCCC65\n", string, i).as_bytes());
+    }
+    let _ = writer.flush();
+    IndexBuilder::new("test_data/test.fastq", Format::Fastq).by_sequence(false).in_memory_map_size(in_memory_map_size).build().unwrap();
+    for i in 0..TEST_LEN {
+        assert! (found("test_data/test.fastq", &format!("@prova{}", i)));
+    }
+    assert! (!found("test_data/test.fastq", "NOT_EXISTING"));
+    IndexBuilder::new("test_data/test.fastq", Format::Fastq).by_sequence(true).in_memory_map_size(in_memory_map_size).build().unwrap();
+    for i in 0..TEST_LEN {
+        assert! (found("test_data/test.fastq", &format!("GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}", i)));
+    }
+    assert! (!found("test_data/test.fastq", "NOT_EXISTING"));
+}
+
+fn run_test_fasta(in_memory_map_size: u64){
+    let path = Path::new("test_data/multi.fasta");
+    let file = File::create(path).unwrap();
+    let mut writer = io::BufWriter::new(file);
+    for i in 0..TEST_LEN {
+        let string = format!(">prova{}", i);
+        let _ = writer.write_all(format!("{}\nGGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}\n", string, i).as_bytes());
+        //writer.write_line();
+    }
+    let _ = writer.flush();
+    IndexBuilder::new("test_data/multi.fasta", Format::Fasta).by_sequence(false).in_memory_map_size(in_memory_map_size).build().unwrap();
+    for i in 0..TEST_LEN {
+        assert! (found("test_data/multi.fasta", &format!(">prova{}", i)));
+    }
+    assert! (!found("test_data/multi.fasta", "NOT_EXISTING"));
+    IndexBuilder::new("test_data/multi.fasta", Format::Fasta).by_sequence(true).in_memory_map_size(in_memory_map_size).build().unwrap();
+    for i in 0..TEST_LEN {
+        assert! (found("test_data/multi.fasta", &format!("GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}", i)));
+    }
+    assert! (!found("test_data/multi.fasta", "NOT_EXISTING"));
+}
+
+fn run_test_wrapped_fasta(in_memory_map_size: u64){
+    let path = Path::new("test_data/wrapped.fasta");
+    let mut writer = io::BufWriter::new(File::create(path).unwrap());
+    let mut records: Vec<(String, String)> = vec![];
+    for i in 0..TEST_LEN {
+        //Sequences of different lengths, wrapped at 60 columns
+        let sequence: String = format!("GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}", i).repeat(1 + (i % 5) as usize);
+        let mut record = format!(">prova{} description", i);
+        for line in sequence.as_bytes().chunks(60) {
+            record.push('\n');
+            record.push_str(std::str::from_utf8(line).unwrap());
+        }
+        let _ = writer.write_all(format!("{}\n", record).as_bytes());
+        records.push((sequence, record));
+    }
+    let _ = writer.flush();
+    IndexBuilder::new("test_data/wrapped.fasta", Format::Fasta).in_memory_map_size(in_memory_map_size).build().unwrap();
+    //One hashmap entry per record, not per pair of lines
+    let index_size = std::fs::metadata("test_data/wrapped.fasta.index").unwrap().len();
+    assert! (index_size < 14 + 8 * 2 * TEST_LEN as u64);
+    let mut searcher = Searcher::open("test_data/wrapped.fasta").unwrap();
+    for (i, (_, record)) in records.iter().enumerate() {
+        assert! (searcher.search(&format!(">prova{} description", i)).unwrap() == vec![record.clone()]);
+    }
+    IndexBuilder::new("test_data/wrapped.fasta", Format::Fasta).by_sequence(true).in_memory_map_size(in_memory_map_size).build().unwrap();
+    let mut searcher = Searcher::open("test_data/wrapped.fasta").unwrap();
+    for (sequence, record) in records.iter() {
+        assert! (searcher.search(sequence).unwrap() == vec![record.clone()]);
+    }
+    assert! (searcher.search_first("GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA1").unwrap().is_none());
+}
+
+fn run_test_id_token(){
+    let path = Path::new("test_data/comments.fastq");
+    let mut writer = io::BufWriter::new(File::create(path).unwrap());
+    for i in 0..TEST_LEN {
+        let _ = writer.write_all(format!("@read{} 1:N:0:{}\nGGTCAGCCCTCAAGG\n+\nIIIIIIIIIIIIIII\n", i, i % 7).as_bytes());
+    }
+    let _ = writer.flush();
+    IndexBuilder::new("test_data/comments.fastq", Format::Fastq).id_token(0).build().unwrap();
+    let mut searcher = Searcher::open("test_data/comments.fastq").unwrap();
+    for i in 0..TEST_LEN {
+        let expected = format!("@read{} 1:N:0:{}\nGGTCAGCCCTCAAGG\n+\nIIIIIIIIIIIIIII", i, i % 7);
+        assert! (searcher.search(&format!("read{}", i)).unwrap() == vec![expected.clone()]);
+        //Whole header lines are normalised like the indexed ones
+        assert! (searcher.search(&format!("@read{} 2:Y", i)).unwrap() == vec![expected]);
+    }
+    assert! (searcher.search_first("read").unwrap().is_none());
+    //Second token
+    IndexBuilder::new("test_data/comments.fastq", Format::Fastq).id_token(1).build().unwrap();
+    assert! (Searcher::open("test_data/comments.fastq").unwrap().search("1:N:0:3").unwrap().len() == (TEST_LEN / 7) as usize);
+    //Fasta, relies on the file written by run_test_wrapped_fasta
+    IndexBuilder::new("test_data/wrapped.fasta", Format::Fasta).id_token(0).build().unwrap();
+    let record = Searcher::open("test_data/wrapped.fasta").unwrap().search_first("prova42").unwrap().unwrap();
+    assert! (record.starts_with(">prova42 description\nGGTCAG"));
+    let result = IndexBuilder::new("test_data/wrapped.fasta", Format::Fasta).by_sequence(true).id_token(0).build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+}
+
+fn run_test(in_memory_map_size: u64){
+    let path = Path::new("test_data/test.csv");
+    let file = File::create(path).unwrap();
+    let mut writer = io::BufWriter::new(file);
+    for i in 0..TEST_LEN {
+        let string = format!("prova{}", i);
+        let _ = writer.write_all(format!("1,{},0,0,0,eruheigrnei,L{}\n", string, string).as_bytes());
+        //writer.write_line();
+    }
+    let _ = writer.flush();
+    IndexBuilder::new("test_data/test.csv", Format::Tabular).column(1).separator(",").in_memory_map_size(in_memory_map_size).build().unwrap();
+    for i in 0..TEST_LEN {
+        assert! (found("test_data/test.csv", &format!("prova{}", i)));
+    }
+    assert! (!found("test_data/test.csv", "NOT_EXISTING"));
+    IndexBuilder::new("test_data/test.csv", Format::Tabular).column(6).separator(",").in_memory_map_size(in_memory_map_size).build().unwrap();
+    for i in 0..TEST_LEN {
+        assert! (found("test_data/test.csv", &format!("Lprova{}", i)));
+    }
+    assert! (!found("test_data/test.csv", "NOT_EXISTING"));
+}
+fn run_test_compressed(){
+    let path = Path::new("test_data/test.csv.gz");
+    let file = File::create(path).unwrap();
+    let buf_writer = io::BufWriter::new(file);
+    let mut writer = BGZFWriter::new(buf_writer, Compression::default());
+    for i in 0..TEST_LEN {
+        let string = format!("prova{}", i);
+        let _ =writer.write_all(format!("1,{},0,0,0,eruheigrneiugrheriuhg,ergbneirgbeiugberiugberiuhg\n", string).as_bytes());
+    }
+    let _ = writer.flush();
+    let _ = writer.close();
+    IndexBuilder::new("test_data/test.csv.gz", Format::Tabular).column(1).separator(",").in_memory_map_size(1000).build().unwrap();
+    for i in 0..TEST_LEN {
+        assert! (found("test_data/test.csv.gz", &format!("prova{}", i)));
+    }
+    assert! (!found("test_data/test.csv.gz", "NOT_EXISTING"));
+}
+fn run_test_bgzf_blocks(){
+    //Several BGZF blocks, so that virtual offsets differ from positions in the uncompressed data
+    let mut writer = BGZFWriter::new(io::BufWriter::new(File::create("test_data/blocks.csv.gz").unwrap()), Compression::default());
+    let mut plain = io::BufWriter::new(File::create("test_data/blocks.csv").unwrap());
+    for i in 0..5000 {
+        let line = format!("{},key{},eruheigrneiugrheriuhg\n", i, i);
+        let _ = writer.write_all(line.as_bytes());
+        let _ = plain.write_all(line.as_bytes());
+    }
+    let _ = writer.close();
+    let _ = plain.flush();
+    let _ = std::fs::remove_file("test_data/blocks.csv.gz.gzi");
+    IndexBuilder::new("test_data/blocks.csv.gz", Format::Tabular).column(1).separator(",").gzi(true).build().unwrap();
+    let mut searcher = Searcher::open("test_data/blocks.csv.gz").unwrap();
+    for i in 0..5000 {
+        assert! (searcher.search(&format!("key{}", i)).unwrap() == vec![format!("{},key{},eruheigrneiugrheriuhg", i, i)]);
+    }
+    assert! (searcher.search_offsets("key4999").unwrap()[0] >> 16 > 0);
+    assert! (searcher.verify(1000).unwrap() > 0);
+    //samtools .gzi: entry count, then 16 bytes for each block but the first
+    let gzi = std::fs::read("test_data/blocks.csv.gz.gzi").unwrap();
+    let blocks = u64::from_le_bytes(gzi[0..8].try_into().unwrap());
+    assert! (blocks > 0 && gzi.len() as u64 == 8 + 16 * blocks);
+    //Indexes built before virtual offsets store positions in the uncompressed data: an index of the
+    //uncompressed file without the flags for virtual offsets (0x1000), fingerprint (0x10) and updates (0x2000) is one
+    IndexBuilder::new("test_data/blocks.csv", Format::Tabular).column(1).separator(",").build().unwrap();
+    let mut index = std::fs::read("test_data/blocks.csv.index").unwrap();
+    let flags = u32::from_be_bytes(index[13..17].try_into().unwrap()) & !0x3010;
+    index[13..17].copy_from_slice(&flags.to_be_bytes());
+    std::fs::write("test_data/blocks_legacy.index", &index).unwrap();
+    //Translated with the .gzi, then with the block headers
+    for remove_gzi in [false, true] {
+        if remove_gzi {
+            std::fs::remove_file("test_data/blocks.csv.gz.gzi").unwrap();
+        }
+        let mut legacy = Searcher::open_index("test_data/blocks_legacy.index", Some("test_data/blocks.csv.gz")).unwrap();
+        for i in (0..5000).step_by(7) {
+            let key = format!("key{}", i);
+            assert! (legacy.search(&key).unwrap() == searcher.search(&key).unwrap());
+        }
+        assert! (legacy.search_offsets("key4999").unwrap() == Searcher::open("test_data/blocks.csv").unwrap().search_offsets("key4999").unwrap());
+    }
+    //Only BGZF files have block indexes
+    let result = IndexBuilder::new("test_data/blocks.csv", Format::Tabular).column(1).separator(",").gzi(true).build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+}
+fn run_test_plain_gzip(){
+    let mut records = String::new();
+    for i in 0..3000 {
+        records.push_str(&format!("@read{}\nACGTTGCA{}\n+\nIIIIIIII\n", i, i));
+    }
+    let mut writer = GzEncoder::new(File::create("test_data/plain.fastq.gz").unwrap(), flate2::Compression::default());
+    let _ = writer.write_all(records.as_bytes());
+    let _ = writer.finish();
+    //Told by its content: plain gzip is indexed with checkpoints
+    IndexBuilder::new("test_data/plain.fastq.gz", Format::Fastq).build().unwrap();
+    assert! (Path::new("test_data/plain.fastq.gz.gzc").exists());
+    //Converted and indexed in one go, in place
+    IndexBuilder::new("test_data/plain.fastq.gz", Format::Fastq).bgzip_source("test_data/plain.fastq.gz").gzi(true).build().unwrap();
+    assert! (!Path::new("test_data/plain.fastq.gz.tmp").exists() && Path::new("test_data/plain.fastq.gz.gzi").exists());
+    assert! (!Path::new("test_data/plain.fastq.gz.gzc").exists());
+    let mut searcher = Searcher::open("test_data/plain.fastq.gz").unwrap();
+    for i in 0..3000 {
+        assert! (searcher.search(&format!("@read{}", i)).unwrap() == vec![format!("@read{}\nACGTTGCA{}\n+\nIIIIIIII", i, i)]);
+    }
+    assert! (searcher.verify(1000).unwrap() > 0);
+    //Uncompressed files, whatever their name: converting then indexing finds the same records
+    std::fs::write("test_data/plain_text.gz", &records).unwrap();
+    IndexBuilder::new("test_data/plain_text.gz", Format::Fastq).build().unwrap();
+    assert! (found("test_data/plain_text.gz", "@read2999"));
+    bgzip("test_data/plain_text.gz", "test_data/converted.dat", false).unwrap();
+    IndexBuilder::new("test_data/converted.dat", Format::Fastq).build().unwrap();
+    let mut converted = Searcher::open("test_data/converted.dat").unwrap();
+    assert! (converted.search_offsets("@read2999").unwrap() == searcher.search_offsets("@read2999").unwrap());
+    //Hashmap size given, and tabular files with a header line, whose records are read while converting
+    std::fs::write("test_data/plain_header.csv", "id,value\na,1\nb,2\n").unwrap();
+    IndexBuilder::new("test_data/plain_header.csv.gz", Format::Tabular).separator(",").has_header(true).column_name("value").hashmap_size(5)
+        .bgzip_source("test_data/plain_header.csv").build().unwrap();
+    let mut searcher = Searcher::open("test_data/plain_header.csv.gz").unwrap();
+    assert! (searcher.search("2").unwrap() == vec!["b,2".to_string()]);
+    assert! (searcher.header_line() == Some("id,value"));
+}
+fn run_test_gzip_checkpoints(){
+    //About 1 MiB, in many deflate blocks, as two gzip members: the second stored, with a file name in its header
+    let mut records = String::new();
+    for i in 0..30000 {
+        records.push_str(&format!("key{},{},{}\n", i, i * 7919 % 10007, "ACGT".repeat(i % 13)));
+    }
+    let split = records.len() / 2 + records[records.len() / 2..].find('\n').unwrap() + 1;
+    let mut file = File::create("test_data/checkpoints.csv.gz").unwrap();
+    let mut writer = GzEncoder::new(&mut file, flate2::Compression::default());
+    let _ = writer.write_all(&records.as_bytes()[..split]);
+    let _ = writer.finish();
+    let mut writer = flate2::GzBuilder::new().filename("second").write(&mut file, flate2::Compression::none());
+    let _ = writer.write_all(&records.as_bytes()[split..]);
+    let _ = writer.finish();
+    drop(file);
+    IndexBuilder::new("test_data/checkpoints.csv.gz", Format::Tabular).separator(",").checkpoint_interval(32*1024).build().unwrap();
+    let checkpoints_size = std::fs::metadata("test_data/checkpoints.csv.gz.gzc").unwrap().len();
+    let mut searcher = Searcher::open("test_data/checkpoints.csv.gz").unwrap();
+    //Out of order, so that seeks go back as well as forward
+    for i in (0..30000).step_by(97).rev().chain((0..30000).step_by(1013)) {
+        assert! (searcher.search(&format!("key{}", i)).unwrap() == vec![format!("key{},{},{}", i, i * 7919 % 10007, "ACGT".repeat(i % 13))]);
+    }
+    assert! (searcher.verify(500).unwrap() > 0);
+    //Checkpoints with the same interval are reused, a different interval records them again
+    IndexBuilder::new("test_data/checkpoints.csv.gz", Format::Tabular).separator(",").column(1).name("value").checkpoint_interval(32*1024).build().unwrap();
+    assert! (std::fs::metadata("test_data/checkpoints.csv.gz.gzc").unwrap().len() == checkpoints_size);
+    IndexBuilder::new("test_data/checkpoints.csv.gz", Format::Tabular).separator(",").checkpoint_interval(1024*1024).build().unwrap();
+    assert! (std::fs::metadata("test_data/checkpoints.csv.gz.gzc").unwrap().len() < checkpoints_size);
+    assert! (found("test_data/checkpoints.csv.gz", "key29999"));
+    //Without checkpoints, the file can't be searched
+    std::fs::remove_file("test_data/checkpoints.csv.gz.gzc").unwrap();
+    assert! (matches!(Searcher::open("test_data/checkpoints.csv.gz").and_then(|mut searcher| searcher.search("key1")), Err(FastSeekError::InvalidArgument(_))));
+}
+fn run_test_zstd_seekable(){
+    let mut records = String::new();
+    for i in 0..5000 {
+        records.push_str(&format!("key{}\t{}\n", i, "ACGT".repeat(i % 7)));
+    }
+    std::fs::write("test_data/records.tsv", &records).unwrap();
+    std::fs::write("test_data/records.tsv.zst", zstd::encode_all(records.as_bytes(), 0).unwrap()).unwrap();
+    //Told by its content: zstd without a seek table can't be indexed
+    let result = IndexBuilder::new("test_data/records.tsv.zst", Format::Tabular).build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+    //Converted in place, in small frames so that records span them
+    zstd_seekable("test_data/records.tsv.zst", "test_data/records.tsv.zst", 4000).unwrap();
+    IndexBuilder::new("test_data/records.tsv.zst", Format::Tabular).build().unwrap();
+    IndexBuilder::new("test_data/records.tsv", Format::Tabular).build().unwrap();
+    let mut searcher = Searcher::open("test_data/records.tsv.zst").unwrap();
+    let mut plain = Searcher::open("test_data/records.tsv").unwrap();
+    for i in (0..5000).rev().step_by(3) {
+        let key = format!("key{}", i);
+        assert! (searcher.search(&key).unwrap() == vec![format!("key{}\t{}", i, "ACGT".repeat(i % 7))]);
+        //Offsets are positions in the decompressed data
+        assert! (searcher.search_offsets(&key).unwrap() == plain.search_offsets(&key).unwrap());
+    }
+    assert! (searcher.verify(1000).unwrap() > 0);
+    //Seekable files convert back, and empty files have no frames
+    bgzip("test_data/records.tsv.zst", "test_data/records_from_zstd.tsv.gz", false).unwrap();
+    IndexBuilder::new("test_data/records_from_zstd.tsv.gz", Format::Tabular).build().unwrap();
+    assert! (found("test_data/records_from_zstd.tsv.gz", "key4999"));
+    std::fs::write("test_data/empty.tsv", "").unwrap();
+    zstd_seekable("test_data/empty.tsv", "test_data/empty.tsv.zst", 4000).unwrap();
+    IndexBuilder::new("test_data/empty.tsv.zst", Format::Tabular).build().unwrap();
+    assert! (!found("test_data/empty.tsv.zst", "key1"));
+}
+fn run_test_duplicates(in_memory_map_size: u64, contiguous_blocks: bool){
+    let path = Path::new("test_data/duplicates.csv");
+    let file = File::create(path).unwrap();
+    let mut writer = io::BufWriter::new(file);
+    for i in 0..TEST_LEN {
+        let _ = writer.write_all(format!("{},prova{},{}\n", i, i % 10, i).as_bytes());
+    }
+    let _ = writer.flush();
+    IndexBuilder::new("test_data/duplicates.csv", Format::Tabular).column(1).separator(",").in_memory_map_size(in_memory_map_size).contiguous_blocks(contiguous_blocks).build().unwrap();
+    let mut searcher = Searcher::open("test_data/duplicates.csv").unwrap();
+    for i in 0..10 {
+        let matches = searcher.search(&format!("prova{}", i)).unwrap();
+        assert! (matches.len() == (TEST_LEN / 10) as usize);
+        //Matches must come out in file order
+        for (j, entry) in matches.iter().enumerate() {
+            assert! (entry.starts_with(&format!("{},", i + 10 * j as u32)));
+        }
+        assert! (searcher.search_first(&format!("prova{}", i)).unwrap().unwrap() == matches[0]);
+        assert! (searcher.search_offsets(&format!("prova{}", i)).unwrap().len() == matches.len());
+    }
+    assert! (searcher.search("NOT_EXISTING").unwrap().is_empty());
+}
+fn run_test_contiguous_blocks_size(){
+    //Relies on the file written by run_test_duplicates
+    IndexBuilder::new("test_data/duplicates.csv", Format::Tabular).column(1).separator(",").hashmap_size(10).build().unwrap();
+    let linked_size = std::fs::metadata("test_data/duplicates.csv.index").unwrap().len();
+    IndexBuilder::new("test_data/duplicates.csv", Format::Tabular).column(1).separator(",").hashmap_size(10).contiguous_blocks(true).build().unwrap();
+    let contiguous_size = std::fs::metadata("test_data/duplicates.csv.index").unwrap().len();
+    assert! (contiguous_size < linked_size);
+    let mut searcher = Searcher::open("test_data/duplicates.csv").unwrap();
+    for i in 0..10 {
+        assert! (searcher.search(&format!("prova{}", i)).unwrap().len() == (TEST_LEN / 10) as usize);
+    }
+}
+fn run_test_compressed_index(){
+    //Relies on the file written by run_test_duplicates
+    let mut keywords: Vec<String> = (0..10).map(|i| format!("prova{}", i)).collect();
+    keywords.push("NOT_EXISTING".to_string());
+    IndexBuilder::new("test_data/duplicates.csv", Format::Tabular).column(1).separator(",").hashmap_size(7).build().unwrap();
+    let uncompressed_size = std::fs::metadata("test_data/duplicates.csv.index").unwrap().len();
+    let expected = Searcher::open("test_data/duplicates.csv").unwrap().search_batch(&keywords, true).unwrap();
+    for compression in [IndexCompression::Blocks, IndexCompression::Full] {
+        for contiguous_blocks in [false, true] {
+            //in_memory_map_size 3 forces multiple iterations over the file
+            IndexBuilder::new("test_data/duplicates.csv", Format::Tabular).column(1).separator(",").hashmap_size(7)
+                .in_memory_map_size(3).contiguous_blocks(contiguous_blocks).compression(compression).build().unwrap();
+            assert! (!Path::new("test_data/duplicates.csv.index.tmp").exists());
+            assert! (std::fs::metadata("test_data/duplicates.csv.index").unwrap().len() < uncompressed_size);
+            let results = Searcher::open("test_data/duplicates.csv").unwrap().search_batch(&keywords, true).unwrap();
+            assert! (results == expected);
+        }
+    }
+    //Large enough to need multiple block chunks
+    let path = Path::new("test_data/many_duplicates.csv");
+    let mut writer = io::BufWriter::new(File::create(path).unwrap());
+    for i in 0..50000 {
+        let _ = writer.write_all(format!("{},prova{}\n", i, i % 3).as_bytes());
+    }
+    let _ = writer.flush();
+    IndexBuilder::new("test_data/many_duplicates.csv", Format::Tabular).column(1).separator(",").hashmap_size(2)
+        .compression(IndexCompression::Full).build().unwrap();
+    let mut searcher = Searcher::open("test_data/many_duplicates.csv").unwrap();
+    let offsets = searcher.search_offsets("prova1").unwrap();
+    assert! (offsets.len() == 16667 && offsets.windows(2).all(|w| w[0] < w[1]));
+    assert! (searcher.search("prova2").unwrap().len() == 16666);
+}
+fn run_test_contiguous_runs(){
+    //Relies on the file written by run_test_compressed_index. Colliding entries beyond the memory left by
+    //in_memory_map_size are sorted in run files: the index is the same
+    let builder = || IndexBuilder::new("test_data/many_duplicates.csv", Format::Tabular).column(1).separator(",").hashmap_size(3).contiguous_blocks(true);
+    builder().build().unwrap();
+    let expected = std::fs::read("test_data/many_duplicates.csv.index").unwrap();
+    builder().in_memory_map_size(3).build().unwrap();
+    assert! (std::fs::read("test_data/many_duplicates.csv.index").unwrap() == expected);
+    assert! (!Path::new("test_data/many_duplicates.csv.index.run0").exists());
+    let mut searcher = Searcher::open("test_data/many_duplicates.csv").unwrap();
+    let offsets = searcher.search_offsets("prova1").unwrap();
+    assert! (offsets.len() == 16667 && offsets.windows(2).all(|w| w[0] < w[1]));
+    //Run files are removed when indexing fails after writing them
+    let mut lines: String = (0..20000).map(|i| format!("{},key{}\n", i, i % 3)).collect();
+    lines.push_str("no second column\n");
+    std::fs::write("test_data/failed_runs.csv", &lines).unwrap();
+    let result = IndexBuilder::new("test_data/failed_runs.csv", Format::Tabular).column(1).separator(",").hashmap_size(3)
+        .contiguous_blocks(true).in_memory_map_size(3).build();
+    assert! (matches!(result, Err(FastSeekError::MissingColumn{line: 20001, column: 1})));
+    let runs = std::fs::read_dir("test_data").unwrap().filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().contains(".run"));
+    assert! (runs.count() == 0);
+}
+fn run_test_batch(){
+    //Relies on the index built by run_test_duplicates
+    let mut keywords: Vec<String> = (0..TEST_LEN).rev().map(|i| format!("prova{}", i)).collect();
+    keywords.push("NOT_EXISTING".to_string());
+    let mut searcher = Searcher::open("test_data/duplicates.csv").unwrap();
+    let results = searcher.search_batch(&keywords, true).unwrap();
+    assert! (results.len() == keywords.len());
+    for (keyword, matches) in keywords.iter().zip(results.iter()) {
+        let expected = searcher.search(keyword).unwrap();
+        assert! (matches == &expected);
+    }
+    assert! (results[(TEST_LEN - 1) as usize].len() == (TEST_LEN / 10) as usize);
+    assert! (results[TEST_LEN as usize].is_empty());
+}
+fn run_test_threads(){
+    //Relies on the files written by run_test_fastq and run_test_compressed_index
+    let index_bytes = |builder: IndexBuilder, index_path: &str| {
+        builder.build().unwrap();
+        return std::fs::read(index_path).unwrap();
+    };
+    for threads in [2, 4] {
+        for (in_memory_map_size, contiguous_blocks) in [(100000, false), (5000, false), (5000, true)] {
+            let builder = || IndexBuilder::new("test_data/many_duplicates.csv", Format::Tabular).column(0).separator(",")
+                .in_memory_map_size(in_memory_map_size).contiguous_blocks(contiguous_blocks);
+            let expected = index_bytes(builder(), "test_data/many_duplicates.csv.index");
+            assert! (index_bytes(builder().threads(threads), "test_data/many_duplicates.csv.index") == expected);
+        }
+        let builder = || IndexBuilder::new("test_data/test.fastq", Format::Fastq).by_sequence(true);
+        let expected = index_bytes(builder(), "test_data/test.fastq.index");
+        assert! (index_bytes(builder().threads(threads), "test_data/test.fastq.index") == expected);
+        assert! (found("test_data/test.fastq", "GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA7"));
+    }
+    //The command line is recorded only if given, so the process arguments don't change the index
+    let builder = || IndexBuilder::new("test_data/test.fastq", Format::Fastq).by_sequence(true);
+    let expected = index_bytes(builder(), "test_data/test.fastq.index");
+    let recorded = index_bytes(builder().command_line("fastseek index-fastq test.fastq"), "test_data/test.fastq.index");
+    assert! (recorded.windows(14).any(|window| window == b"fastseek index"));
+    assert! (!expected.windows(14).any(|window| window == b"fastseek index"));
+    //Errors are reported as with a single thread
+    let mut content = String::new();
+    for i in 0..10000 {
+        content.push_str(&format!("{},{}\n", i, i));
+    }
+    content.push_str("broken\n");
+    std::fs::write("test_data/errors.csv", content).unwrap();
+    let result = IndexBuilder::new("test_data/errors.csv", Format::Tabular).column(1).separator(",").threads(3).build();
+    assert! (matches!(result, Err(FastSeekError::MissingColumn{line: 10001, column: 1})));
+    let result = IndexBuilder::new("test_data/errors.csv", Format::Tabular).threads(0).build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+}
+fn run_test_stale_index(){
+    let lines: Vec<String> = (0..20000).map(|i| format!("{:05},key{:05}\n", i, i)).collect();
+    std::fs::write("test_data/stale.csv", lines.concat()).unwrap();
+    IndexBuilder::new("test_data/stale.csv", Format::Tabular).column(1).separator(",").build().unwrap();
+    let mut searcher = Searcher::open("test_data/stale.csv").unwrap();
+    assert! (!searcher.source_modified());
+    assert! (searcher.verify(1000000).unwrap() == 20000);
+    //Same size, first and last blocks unchanged: only verify detects the moved records
+    let mut moved = lines.clone();
+    moved.remove(5000);
+    moved.insert(15000, "99999,key99999\n".to_string());
+    std::fs::write("test_data/stale.csv", moved.concat()).unwrap();
+    //Timestamps may be too coarse to tell the writes apart
+    let modified = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+    File::options().write(true).open("test_data/stale.csv").unwrap().set_modified(modified).unwrap();
+    let mut searcher = Searcher::open("test_data/stale.csv").unwrap();
+    assert! (searcher.source_modified());
+    assert! (matches!(searcher.verify(1000000), Err(FastSeekError::StaleIndex(_))));
+    //Same content, modified again
+    std::fs::write("test_data/stale.csv", lines.concat()).unwrap();
+    File::options().write(true).open("test_data/stale.csv").unwrap().set_modified(modified).unwrap();
+    let mut searcher = Searcher::open("test_data/stale.csv").unwrap();
+    assert! (searcher.source_modified());
+    assert! (searcher.verify(100).unwrap() > 0);
+    //Changed size or content
+    std::fs::write("test_data/stale.csv", format!("{}00000,key00000\n", lines.concat())).unwrap();
+    assert! (matches!(Searcher::open("test_data/stale.csv"), Err(FastSeekError::StaleIndex(_))));
+    std::fs::write("test_data/stale.csv", lines.concat().replace("00000,key00000", "00000,key0000X")).unwrap();
+    assert! (matches!(Searcher::open("test_data/stale.csv"), Err(FastSeekError::StaleIndex(_))));
+}
+fn run_test_update(){
+    //Keys repeated, and a small hashmap, so that appended records join existing collision lists and blocks
+    let line = |i: u32| format!("{},key{}\n", i, i % 150);
+    let first: String = (0..1000).map(line).collect();
+    let appended: String = (1000..1500).map(line).collect();
+    let appended_later: String = (1500..1650).map(line).collect();
+    for contiguous_blocks in [false, true] {
+        std::fs::write("test_data/update.csv", &first).unwrap();
+        IndexBuilder::new("test_data/update.csv", Format::Tabular).column(1).separator(",").hashmap_size(64)
+            .contiguous_blocks(contiguous_blocks).ordered(KeyOrder::Lexicographic).build().unwrap();
+        assert! (update_index("test_data/update.csv.index", None).unwrap() == 0);
+        std::fs::OpenOptions::new().append(true).open("test_data/update.csv").unwrap().write_all(appended.as_bytes()).unwrap();
+        assert! (matches!(Searcher::open("test_data/update.csv"), Err(FastSeekError::StaleIndex(_))));
+        let old_index = std::fs::read("test_data/update.csv.index").unwrap();
+        let old_sorted = std::fs::read("test_data/update.csv.index.sorted").unwrap();
+        assert! (update_index("test_data/update.csv.index", None).unwrap() == 500);
+        //Interrupted before writing the header: hashmap slots updated, old header and ordered index
+        let header_size = u32::from_be_bytes(old_index[9..13].try_into().unwrap()) as usize;
+        let mut interrupted = std::fs::read("test_data/update.csv.index").unwrap();
+        interrupted[..header_size].copy_from_slice(&old_index[..header_size]);
+        std::fs::write("test_data/update.csv.index", &interrupted).unwrap();
+        std::fs::write("test_data/update.csv.index.sorted", &old_sorted).unwrap();
+        assert! (matches!(Searcher::open("test_data/update.csv"), Err(FastSeekError::StaleIndex(_))));
+        //Then appended to: only the records not added by the interrupted update are added
+        std::fs::OpenOptions::new().append(true).open("test_data/update.csv").unwrap().write_all(appended_later.as_bytes()).unwrap();
+        assert! (update_index("test_data/update.csv.index", None).unwrap() == 150);
+        let mut searcher = Searcher::open("test_data/update.csv").unwrap();
+        assert! (!searcher.source_modified());
+        assert! (searcher.verify(1000000).unwrap() == 1650);
+        for key in 0..150 {
+            let mut records = searcher.search(&format!("key{}", key)).unwrap();
+            records.sort();
+            let mut expected: Vec<String> = (0..1650).filter(|i| i % 150 == key).map(|i| line(i).trim_end().to_string()).collect();
+            expected.sort();
+            assert! (records == expected);
+        }
+        assert! (searcher.search_prefix("key14").unwrap().len() == 121);
+        //Appended again, with a key not seen before
+        std::fs::OpenOptions::new().append(true).open("test_data/update.csv").unwrap().write_all(b"1650,new\n").unwrap();
+        assert! (update_index("test_data/update.csv.index", Some("test_data/update.csv")).unwrap() == 1);
+        assert! (Searcher::open("test_data/update.csv").unwrap().search("new").unwrap() == vec!["1650,new".to_string()]);
+    }
+    //Fastq records
+    let record = |i: u32| format!("@read{}\nACGT\n+\nIIII\n", i);
+    std::fs::write("test_data/update.fastq", (0..50).map(record).collect::<String>()).unwrap();
+    IndexBuilder::new("test_data/update.fastq", Format::Fastq).build().unwrap();
+    std::fs::OpenOptions::new().append(true).open("test_data/update.fastq").unwrap().write_all((50..80).map(record).collect::<String>().as_bytes()).unwrap();
+    assert! (update_index("test_data/update.fastq.index", None).unwrap() == 30);
+    assert! (found("test_data/update.fastq", "@read79"));
+    assert! (found("test_data/update.fastq", "@read0"));
+    //Changes other than appends, and unterminated last lines, need the index built again
+    std::fs::write("test_data/update.csv", first.replace("0,key0\n", "0,keyX\n")).unwrap();
+    IndexBuilder::new("test_data/update.csv", Format::Tabular).column(1).separator(",").build().unwrap();
+    std::fs::write("test_data/update.csv", format!("{}{}", first, appended)).unwrap();
+    assert! (matches!(update_index("test_data/update.csv.index", None), Err(FastSeekError::StaleIndex(_))));
+    std::fs::write("test_data/update.csv", first.trim_end()).unwrap();
+    IndexBuilder::new("test_data/update.csv", Format::Tabular).column(1).separator(",").build().unwrap();
+    std::fs::write("test_data/update.csv", format!("{}5\n", first.trim_end())).unwrap();
+    assert! (matches!(update_index("test_data/update.csv.index", None), Err(FastSeekError::StaleIndex(_))));
+    //Compressed indexes
+    std::fs::write("test_data/update.csv", &first).unwrap();
+    IndexBuilder::new("test_data/update.csv", Format::Tabular).column(1).separator(",").compression(IndexCompression::Blocks).build().unwrap();
+    assert! (matches!(update_index("test_data/update.csv.index", None), Err(FastSeekError::InvalidArgument(_))));
+}
+fn run_test_header_versions(){
+    //v0 indexes, without and with flags: one hashmap slot, pointing to offset 4
+    std::fs::write("test_data/v0.csv", "a,b\nc,d\n").unwrap();
+    let mut index: Vec<u8> = vec![13, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, b',', 0];
+    index.extend_from_slice(&4u64.to_be_bytes());
+    std::fs::write("test_data/v0.csv.index", &index).unwrap();
+    assert! (Searcher::open("test_data/v0.csv").unwrap().search("c").unwrap() == vec!["c,d".to_string()]);
+    index[0] = 14;
+    index.insert(13, 0);
+    std::fs::write("test_data/v0.csv.index", &index).unwrap();
+    assert! (Searcher::open("test_data/v0.csv").unwrap().search("c").unwrap() == vec!["c,d".to_string()]);
+    //v1 index of a column that doesn't fit in a byte
+    let line: Vec<String> = (0..300).map(|i| format!("c{}", i)).collect();
+    std::fs::write("test_data/wide.csv", format!("{}\n", line.join(","))).unwrap();
+    IndexBuilder::new("test_data/wide.csv", Format::Tabular).column(299).separator(",").build().unwrap();
+    assert! (std::fs::read("test_data/wide.csv.index").unwrap().starts_with(b"\x89FSK"));
+    assert! (found("test_data/wide.csv", "c299"));
+    assert! (!found("test_data/wide.csv", "c43"));
+}
+fn run_test_composite_key(){
+    let path = Path::new("test_data/composite.csv");
+    let mut writer = io::BufWriter::new(File::create(path).unwrap());
+    for i in 0..TEST_LEN {
+        let _ = writer.write_all(format!("sample{},{},gene{}\n", i % 10, i, i / 10).as_bytes());
+    }
+    let _ = writer.flush();
+    for threads in [1, 3] {
+        IndexBuilder::new("test_data/composite.csv", Format::Tabular).columns(&[0, 2]).separator(",").threads(threads).build().unwrap();
+        let mut searcher = Searcher::open("test_data/composite.csv").unwrap();
+        for i in 0..TEST_LEN {
+            let expected = vec![format!("sample{},{},gene{}", i % 10, i, i / 10)];
+            assert! (searcher.search(&format!("sample{},gene{}", i % 10, i / 10)).unwrap() == expected);
+            let key = searcher.join_key(&[format!("sample{}", i % 10), format!("gene{}", i / 10)]).unwrap();
+            assert! (searcher.search(&key).unwrap() == expected);
+        }
+        assert! (searcher.search("sample1").unwrap().is_empty());
+        assert! (searcher.search("sample1,gene2,x").unwrap().is_empty());
+        assert! (matches!(searcher.join_key(&["a".to_string(), "b".to_string(), "c".to_string()]), Err(FastSeekError::InvalidArgument(_))));
+    }
+    //Every column must exist
+    std::fs::write("test_data/errors.csv", "a,b,c\nd,e\n").unwrap();
+    let result = IndexBuilder::new("test_data/errors.csv", Format::Tabular).columns(&[0, 2]).separator(",").build();
+    assert! (matches!(result, Err(FastSeekError::MissingColumn{line: 2, column: 2})));
+}
+fn run_test_errors(){
+    //Relies on the index built by run_test_duplicates
+    let mut index = std::fs::read("test_data/duplicates.csv.index").unwrap();
+    //Truncated index
+    std::fs::write("test_data/errors.csv", std::fs::read("test_data/duplicates.csv").unwrap()).unwrap();
+    std::fs::write("test_data/errors.csv.index", &index[..5]).unwrap();
+    assert! (matches!(Searcher::open("test_data/errors.csv"), Err(FastSeekError::CorruptIndex(_))));
+    //Unknown version, v1 header
+    index[8] = 200;
+    std::fs::write("test_data/errors.csv.index", &index).unwrap();
+    assert! (matches!(Searcher::open("test_data/errors.csv"), Err(FastSeekError::UnsupportedVersion(200))));
+    //Unknown version, v0 header
+    std::fs::write("test_data/errors.csv.index", [13, 200, 0, 0, 0, 0, 0, 0, 0, 1, 0, b',', 0, 0xff]).unwrap();
+    assert! (matches!(Searcher::open("test_data/errors.csv"), Err(FastSeekError::UnsupportedVersion(200))));
+    //Unknown hash algorithm
+    index[8] = 1;
+    index[17] = 9;
+    std::fs::write("test_data/errors.csv.index", &index).unwrap();
+    assert! (matches!(Searcher::open("test_data/errors.csv"), Err(FastSeekError::CorruptIndex(_))));
+    //Missing index
+    assert! (matches!(Searcher::open("test_data/not_existing.csv"), Err(FastSeekError::Io(_))));
+
+    //Missing column
+    std::fs::write("test_data/errors.csv", "a,b,c\nd,e\n").unwrap();
+    let result = IndexBuilder::new("test_data/errors.csv", Format::Tabular).column(2).separator(",").build();
+    assert! (matches!(result, Err(FastSeekError::MissingColumn{line: 2, column: 2})));
+    //Malformed fastq
+    std::fs::write("test_data/errors.fastq", "@r1\nACGT\n+\nIIII\n@r2\nACGT\nIIII\nIIII\n").unwrap();
+    let result = IndexBuilder::new("test_data/errors.fastq", Format::Fastq).build();
+    assert! (matches!(result, Err(FastSeekError::MalformedRecord{line: 7, ..})));
+}
+fn run_test_named_indexes(){
+    //Relies on the files written by run_test_fastq and run_test_composite_key
+    let by_id = |name: &str| IndexBuilder::new("test_data/test.fastq", Format::Fastq).name(name).in_memory_map_size(30);
+    let by_sequence = |name: &str| IndexBuilder::new("test_data/test.fastq", Format::Fastq).name(name).by_sequence(true)
+        .hashmap_size(7).compression(IndexCompression::Blocks);
+    //Built one at a time, then together: the indexes are the same
+    by_id("id_alone").build().unwrap();
+    by_sequence("sequence_alone").build().unwrap();
+    for threads in [1, 3] {
+        IndexBuilder::build_all(vec![by_id("id").threads(threads), by_sequence("sequence")]).unwrap();
+        let index_bytes = |name: &str| std::fs::read(format!("test_data/test.fastq.{}.index", name)).unwrap();
+        assert! (index_bytes("id") == index_bytes("id_alone"));
+        assert! (index_bytes("sequence") == index_bytes("sequence_alone"));
+    }
+    let mut id_searcher = Searcher::open_named("test_data/test.fastq", Some("id")).unwrap();
+    let mut sequence_searcher = Searcher::open_named("test_data/test.fastq", Some("sequence")).unwrap();
+    for i in 0..TEST_LEN {
+        assert! (id_searcher.search_first(&format!("@prova{}", i)).unwrap().is_some());
+        assert! (sequence_searcher.search_first(&format!("GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA{}", i)).unwrap().is_some());
+    }
+    assert! (id_searcher.search_first("GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA7").unwrap().is_none());
+    //The default index is left alone
+    assert! (found("test_data/test.fastq", "GGTCAGCCCTCAAGGGAATCTGAACTCCTCCA7"));
+    assert! (matches!(Searcher::open_named("test_data/test.fastq", Some("missing")), Err(FastSeekError::Io(_))));
+
+    //Two column indexes of the same tabular file
+    let builder = |name: &str, column: usize| IndexBuilder::new("test_data/composite.csv", Format::Tabular).name(name).column(column).separator(",");
+    IndexBuilder::build_all(vec![builder("sample", 0), builder("gene", 2)]).unwrap();
+    assert! (Searcher::open_named("test_data/composite.csv", Some("sample")).unwrap().search("sample3").unwrap().len() == 10);
+    assert! (Searcher::open_named("test_data/composite.csv", Some("gene")).unwrap().search("gene3").unwrap().len() == 10);
+    assert! (found("test_data/composite.csv", "sample3,gene3"));
+    //Every index must go to a different file, of the same input
+    let result = IndexBuilder::build_all(vec![builder("sample", 0), builder("sample", 2)]);
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+    let result = IndexBuilder::build_all(vec![builder("sample", 0), by_id("id")]);
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+    assert! (matches!(builder("../sample", 0).build(), Err(FastSeekError::InvalidArgument(_))));
+}
+fn run_test_index_location(){
+    //Relies on the files written by run_test_composite_key and run_test_header_versions
+    let _ = std::fs::remove_dir_all("test_data/indexes");
+    std::fs::create_dir_all("test_data/indexes/moved").unwrap();
+    let builder = || IndexBuilder::new("test_data/composite.csv", Format::Tabular).column(1).separator(",");
+    builder().index_dir("test_data/indexes").build().unwrap();
+    builder().index_file("test_data/indexes/by_number.idx").build().unwrap();
+    assert! (fastseek::index_path("test_data/composite.csv", Some("test_data/indexes"), None) == "test_data/indexes/composite.csv.index");
+    for index_path in ["test_data/indexes/composite.csv.index", "test_data/indexes/by_number.idx"] {
+        //The index records where the input file is
+        let mut searcher = Searcher::open_index(index_path, None).unwrap();
+        assert! (searcher.search("42").unwrap() == vec!["sample2,42,gene4".to_string()]);
+        let mut searcher = Searcher::open_index(index_path, Some("test_data/composite.csv")).unwrap();
+        assert! (searcher.search("42").unwrap() == vec!["sample2,42,gene4".to_string()]);
+    }
+    //Index and file in the same directory can be moved together
+    std::fs::copy("test_data/composite.csv", "test_data/indexes/composite.csv").unwrap();
+    IndexBuilder::new("test_data/indexes/composite.csv", Format::Tabular).column(1).separator(",").build().unwrap();
+    std::fs::rename("test_data/indexes/composite.csv", "test_data/indexes/moved/composite.csv").unwrap();
+    std::fs::rename("test_data/indexes/composite.csv.index", "test_data/indexes/moved/composite.csv.index").unwrap();
+    let mut searcher = Searcher::open_index("test_data/indexes/moved/composite.csv.index", None).unwrap();
+    assert! (searcher.filename() == "test_data/indexes/moved/composite.csv");
+    assert! (searcher.search("42").unwrap().len() == 1);
+    //v0 indexes don't record the input file
+    let result = Searcher::open_index("test_data/v0.csv.index", None);
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+    assert! (Searcher::open_index("test_data/v0.csv.index", Some("test_data/v0.csv")).is_ok());
+}
+fn run_test_quoted_csv(){
+    let mut content = String::new();
+    let mut offsets: Vec<u64> = vec![];
+    for i in 0..TEST_LEN {
+        offsets.push(content.len() as u64);
+        match i % 3 {
+            0 => content.push_str(&format!("{},\"name, {}\",plain\n", i, i)),
+            1 => content.push_str(&format!("\"{}\",\"said \"\"{}\"\"\",\"two\nlines\"\r\n", i, i)),
+            _ => content.push_str(&format!("{},\"line\n{}\n\",\"\"\n", i, i)),
+        }
+    }
+    std::fs::write("test_data/quoted.csv", &content).unwrap();
+    let keys = |i: u32| match i % 3 {
+        0 => format!("name, {}", i),
+        1 => format!("said \"{}\"", i),
+        _ => format!("line\n{}\n", i),
+    };
+    for (threads, in_memory_map_size) in [(1, 1000), (3, 1000), (1, 7)] {
+        IndexBuilder::new("test_data/quoted.csv", Format::Tabular).column(1).separator(",").quote('"')
+            .threads(threads).in_memory_map_size(in_memory_map_size).build().unwrap();
+        let mut searcher = Searcher::open("test_data/quoted.csv").unwrap();
+        for i in 0..TEST_LEN {
+            //Offsets point to the start of the record, the whole record is returned
+            assert! (searcher.search_offsets(&keys(i)).unwrap() == vec![offsets[i as usize]]);
+            let end = offsets.get(i as usize + 1).map(|&end| end as usize).unwrap_or(content.len());
+            assert! (searcher.search_first(&keys(i)).unwrap().unwrap() == content[offsets[i as usize] as usize..end].trim_end());
+        }
+        assert! (searcher.search("name").unwrap().is_empty());
+        assert! (searcher.quote() == Some('"'));
+    }
+    //Composite key of quoted fields
+    IndexBuilder::new("test_data/quoted.csv", Format::Tabular).columns(&[0, 2]).separator(",").quote('"').build().unwrap();
+    assert! (found("test_data/quoted.csv", "1,two\nlines"));
+    assert! (found("test_data/quoted.csv", "2,"));
+    //Malformed quoting
+    std::fs::write("test_data/errors.csv", "a,b\n\"c\"d,e\n").unwrap();
+    let result = IndexBuilder::new("test_data/errors.csv", Format::Tabular).column(1).separator(",").quote('"').build();
+    assert! (matches!(result, Err(FastSeekError::MalformedRecord{line: 2, ..})));
+    std::fs::write("test_data/errors.csv", "a,b\nc,\"d\ne\n").unwrap();
+    let result = IndexBuilder::new("test_data/errors.csv", Format::Tabular).column(1).separator(",").quote('"').build();
+    assert! (matches!(result, Err(FastSeekError::MalformedRecord{line: 2, ..})));
+    let result = IndexBuilder::new("test_data/errors.csv", Format::Tabular).separator(",").quote(',').build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+    let result = IndexBuilder::new("test_data/test.fastq", Format::Fastq).quote('"').build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+}
+fn run_test_header_and_comments(){
+    let mut content = String::from("# generated by the self test\nsample_id\tgene_id\tvalue\n");
+    for i in 0..TEST_LEN {
+        if i % 7 == 0 {
+            content.push_str(&format!("#comment {}\tgene_id\n", i));
+        }
+        content.push_str(&format!("sample{}\tgene{}\t{}\n", i, i, i));
+    }
+    std::fs::write("test_data/header.tsv", &content).unwrap();
+    for threads in [1, 3] {
+        IndexBuilder::new("test_data/header.tsv", Format::Tabular).has_header(true).comment_char('#').column_name("gene_id")
+            .threads(threads).build().unwrap();
+        let mut searcher = Searcher::open("test_data/header.tsv").unwrap();
+        assert! (searcher.header_line() == Some("sample_id\tgene_id\tvalue"));
+        for i in 0..TEST_LEN {
+            assert! (searcher.search(&format!("gene{}", i)).unwrap() == vec![format!("sample{}\tgene{}\t{}", i, i, i)]);
+        }
+        //Neither the header line nor comments are indexed
+        assert! (searcher.search("gene_id").unwrap().is_empty());
+        assert! (searcher.verify(1000).unwrap() == TEST_LEN as u64);
+    }
+    //Composite key by name
+    IndexBuilder::new("test_data/header.tsv", Format::Tabular).has_header(true).comment_char('#').column_names(&["value", "sample_id"])
+        .build().unwrap();
+    assert! (found("test_data/header.tsv", "5\tsample5"));
+    //Without --has-header, the header line is indexed
+    IndexBuilder::new("test_data/header.tsv", Format::Tabular).comment_char('#').column(1).build().unwrap();
+    assert! (Searcher::open("test_data/header.tsv").unwrap().header_line().is_none());
+    assert! (found("test_data/header.tsv", "gene_id"));
+    let result = IndexBuilder::new("test_data/header.tsv", Format::Tabular).has_header(true).column_name("missing").build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+    //Header strings longer than their u16 length are rejected, not truncated
+    std::fs::write("test_data/long_header.tsv", format!("id\t{}\na\tb\n", "x".repeat(70000))).unwrap();
+    let _ = std::fs::remove_file("test_data/long_header.tsv.index");
+    let result = IndexBuilder::new("test_data/long_header.tsv", Format::Tabular).has_header(true).build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(message)) if message.starts_with("header line")));
+    assert! (!Path::new("test_data/long_header.tsv.index").exists());
+    let result = IndexBuilder::new("test_data/long_header.tsv", Format::Tabular).command_line(&"x".repeat(70000)).build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(message)) if message.starts_with("command line")));
+    let result = IndexBuilder::new("test_data/header.tsv", Format::Tabular).column_name("gene_id").build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+    let result = IndexBuilder::new("test_data/test.fastq", Format::Fastq).comment_char('#').build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+}
+fn run_test_separators(){
+    for separator in ["||", "::", ",", "→"] {
+        let lines: Vec<String> = (0..TEST_LEN).map(|i| format!("id{}{}x:y|z{}{}{}gene{}\n", i, separator, separator, i, separator, i % 10)).collect();
+        std::fs::write("test_data/separators.txt", lines.concat()).unwrap();
+        IndexBuilder::new("test_data/separators.txt", Format::Tabular).column(2).separator(separator).build().unwrap();
+        let mut searcher = Searcher::open("test_data/separators.txt").unwrap();
+        for i in 0..TEST_LEN {
+            assert! (searcher.search(&i.to_string()).unwrap() == vec![lines[i as usize].trim_end().to_string()]);
+        }
+        IndexBuilder::new("test_data/separators.txt", Format::Tabular).columns(&[0, 3]).separator(separator).threads(3).build().unwrap();
+        let mut searcher = Searcher::open("test_data/separators.txt").unwrap();
+        let key = searcher.join_key(&["id12".to_string(), "gene2".to_string()]).unwrap();
+        assert! (key == format!("id12{}gene2", separator));
+        assert! (searcher.search(&key).unwrap() == vec![lines[12].trim_end().to_string()]);
+    }
+    //Runs of blanks, leading and trailing ones ignored
+    let lines: Vec<String> = (0..TEST_LEN).map(|i| format!("{}id{}  \t{}{} gene{}\t \r\n", " ".repeat(i as usize % 3), i, i, " ".repeat(i as usize % 4 + 1), i % 10)).collect();
+    std::fs::write("test_data/whitespace.txt", lines.concat()).unwrap();
+    for threads in [1, 3] {
+        IndexBuilder::new("test_data/whitespace.txt", Format::Tabular).column(2).whitespace(true).threads(threads).build().unwrap();
+        let mut searcher = Searcher::open("test_data/whitespace.txt").unwrap();
+        assert! (searcher.search("gene3").unwrap().len() == 10);
+        assert! (searcher.search(" gene3").unwrap().is_empty());
+        IndexBuilder::new("test_data/whitespace.txt", Format::Tabular).columns(&[0, 2]).whitespace(true).threads(threads).build().unwrap();
+        let mut searcher = Searcher::open("test_data/whitespace.txt").unwrap();
+        for i in 0..TEST_LEN {
+            let expected = vec![lines[i as usize].trim_end_matches(['\r', '\n']).to_string()];
+            assert! (searcher.search(&format!("id{} gene{}", i, i % 10)).unwrap() == expected);
+            assert! (searcher.search(&format!("id{} \t gene{}", i, i % 10)).unwrap() == expected);
+        }
+    }
+    let result = IndexBuilder::new("test_data/whitespace.txt", Format::Tabular).separator("").build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+    let result = IndexBuilder::new("test_data/whitespace.txt", Format::Tabular).whitespace(true).quote('"').build();
+    assert! (matches!(result, Err(FastSeekError::InvalidArgument(_))));
+}
+fn run_test_normalization(){
+    //Key in the last column of a file with CRLF line endings
+    let lines: Vec<String> = (0..TEST_LEN).map(|i| format!("{}\t Gene{} \r\n", i, i)).collect();
+    std::fs::write("test_data/normalization.txt", lines.concat()).unwrap();
+    IndexBuilder::new("test_data/normalization.txt", Format::Tabular).column(1).build().unwrap();
+    let mut searcher = Searcher::open("test_data/normalization.txt").unwrap();
+    assert! (searcher.search(" Gene7 ").unwrap() == vec![lines[7].trim_end_matches(['\r', '\n']).to_string()]);
+    assert! (searcher.search("gene7").unwrap().is_empty());
+    assert! (searcher.verify(TEST_LEN as u64).unwrap() == TEST_LEN as u64);
+    for case in [CaseFolding::Ascii, CaseFolding::Unicode] {
+        for threads in [1, 3] {
+            IndexBuilder::new("test_data/normalization.txt", Format::Tabular).column(1).trim_keys(true).case_folding(case).threads(threads).build().unwrap();
+            let mut searcher = Searcher::open("test_data/normalization.txt").unwrap();
+            for i in 0..TEST_LEN {
+                let expected = vec![lines[i as usize].trim_end_matches(['\r', '\n']).to_string()];
+                assert! (searcher.search(&format!("gene{}", i)).unwrap() == expected);
+                assert! (searcher.search(&format!("  GENE{}\t", i)).unwrap() == expected);
+            }
+            assert! (searcher.verify(TEST_LEN as u64).unwrap() == TEST_LEN as u64);
+        }
+    }
+    //Only Unicode case folding lowercases non-ASCII letters
+    std::fs::write("test_data/normalization_unicode.txt", "ÉCOLE\t1\nStraße\t2\n").unwrap();
+    IndexBuilder::new("test_data/normalization_unicode.txt", Format::Tabular).case_folding(CaseFolding::Unicode).build().unwrap();
+    assert! (found("test_data/normalization_unicode.txt", "école"));
+    assert! (!found("test_data/normalization_unicode.txt", "STRASSE"));
+    assert! (found("test_data/normalization_unicode.txt", "STRAßE"));
+    IndexBuilder::new("test_data/normalization_unicode.txt", Format::Tabular).case_folding(CaseFolding::Ascii).build().unwrap();
+    assert! (found("test_data/normalization_unicode.txt", "École"));
+    assert! (!found("test_data/normalization_unicode.txt", "école"));
+    //Quotes removed after trimming, field by field in composite keys
+    let lines: Vec<String> = (0..TEST_LEN).map(|i| format!(" \"Id{}\",'x{}' ,{}\n", i, i % 10, i)).collect();
+    std::fs::write("test_data/normalization_quotes.txt", lines.concat()).unwrap();
+    IndexBuilder::new("test_data/normalization_quotes.txt", Format::Tabular).separator(",").columns(&[0, 1])
+        .trim_keys(true).strip_quotes(true).case_folding(CaseFolding::Ascii).build().unwrap();
+    let mut searcher = Searcher::open("test_data/normalization_quotes.txt").unwrap();
+    let key = searcher.join_key(&["ID12".to_string(), " \"x2\" ".to_string()]).unwrap();
+    assert! (searcher.search(&key).unwrap() == vec![lines[12].trim_end().to_string()]);
+    assert! (searcher.search("id12,x2").unwrap().len() == 1);
+    assert! (searcher.verify(TEST_LEN as u64).unwrap() == TEST_LEN as u64);
+    IndexBuilder::new("test_data/normalization_quotes.txt", Format::Tabular).separator(",").column(0).strip_quotes(true).build().unwrap();
+    assert! (!found("test_data/normalization_quotes.txt", "Id3"));
+    assert! (found("test_data/normalization_quotes.txt", " \"Id3\""));
+    //Fastq indexed case-insensitively, by header and by sequence
+    std::fs::write("test_data/normalization.fastq", "@Read1 sample\nACGTacgt\n+\nIIIIIIII\n@READ2\nacgtACGT\n+\nIIIIIIII\n").unwrap();
+    IndexBuilder::new("test_data/normalization.fastq", Format::Fastq).id_token(0).case_folding(CaseFolding::Ascii).build().unwrap();
+    let mut searcher = Searcher::open("test_data/normalization.fastq").unwrap();
+    assert! (searcher.search("read1").unwrap() == vec!["@Read1 sample\nACGTacgt\n+\nIIIIIIII".to_string()]);
+    assert! (searcher.search("@ReAd2 other").unwrap().len() == 1);
+    IndexBuilder::new("test_data/normalization.fastq", Format::Fastq).by_sequence(true).case_folding(CaseFolding::Ascii).build().unwrap();
+    assert! (Searcher::open("test_data/normalization.fastq").unwrap().search("ACGTACGT").unwrap().len() == 2);
+}
+fn run_test_ordered_index(){
+    let lines: Vec<String> = (0..TEST_LEN).map(|i| format!("ENSG{:05}\t{}\tgene{}\n", (i * 37) % TEST_LEN, i * 50, i % 10)).collect();
+    std::fs::write("test_data/ordered.tsv", lines.concat()).unwrap();
+    let record = |line: &String| line.trim_end().to_string();
+    for threads in [1, 3] {
+        IndexBuilder::new("test_data/ordered.tsv", Format::Tabular).ordered(KeyOrder::Lexicographic).threads(threads).build().unwrap();
+        let mut searcher = Searcher::open("test_data/ordered.tsv").unwrap();
+        let mut expected: Vec<String> = lines.iter().filter(|line| line.starts_with("ENSG0001")).map(record).collect();
+        expected.sort();
+        assert! (searcher.search_prefix("ENSG0001").unwrap() == expected);
+        assert! (searcher.search_prefix("ENSG1").unwrap().is_empty());
+        assert! (searcher.search_prefix("").unwrap().len() == TEST_LEN as usize);
+        let range = searcher.search_range(Some("ENSG00010"), Some("ENSG00019")).unwrap();
+        assert! (range == expected);
+        assert! (searcher.search_range(None, Some("ENSG00004")).unwrap().len() == 5);
+        assert! (searcher.search_range(Some("ENSG00095"), None).unwrap().len() == 5);
+        //Hash searches still work
+        assert! (searcher.search("ENSG00037").unwrap() == vec![record(&lines[1])]);
+    }
+    //Numbers sorted by value, duplicates in file order
+    IndexBuilder::new("test_data/ordered.tsv", Format::Tabular).column(1).ordered(KeyOrder::Numeric).build().unwrap();
+    let mut searcher = Searcher::open("test_data/ordered.tsv").unwrap();
+    let range = searcher.search_range(Some("1000"), Some("2000.0")).unwrap();
+    assert! (range == lines[20..=40].iter().map(record).collect::<Vec<String>>());
+    assert! (searcher.search_range(Some("-1e9"), Some("99")).unwrap() == vec![record(&lines[0]), record(&lines[1])]);
+    assert! (matches!(searcher.search_range(Some("low"), None), Err(FastSeekError::InvalidArgument(_))));
+    assert! (matches!(searcher.search_prefix("1"), Err(FastSeekError::InvalidArgument(_))));
+    IndexBuilder::new("test_data/ordered.tsv", Format::Tabular).column(2).ordered(KeyOrder::Lexicographic).in_memory_map_size(7).build().unwrap();
+    let mut searcher = Searcher::open("test_data/ordered.tsv").unwrap();
+    let genes = searcher.search_range(Some("gene3"), Some("gene4")).unwrap();
+    let expected: Vec<String> = lines.iter().filter(|line| line.ends_with("gene3\n")).chain(lines.iter().filter(|line| line.ends_with("gene4\n"))).map(record).collect();
+    assert! (genes == expected);
+    let result = IndexBuilder::new("test_data/ordered.tsv", Format::Tabular).column(2).ordered(KeyOrder::Numeric).build();
+    assert! (matches!(result, Err(FastSeekError::MalformedRecord{line: 1, ..})));
+    //Indexes without ordered keys, or whose ordered index was left by another build
+    IndexBuilder::new("test_data/ordered.tsv", Format::Tabular).build().unwrap();
+    let mut searcher = Searcher::open("test_data/ordered.tsv").unwrap();
+    assert! (matches!(searcher.search_prefix("ENSG"), Err(FastSeekError::InvalidArgument(_))));
+    IndexBuilder::new("test_data/ordered.tsv", Format::Tabular).ordered(KeyOrder::Lexicographic).build().unwrap();
+    std::fs::copy("test_data/ordered.tsv", "test_data/ordered_copy.tsv").unwrap();
+    std::fs::OpenOptions::new().append(true).open("test_data/ordered_copy.tsv").unwrap().write_all(b"ENSG99999\t0\tgene0\n").unwrap();
+    IndexBuilder::new("test_data/ordered_copy.tsv", Format::Tabular).ordered(KeyOrder::Lexicographic).build().unwrap();
+    std::fs::copy("test_data/ordered.tsv.index.sorted", "test_data/ordered_copy.tsv.index.sorted").unwrap();
+    let mut searcher = Searcher::open("test_data/ordered_copy.tsv").unwrap();
+    assert! (matches!(searcher.search_prefix("ENSG"), Err(FastSeekError::StaleIndex(_))));
+    //Fasta headers, normalised as the hash index
+    std::fs::write("test_data/ordered.fasta", ">chr2 b\nAC\n>Chr10 a\nGT\n>chr1 c\nTT\n").unwrap();
+    IndexBuilder::new("test_data/ordered.fasta", Format::Fasta).id_token(0).case_folding(CaseFolding::Ascii).ordered(KeyOrder::Lexicographic).build().unwrap();
+    let mut searcher = Searcher::open("test_data/ordered.fasta").unwrap();
+    assert! (searcher.search_prefix("CHR1").unwrap() == vec![">chr1 c\nTT".to_string(), ">Chr10 a\nGT".to_string()]);
+}
+fn run_test_structured_output(){
+    let lines: Vec<String> = (0..TEST_LEN).map(|i| format!("id{}\t{}\tgene{}\n", i, i * 2, i % 10)).collect();
+    std::fs::write("test_data/output.tsv", lines.concat()).unwrap();
+    IndexBuilder::new("test_data/output.tsv", Format::Tabular).column(2).ordered(KeyOrder::Lexicographic).build().unwrap();
+    let mut searcher = Searcher::open("test_data/output.tsv").unwrap();
+    let matches = searcher.search_matches(&["gene3".to_string(), "gene10".to_string()], true).unwrap();
+    assert! (matches[0].len() == 10 && matches[1].is_empty());
+    for (found, i) in matches[0].iter().zip((3..TEST_LEN).step_by(10)) {
+        assert! (found.key == "gene3" && found.record == lines[i as usize].trim_end());
+        assert! (found.offset == lines[..i as usize].concat().len() as u64);
+        assert! (searcher.record_fields(&found.record).unwrap() == vec![format!("id{}", i), (i * 2).to_string(), "gene3".to_string()]);
+    }
+    assert! (searcher.prefix_matches("gene9").unwrap().iter().all(|found| found.key == "gene9"));
+    //Csv fields without their quotes, whitespace-separated fields
+    std::fs::write("test_data/output.csv", "1,\"Smith, John\",\"a \"\"b\"\"\"\n").unwrap();
+    IndexBuilder::new("test_data/output.csv", Format::Tabular).separator(",").quote('"').build().unwrap();
+    let mut searcher = Searcher::open("test_data/output.csv").unwrap();
+    let record = searcher.search_first("1").unwrap().unwrap();
+    assert! (searcher.record_fields(&record).unwrap() == vec!["1", "Smith, John", "a \"b\""]);
+    //Fields printed by raw output are quoted again as in the file
+    assert! (super::join_fields(&searcher.record_fields(&record).unwrap(), ",", searcher.quote()) == record);
+    let fields = ["plain", "a,b", "say \"hi\"", "two\nlines", "cr\r"].map(String::from);
+    assert! (super::join_fields(&fields, ",", Some('"')) == "plain,\"a,b\",\"say \"\"hi\"\"\",\"two\nlines\",\"cr\r\"");
+    assert! (super::join_fields(&fields, ";", Some('\'')) == "plain;a,b;say \"hi\";'two\nlines';'cr\r'");
+    //Without a quote character, as in tsv files, fields are left alone
+    assert! (super::join_fields(&fields, "\t", None) == "plain\ta,b\tsay \"hi\"\ttwo\nlines\tcr\r");
+    std::fs::write("test_data/output.txt", "  a  b\tc \n").unwrap();
+    IndexBuilder::new("test_data/output.txt", Format::Tabular).whitespace(true).build().unwrap();
+    let mut searcher = Searcher::open("test_data/output.txt").unwrap();
+    let record = searcher.search_first("a").unwrap().unwrap();
+    assert! (searcher.record_fields(&record).unwrap() == vec!["a", "b", "c"] && searcher.separator() == " ");
+    assert! (matches!(Searcher::open("test_data/multi.fasta").unwrap().record_fields(">prova1"), Err(FastSeekError::InvalidArgument(_))));
+    assert! (super::json_string("a\"b\\c\n\t\u{1}é") == "\"a\\\"b\\\\c\\n\\t\\u0001é\"");
+    assert! (super::tsv_field("a\tb\\c\r\n") == "a\\tb\\\\c\\r\\n");
+}
+pub fn test(){
+    run_test_fastq(1000);
+    run_test_fasta(1000);
+    run_test_wrapped_fasta(1000);
+    run_test_wrapped_fasta(7);
+    run_test_id_token();
+    run_test(10000);
+    run_test_compressed();
+    run_test(6);
+    run_test_duplicates(1000, false);
+    run_test_duplicates(3, false);
+    run_test_duplicates(1000, true);
+    run_test_duplicates(3, true);
+    run_test_contiguous_blocks_size();
+    run_test_compressed_index();
+    run_test_contiguous_runs();
+    run_test_batch();
+    run_test_errors();
+    run_test_header_versions();
+    run_test_composite_key();
+    run_test_threads();
+    run_test_stale_index();
+    run_test_named_indexes();
+    run_test_index_location();
+    run_test_quoted_csv();
+    run_test_header_and_comments();
+    run_test_separators();
+    run_test_normalization();
+    run_test_ordered_index();
+    run_test_structured_output();
+    run_test_bgzf_blocks();
+    run_test_plain_gzip();
+    run_test_gzip_checkpoints();
+    run_test_zstd_seekable();
+    run_test_update();
+}
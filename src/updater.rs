@@ -0,0 +1,180 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use crate::bgzf::{file_compression, FileCompression};
+use crate::error::{with_filename, FastSeekError, Result};
+use crate::file_reader::StandardFileReader;
+use crate::fingerprint::Fingerprint;
+use crate::header::{FLAG_COMPRESSED_BLOCKS, FLAG_CONTIGUOUS_BLOCKS, FLAG_ORDERED_KEYS, FLAG_UPDATABLE};
+use crate::index_reader::IndexReader;
+use crate::index_structure::{rewrite_header, IndexEntry, IndexEntryType, CONTINUATION_BIT, HASHMAP_ENTRY_SIZE};
+use crate::ordered_index::{ordered_index_path, OrderedIndexReader, OrderedIndexWriter, SortedKey};
+use crate::searcher::input_reader_of_header;
+use crate::{hash_function, resolve_source_path};
+
+/// Adds the records appended to the indexed file since the index at `index_path` was built, or last updated, to it:
+/// the file is `filename`, or if None the path recorded in the index. Returns the number of records added.
+///
+/// Only the appended data is read. Its records go to the existing hashmap, whose size doesn't change, and new
+/// collision entries are appended to the index: after the file has grown a lot, building the index again makes
+/// searches faster. The fingerprint of the file is written last, in a single sector, so an update interrupted
+/// before it leaves the index reported as out of date, and running it again completes it. Records appended while
+/// updating are left to the next update. Uncompressed files and indexes only. Lines in errors are counted from the
+/// start of the appended data.
+pub fn update_index(index_path: &str, filename: Option<&str>) -> Result<u64> {
+    let mut index_reader = IndexReader::open(index_path)?;
+    let header = &index_reader.header;
+    let filename = match filename {
+        Some(filename) => filename.to_string(),
+        None if !header.source_path.is_empty() => resolve_source_path(index_path, &header.source_path),
+        None => return Err(FastSeekError::InvalidArgument(format!("index {} does not record the path of the indexed file", index_path))),
+    };
+    let filename = filename.as_str();
+    if header.has_flag(FLAG_COMPRESSED_BLOCKS) {
+        return Err(FastSeekError::InvalidArgument(format!("index {} is compressed, so it can't be updated: build it again", index_path)));
+    }
+    if file_compression(filename)? != FileCompression::None {
+        return Err(FastSeekError::InvalidArgument(format!("{} is compressed, so its index can't be updated: build it again", filename)));
+    }
+    //The fingerprint of updatable indexes covers exactly the data indexed, so appended records start at its size
+    if !header.has_flag(FLAG_UPDATABLE) {
+        return Err(FastSeekError::StaleIndex(format!("index {} does not record how much of the file it indexes", index_path)));
+    }
+    //Taken before reading the file: records ending past its size are left to the next update
+    let fingerprint = Fingerprint::of_file(filename)?;
+    if let Some(reason) = header.fingerprint.append_mismatch(filename)? {
+        return Err(FastSeekError::StaleIndex(reason));
+    }
+    let start = header.fingerprint.size;
+    if start > 0 && fingerprint.size > start {
+        let mut file = fs::File::open(filename).map_err(|e| with_filename(e, "open", filename))?;
+        let mut last_byte = [0];
+        file.seek(SeekFrom::Start(start - 1))?;
+        file.read_exact(&mut last_byte)?;
+        if last_byte[0] != b'\n' {
+            return Err(FastSeekError::StaleIndex("the last line indexed had no line terminator, and the appended data continues it".to_string()));
+        }
+    }
+
+    //The ordered index, if any, is written again with the new keys
+    let ordered_path = ordered_index_path(index_path);
+    let ordered_temp_path = format!("{}.tmp", ordered_path);
+    //An update interrupted after writing the header leaves the ordered index matching it in <ordered index>.tmp
+    if Path::new(&ordered_temp_path).exists() && OrderedIndexReader::open(&ordered_temp_path, &header.fingerprint).is_ok() {
+        fs::rename(&ordered_temp_path, &ordered_path).map_err(|e| with_filename(e, "replace", &ordered_path))?;
+    }
+    let mut ordered = match header.has_flag(FLAG_ORDERED_KEYS) && Path::new(&ordered_path).exists() {
+        true => {
+            let mut reader = OrderedIndexReader::open(&ordered_path, &header.fingerprint)?;
+            let mut writer = OrderedIndexWriter::new(ordered_temp_path.clone(), reader.order, fingerprint.clone());
+            reader.copy_to(&mut writer)?;
+            Some(writer)
+        }
+        false => None,
+    };
+
+    //Offsets of the appended records of each hashmap slot, in file order
+    let hashmap_size = header.hashmap_size as u128;
+    let mut added: BTreeMap<u64, Vec<u64>> = BTreeMap::new();
+    let mut input_reader = input_reader_of_header(header, Box::new(StandardFileReader::new(filename)?))?;
+    input_reader.seek(start)?;
+    let mut record = String::new();
+    let mut indexed_length = None;
+    while let Some(offset) = input_reader.read_record(&mut record)? {
+        if input_reader.position()? > fingerprint.size {
+            indexed_length = Some(offset);
+            break;
+        }
+        let key = input_reader.key_extractor().extract(&record, input_reader.line())?;
+        if let Some(ordered) = ordered.as_mut() {
+            ordered.add(SortedKey::of_record(ordered.order, &key, input_reader.line())?, offset)?;
+        }
+        added.entry(hash_function(&key, hashmap_size)).or_default().push(offset);
+        record.clear();
+    }
+    let indexed_length = match indexed_length {
+        Some(indexed_length) => indexed_length,
+        None => input_reader.position()?.min(fingerprint.size),
+    };
+    let fingerprint = match indexed_length == fingerprint.size {
+        true => fingerprint,
+        false => Fingerprint::of_prefix(filename, indexed_length)?,
+    };
+    //Written to <ordered index>.tmp, which replaces it once the index is updated
+    if let Some(mut ordered) = ordered {
+        ordered.fingerprint = fingerprint.clone();
+        ordered.finish()?;
+    }
+
+    //New collision entries are appended to the index, then the hashmap slots pointing to them are written
+    let mut index_file = OpenOptions::new().write(true).open(index_path).map_err(|e| with_filename(e, "open", index_path))?;
+    let mut address = index_file.seek(SeekFrom::End(0))?;
+    let mut writer = io::BufWriter::new(&mut index_file);
+    let mut slots: Vec<(u64, IndexEntry)> = vec![];
+    let contiguous_blocks = index_reader.header.has_flag(FLAG_CONTIGUOUS_BLOCKS);
+    let mut count = 0;
+    for (hash, mut offsets) in added {
+        //Slots written by an interrupted update already hold the records it appended
+        let mut block = index_reader.get_candidate_offsets(hash)?;
+        let stored: HashSet<u64> = block.iter().copied().filter(|&offset| offset >= start).collect();
+        offsets.retain(|offset| !stored.contains(offset));
+        if offsets.is_empty() {
+            continue;
+        }
+        count += offsets.len() as u64;
+        if contiguous_blocks {
+            //A block can't grow in place: it is written again at the end, with the new offsets
+            block.extend(offsets);
+            if block.len() == 1 {
+                slots.push((hash, IndexEntry::new_direct(block[0])));
+                continue;
+            }
+            slots.push((hash, IndexEntry::new_indirect(address)));
+            for (i, offset) in block.iter().enumerate() {
+                let entry = match i == block.len() - 1 {
+                    true => *offset,
+                    false => *offset | CONTINUATION_BIT,
+                };
+                writer.write_all(&entry.to_be_bytes())?;
+                address += 8;
+            }
+            continue;
+        }
+        let mut entry = index_reader.get_hashmap_entry(hash)?;
+        //Linked lists: nodes as written by IndexStructure::add_entry, pointing to older nodes
+        for offset in offsets {
+            let node = match entry.get_type() {
+                IndexEntryType::NULL => None,
+                IndexEntryType::Direct => Some((entry.get_offset(), IndexEntry::new_direct(offset))),
+                IndexEntryType::Indirect => Some((offset, entry)),
+            };
+            entry = match node {
+                None => IndexEntry::new_direct(offset),
+                Some((node_offset, next)) => {
+                    writer.write_all(&node_offset.to_be_bytes())?;
+                    writer.write_all(&next.to_be_bytes())?;
+                    address += 16;
+                    IndexEntry::new_indirect(address - 16)
+                }
+            };
+        }
+        slots.push((hash, entry));
+    }
+    writer.flush()?;
+    drop(writer);
+    for (hash, entry) in slots {
+        index_file.seek(SeekFrom::Start(index_reader.header_size + hash * HASHMAP_ENTRY_SIZE as u64))?;
+        index_file.write_all(&entry.to_be_bytes())?;
+    }
+    index_file.sync_all()?;
+    drop(index_file);
+
+    let header = &mut index_reader.header;
+    header.fingerprint = fingerprint;
+    rewrite_header(index_path, header)?;
+    if header.has_flag(FLAG_ORDERED_KEYS) && Path::new(&ordered_temp_path).exists() {
+        fs::rename(&ordered_temp_path, &ordered_path).map_err(|e| with_filename(e, "replace", &ordered_path))?;
+    }
+    return Ok(count);
+}
@@ -0,0 +1,233 @@
+use std::fs::File;
+use std::io::{self, BufRead, Read, Seek, SeekFrom};
+use zstd::bulk::Compressor;
+use zstd::zstd_safe::CParameter;
+use crate::bgzf::decompressed_reader;
+use crate::error::{with_filename, FastSeekError, Result};
+use crate::file_reader::FileReader;
+use crate::file_writer::FileWriter;
+
+//Zstandard seekable format (contrib/seekable_format of zstd): independent zstd frames, then a skippable frame holding
+//the seek table, so that each frame can be decompressed on its own:
+//  frames | skippable frame magic (u32) | table size (u32)
+//  | entries: compressed size (u32) | decompressed size (u32) [| checksum (u32)]
+//  | frame count (u32) | descriptor (u8, bit 7: entries have a checksum) | seekable magic (u32)
+//all little-endian.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+//Skippable frames have magics 0x184D2A50 to 0x184D2A5F
+const SKIPPABLE_MAGIC: u32 = 0x184d2a50;
+const SEEK_TABLE_MAGIC: u32 = 0x184d2a5e;
+const SEEKABLE_MAGIC: u32 = 0x8f92eab1;
+const SKIPPABLE_HEADER_SIZE: u64 = 8;
+const SEEK_TABLE_FOOTER_SIZE: u64 = 9;
+//Frame sizes are u32: larger frames are split
+const MAX_FRAME_SIZE: usize = 1 << 30;
+
+//Whether the start of a file is the start of a zstd frame, or of a skippable frame
+pub fn is_zstd(start: &[u8]) -> bool{
+    return start.starts_with(&ZSTD_MAGIC)
+        || (start.len() >= 4 && u32::from_le_bytes(start[0..4].try_into().unwrap()) & 0xfffffff0 == SKIPPABLE_MAGIC);
+}
+
+//Whether the zstd file ends with a seek table
+pub fn has_seek_table(file: &mut File) -> Result<bool>{
+    let size = file.metadata()?.len();
+    if size < SKIPPABLE_HEADER_SIZE + SEEK_TABLE_FOOTER_SIZE {
+        return Ok(false);
+    }
+    file.seek(SeekFrom::Start(size - 4))?;
+    let mut magic = [0; 4];
+    file.read_exact(&mut magic)?;
+    return Ok(u32::from_le_bytes(magic) == SEEKABLE_MAGIC);
+}
+
+/// Recompresses `input`, a zstd-compressed, gzip-compressed or uncompressed file, to `output` in the zstd seekable
+/// format, which FastSeek can index, in frames of `frame_size` uncompressed bytes. Smaller frames make searches faster
+/// and compress worse. `output` is written to `<output>.tmp` first, so it can be `input` itself, replaced once converted.
+pub fn zstd_seekable(input: &str, output: &str, frame_size: usize) -> Result<()>{
+    let frame_size = frame_size.clamp(1, MAX_FRAME_SIZE);
+    let mut input_reader = decompressed_reader(input)?;
+    let temp_path = format!("{}.tmp", output);
+    let mut writer = FileWriter::get_writer(temp_path.clone())?;
+    let mut compressor = Compressor::new(zstd::DEFAULT_COMPRESSION_LEVEL)?;
+    //Each frame checks its own data
+    compressor.set_parameter(CParameter::ChecksumFlag(true))?;
+    let mut frames: Vec<(u32, u32)> = vec![];
+    let mut data = Vec::with_capacity(frame_size);
+    loop {
+        data.clear();
+        (&mut input_reader).take(frame_size as u64).read_to_end(&mut data)?;
+        if data.is_empty() {
+            break;
+        }
+        let frame = compressor.compress(&data)?;
+        writer.write_all(&frame)?;
+        frames.push((frame.len() as u32, data.len() as u32));
+    }
+    writer.write_all(&SEEK_TABLE_MAGIC.to_le_bytes())?;
+    writer.write_all(&((frames.len() as u64 * 8 + SEEK_TABLE_FOOTER_SIZE) as u32).to_le_bytes())?;
+    for (compressed_size, decompressed_size) in frames.iter() {
+        writer.write_all(&compressed_size.to_le_bytes())?;
+        writer.write_all(&decompressed_size.to_le_bytes())?;
+    }
+    writer.write_all(&(frames.len() as u32).to_le_bytes())?;
+    writer.write_all(&[0])?;
+    writer.write_all(&SEEKABLE_MAGIC.to_le_bytes())?;
+    writer.flush()?;
+    drop(writer);
+    std::fs::rename(&temp_path, output).map_err(|e| with_filename(e, "replace", output))?;
+    return Ok(());
+}
+
+//Reader of a zstd seekable file, decompressing only the frame holding the position sought.
+//Offsets are positions in the decompressed data.
+pub struct ZstdSeekableReader{
+    file: File,
+    //Compressed and decompressed offset of the start of each frame, and of the end of the last one
+    frames: Vec<(u64, u64)>,
+    //Data of the frame loaded, its decompressed offset and the position of the next byte to read in it
+    data: Vec<u8>,
+    data_offset: u64,
+    position: usize,
+    //Frame read once data is, the one after the frame loaded
+    next_frame: usize,
+}
+
+impl ZstdSeekableReader{
+    pub fn new(filename: &str) -> Result<ZstdSeekableReader>{
+        let mut file = File::open(filename).map_err(|e| with_filename(e, "open", filename))?;
+        if !has_seek_table(&mut file)? {
+            return Err(FastSeekError::InvalidArgument(format!(
+                "{} is zstd-compressed but not in the seekable format, so records can't be read from random positions of it: \
+                convert it with `fastseek zstd-seekable {}`", filename, filename
+            )));
+        }
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("invalid zstd seek table in {}", filename));
+        let size = file.metadata()?.len();
+        let mut footer = [0; SEEK_TABLE_FOOTER_SIZE as usize];
+        file.seek(SeekFrom::Start(size - SEEK_TABLE_FOOTER_SIZE))?;
+        file.read_exact(&mut footer)?;
+        let count = u32::from_le_bytes(footer[0..4].try_into().unwrap()) as u64;
+        let descriptor = footer[4];
+        //Bits 2 to 6 are reserved, and must be 0
+        if descriptor & 0x7c != 0 {
+            return Err(invalid().into());
+        }
+        let entry_size = match descriptor & 0x80 != 0 {
+            true => 12,
+            false => 8,
+        };
+        let table_size = count * entry_size + SEEK_TABLE_FOOTER_SIZE;
+        if SKIPPABLE_HEADER_SIZE + table_size > size {
+            return Err(invalid().into());
+        }
+        let table_start = size - table_size - SKIPPABLE_HEADER_SIZE;
+        let mut table = vec![0; (SKIPPABLE_HEADER_SIZE + table_size - SEEK_TABLE_FOOTER_SIZE) as usize];
+        file.seek(SeekFrom::Start(table_start))?;
+        file.read_exact(&mut table)?;
+        let read_u32 = |start: usize| u32::from_le_bytes(table[start..start + 4].try_into().unwrap()) as u64;
+        if read_u32(0) != SEEK_TABLE_MAGIC as u64 || read_u32(4) != table_size {
+            return Err(invalid().into());
+        }
+        let mut frames = vec![(0, 0)];
+        for i in 0..count as usize {
+            let (compressed_offset, decompressed_offset) = frames[i];
+            let entry_start = SKIPPABLE_HEADER_SIZE as usize + i * entry_size as usize;
+            frames.push((compressed_offset + read_u32(entry_start), decompressed_offset + read_u32(entry_start + 4)));
+        }
+        if frames[count as usize].0 > table_start {
+            return Err(invalid().into());
+        }
+        return Ok(ZstdSeekableReader{file, frames, data: vec![], data_offset: 0, position: 0, next_frame: 0});
+    }
+
+    fn frame_count(&self) -> usize{
+        return self.frames.len() - 1;
+    }
+
+    fn load_frame(&mut self, frame: usize) -> io::Result<()>{
+        let (compressed_offset, decompressed_offset) = self.frames[frame];
+        let (next_compressed_offset, next_decompressed_offset) = self.frames[frame + 1];
+        let mut compressed = vec![0; (next_compressed_offset - compressed_offset) as usize];
+        self.file.seek(SeekFrom::Start(compressed_offset))?;
+        self.file.read_exact(&mut compressed)?;
+        let size = (next_decompressed_offset - decompressed_offset) as usize;
+        self.data = zstd::bulk::decompress(&compressed, size)?;
+        if self.data.len() != size {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("zstd frame at {} has the wrong size", compressed_offset)));
+        }
+        self.data_offset = decompressed_offset;
+        self.position = 0;
+        self.next_frame = frame + 1;
+        return Ok(());
+    }
+}
+
+impl BufRead for ZstdSeekableReader{
+    fn fill_buf(&mut self) -> io::Result<&[u8]>{
+        //Frames may be empty
+        while self.position >= self.data.len() && self.next_frame < self.frame_count() {
+            self.load_frame(self.next_frame)?;
+        }
+        return Ok(&self.data[self.position..]);
+    }
+
+    fn consume(&mut self, amount: usize){
+        self.position = (self.position + amount).min(self.data.len());
+    }
+}
+
+impl Read for ZstdSeekableReader{
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize>{
+        let data = self.fill_buf()?;
+        let n = data.len().min(buffer.len());
+        buffer[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        return Ok(n);
+    }
+}
+
+impl FileReader for ZstdSeekableReader{
+    fn seek(&mut self, pos: u64) -> Result<()> {
+        if pos >= self.data_offset && pos < self.data_offset + self.data.len() as u64 {
+            self.position = (pos - self.data_offset) as usize;
+            return Ok(());
+        }
+        let end = self.frames[self.frame_count()].1;
+        if pos >= end {
+            self.data.clear();
+            self.data_offset = end;
+            self.position = 0;
+            self.next_frame = self.frame_count();
+            return Ok(());
+        }
+        //Last frame starting at or before pos
+        let frame = self.frames.partition_point(|&(_, offset)| offset <= pos) - 1;
+        self.load_frame(frame)?;
+        self.position = (pos - self.data_offset) as usize;
+        return Ok(());
+    }
+    fn position(&mut self) -> Result<u64> {
+        return Ok(self.data_offset + self.position as u64);
+    }
+    fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
+        Read::read_exact(self, buffer)?;
+        return Ok(());
+    }
+    fn read_line(&mut self, buffer: &mut String) -> Result<usize> {
+        return Ok(BufRead::read_line(self, buffer)?);
+    }
+    fn num_lines(&mut self) -> Result<u64> {
+        let mut number_lines = 0;
+        let mut buffer = [0; 8192];
+        loop {
+            let n = self.read(&mut buffer)?;
+            if n == 0 { break; }
+            number_lines += buffer[..n].iter()
+                .filter(|&&byte| byte == b'\n')
+                .count();
+        }
+        FileReader::seek(self, 0)?;
+        return Ok(number_lines as u64);
+    }
+}